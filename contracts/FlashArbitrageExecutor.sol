@@ -0,0 +1,182 @@
+// SPDX-License-Identifier: MIT
+pragma solidity ^0.8.19;
+
+interface IUniswapV3Pool {
+    function token0() external view returns (address);
+
+    function token1() external view returns (address);
+
+    function flash(address recipient, uint256 amount0, uint256 amount1, bytes calldata data) external;
+
+    function swap(
+        address recipient,
+        bool zeroForOne,
+        int256 amountSpecified,
+        uint160 sqrtPriceLimitX96,
+        bytes calldata data
+    ) external returns (int256 amount0, int256 amount1);
+}
+
+interface IUniswapV2Pair {
+    function token0() external view returns (address);
+
+    function token1() external view returns (address);
+
+    function getReserves() external view returns (uint112 reserve0, uint112 reserve1, uint32 blockTimestampLast);
+
+    function swap(uint256 amount0Out, uint256 amount1Out, address to, bytes calldata data) external;
+}
+
+interface IERC20 {
+    function balanceOf(address account) external view returns (uint256);
+
+    function transfer(address to, uint256 amount) external returns (bool);
+}
+
+/// Borrows `amount` of `token` from a Uniswap V3 pool with `flash`, routes it
+/// through every leg in order and repays the loan in the same transaction.
+/// The whole call reverts unless the route returns at least the loan, the
+/// flash fee and `minProfit`, so the owner never ends up holding a half
+/// finished route.
+///
+/// The flash pool is locked for the duration of the loan, so it must not be
+/// one of the pools in `legs`.
+///
+/// Legs may swap on Uniswap V3 pools or on Uniswap V2-style pairs. A V2 leg
+/// pays the pair up front and takes the output its reserves give at the
+/// leg's fee; it has no price limit, so only `minProfit` guards it.
+///
+/// Every leg is an exact input of everything the previous one returned. A V3
+/// leg that hits its price limit before taking all of it reverts the route
+/// rather than leave the rest stranded on the contract.
+contract FlashArbitrageExecutor {
+    uint8 private constant UNISWAP_V3 = 0;
+    uint8 private constant UNISWAP_V2 = 1;
+    uint256 private constant FEE_DENOMINATOR = 1e6;
+
+    struct Leg {
+        address pool;
+        uint8 protocol;
+        bool zeroForOne;
+        uint160 sqrtPriceLimitX96;
+        // V2 swap fee in hundredths of a basis point.
+        uint24 fee;
+    }
+
+    address public immutable owner;
+
+    address private activeFlashPool;
+    address private activeSwapPool;
+
+    constructor() {
+        owner = msg.sender;
+    }
+
+    modifier onlyOwner() {
+        require(msg.sender == owner, "not owner");
+        _;
+    }
+
+    function execute(
+        address flashPool,
+        address token,
+        uint256 amount,
+        Leg[] calldata legs,
+        uint256 minProfit
+    ) external onlyOwner {
+        require(legs.length > 0, "no legs");
+
+        bool tokenIsToken0 = IUniswapV3Pool(flashPool).token0() == token;
+        require(tokenIsToken0 || IUniswapV3Pool(flashPool).token1() == token, "token not in flash pool");
+
+        activeFlashPool = flashPool;
+        IUniswapV3Pool(flashPool).flash(
+            address(this),
+            tokenIsToken0 ? amount : 0,
+            tokenIsToken0 ? 0 : amount,
+            abi.encode(token, amount, legs, minProfit)
+        );
+        activeFlashPool = address(0);
+    }
+
+    function uniswapV3FlashCallback(uint256 fee0, uint256 fee1, bytes calldata data) external {
+        require(msg.sender == activeFlashPool, "unexpected flash callback");
+
+        (address token, uint256 amount, Leg[] memory legs, uint256 minProfit) =
+            abi.decode(data, (address, uint256, Leg[], uint256));
+
+        uint256 amountOut = amount;
+        for (uint256 i = 0; i < legs.length; i++) {
+            amountOut = _swap(legs[i], amountOut);
+        }
+
+        // Only the borrowed side carries a fee, the other is zero.
+        uint256 owed = amount + fee0 + fee1;
+        require(amountOut >= owed + minProfit, "profit below minimum");
+
+        _safeTransfer(token, msg.sender, owed);
+        _safeTransfer(token, owner, amountOut - owed);
+    }
+
+    function uniswapV3SwapCallback(int256 amount0Delta, int256 amount1Delta, bytes calldata) external {
+        require(msg.sender == activeSwapPool, "unexpected swap callback");
+
+        if (amount0Delta > 0) {
+            _safeTransfer(IUniswapV3Pool(msg.sender).token0(), msg.sender, uint256(amount0Delta));
+        }
+        if (amount1Delta > 0) {
+            _safeTransfer(IUniswapV3Pool(msg.sender).token1(), msg.sender, uint256(amount1Delta));
+        }
+    }
+
+    /// Sends any token left on the contract to the owner.
+    function withdraw(address token) external onlyOwner {
+        _safeTransfer(token, owner, IERC20(token).balanceOf(address(this)));
+    }
+
+    function _swap(Leg memory leg, uint256 amountIn) private returns (uint256 amountOut) {
+        if (leg.protocol == UNISWAP_V2) {
+            return _swapV2(leg, amountIn);
+        }
+        require(leg.protocol == UNISWAP_V3, "unknown protocol");
+
+        // A positive amountSpecified is an exact input, one past int256 would
+        // wrap into an exact output.
+        require(amountIn > 0 && amountIn <= uint256(type(int256).max), "amount out of range");
+        activeSwapPool = leg.pool;
+        (int256 amount0, int256 amount1) = IUniswapV3Pool(leg.pool).swap(
+            address(this),
+            leg.zeroForOne,
+            int256(amountIn),
+            leg.sqrtPriceLimitX96,
+            ""
+        );
+        activeSwapPool = address(0);
+
+        require(uint256(leg.zeroForOne ? amount0 : amount1) == amountIn, "partial fill");
+        amountOut = uint256(-(leg.zeroForOne ? amount1 : amount0));
+    }
+
+    /// `UniswapV2Library.getAmountOut` with the leg's fee, then the pair's
+    /// low-level swap.
+    function _swapV2(Leg memory leg, uint256 amountIn) private returns (uint256 amountOut) {
+        require(amountIn > 0, "amount out of range");
+        require(leg.fee < FEE_DENOMINATOR, "fee out of range");
+        IUniswapV2Pair pair = IUniswapV2Pair(leg.pool);
+        (uint256 reserve0, uint256 reserve1,) = pair.getReserves();
+        (uint256 reserveIn, uint256 reserveOut) = leg.zeroForOne ? (reserve0, reserve1) : (reserve1, reserve0);
+
+        uint256 amountInWithFee = amountIn * (FEE_DENOMINATOR - leg.fee);
+        amountOut = (amountInWithFee * reserveOut) / (reserveIn * FEE_DENOMINATOR + amountInWithFee);
+        require(amountOut > 0, "insufficient output");
+
+        _safeTransfer(leg.zeroForOne ? pair.token0() : pair.token1(), leg.pool, amountIn);
+        pair.swap(leg.zeroForOne ? 0 : amountOut, leg.zeroForOne ? amountOut : 0, address(this), "");
+    }
+
+    function _safeTransfer(address token, address to, uint256 amount) private {
+        (bool success, bytes memory result) =
+            token.call(abi.encodeWithSelector(IERC20.transfer.selector, to, amount));
+        require(success && (result.length == 0 || abi.decode(result, (bool))), "transfer failed");
+    }
+}
@@ -0,0 +1,114 @@
+use crate::{discovery::load_abi, error, gas::FeeQuote, nonce::NonceManager, rpc::FailoverHttp};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use web3::{
+    contract::{Contract, Options},
+    ethabi::Token,
+    signing::SecretKey,
+    types::{Address, Bytes, TransactionParameters, H256, U256},
+    Web3,
+};
+
+/// Gas an `approve` is sent with, well above what ERC-20s use.
+const APPROVE_GAS: u64 = 100_000;
+
+/// How much a spender is allowed when the allowance falls short.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ApprovalPolicy {
+    /// Exactly the amount needed, so a compromised spender can take no more.
+    #[default]
+    Exact,
+    /// The maximum, so the token never has to be approved again.
+    Infinite,
+}
+
+impl fmt::Display for ApprovalPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ApprovalPolicy::Exact => write!(f, "Exact"),
+            ApprovalPolicy::Infinite => write!(f, "Infinite"),
+        }
+    }
+}
+
+/// `token.allowance(owner, spender)`.
+pub async fn allowance(
+    web3: &Web3<FailoverHttp>,
+    token: Address,
+    owner: Address,
+    spender: Address,
+) -> error::Result<U256> {
+    let contract = Contract::new(web3.eth(), token, load_abi("./erc20_abi.json")?);
+    Ok(contract
+        .query(
+            "allowance",
+            (owner, spender),
+            None,
+            Options::default(),
+            None,
+        )
+        .await?)
+}
+
+/// Sends approvals from the account of `nonces`.
+pub struct Approver<'a> {
+    pub web3: &'a Web3<FailoverHttp>,
+    pub nonces: &'a NonceManager,
+    pub key: &'a SecretKey,
+    pub fee: FeeQuote,
+}
+
+impl Approver<'_> {
+    /// Approves `spender` on `token` unless it is already allowed `amount`,
+    /// returning the hashes of the approvals sent. A nonzero allowance is
+    /// reset to zero first, as tokens like USDT refuse to change it
+    /// otherwise.
+    pub async fn ensure(
+        &self,
+        token: Address,
+        spender: Address,
+        amount: U256,
+        policy: ApprovalPolicy,
+    ) -> error::Result<Vec<H256>> {
+        let current = allowance(self.web3, token, self.nonces.address(), spender).await?;
+        if current >= amount {
+            return Ok(Vec::new());
+        }
+
+        let mut sent = Vec::new();
+        if !current.is_zero() {
+            sent.push(self.approve(token, spender, U256::zero()).await?);
+        }
+        let approved = match policy {
+            ApprovalPolicy::Exact => amount,
+            ApprovalPolicy::Infinite => U256::MAX,
+        };
+        sent.push(self.approve(token, spender, approved).await?);
+        Ok(sent)
+    }
+
+    /// Sets the allowance of `spender` on `token` back to zero, if it is
+    /// not already.
+    pub async fn revoke(&self, token: Address, spender: Address) -> error::Result<Option<H256>> {
+        if allowance(self.web3, token, self.nonces.address(), spender)
+            .await?
+            .is_zero()
+        {
+            return Ok(None);
+        }
+        Ok(Some(self.approve(token, spender, U256::zero()).await?))
+    }
+
+    async fn approve(&self, token: Address, spender: Address, amount: U256) -> error::Result<H256> {
+        let data = load_abi("./erc20_abi.json")?
+            .function("approve")?
+            .encode_input(&[Token::Address(spender), Token::Uint(amount)])?;
+        let tx = TransactionParameters {
+            to: Some(token),
+            gas: U256::from(APPROVE_GAS),
+            data: Bytes(data),
+            ..Default::default()
+        };
+        self.nonces.send(self.web3, tx, &self.fee, self.key).await
+    }
+}
@@ -0,0 +1,759 @@
+use crate::{
+    dex::{simulate_route, Pool, Protocol},
+    discovery::{load_abi, DiscoveredPool},
+    error::{self, Error},
+    ledger::{PaperLedger, PaperTrade},
+    profit::ProfitBreakdown,
+    routing::{PoolGraph, Route},
+    v3_pool::{PoolEvent, PoolState, SwapSimulation},
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt, fs};
+use web3::{
+    ethabi::{RawLog, Token},
+    types::{Address, Log, U256, U64},
+};
+
+const MISSED_OPPORTUNITIES_LISTED: usize = 5;
+
+/// Pool snapshots and the events that followed them, as written by
+/// `arbitrage-bot record`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BacktestData {
+    /// Block the snapshots were taken at.
+    pub block: u64,
+    pub master_decimals: u8,
+    /// Cost of one execution at the gas price seen while recording.
+    pub gas_cost_in_master: f64,
+    /// V3 pools only. `record` and `backtest` refuse configs with V2
+    /// factories rather than replay a different route set.
+    pub pools: Vec<PoolState>,
+    /// Events in decoded form.
+    #[serde(default)]
+    pub events: Vec<RecordedEvent>,
+    /// Raw `eth_getLogs` output for the pools.
+    #[serde(default)]
+    pub logs: Vec<Log>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RecordedEvent {
+    pub block: u64,
+    #[serde(default)]
+    pub log_index: u64,
+    pub pool: Address,
+    pub event: PoolEvent,
+}
+
+pub fn load_data(path: &str) -> error::Result<BacktestData> {
+    let data = fs::read_to_string(path)
+        .map_err(|e| Error::Decode(format!("Failed to read {}: {}", path, e)))?;
+    serde_json::from_str(&data)
+        .map_err(|e| Error::Decode(format!("Failed to parse {}: {}", path, e)))
+}
+
+/// Events from a CSV file with the header
+/// `block,log_index,pool,kind,sqrt_price_x96,liquidity,tick,tick_lower,tick_upper,amount`,
+/// or from a JSON array of decoded events or raw `eth_getLogs` logs.
+pub fn load_events(path: &str) -> error::Result<Vec<RecordedEvent>> {
+    let data = fs::read_to_string(path)
+        .map_err(|e| Error::Decode(format!("Failed to read {}: {}", path, e)))?;
+    if path.ends_with(".csv") {
+        return data
+            .lines()
+            .skip(1)
+            .filter(|line| !line.trim().is_empty())
+            .enumerate()
+            .map(|(i, line)| {
+                parse_csv_event(line)
+                    .map_err(|e| Error::Decode(format!("{} line {}: {}", path, i + 2, e)))
+            })
+            .collect();
+    }
+
+    if let Ok(events) = serde_json::from_str::<Vec<RecordedEvent>>(&data) {
+        return Ok(events);
+    }
+    let logs: Vec<Log> = serde_json::from_str(&data)
+        .map_err(|e| Error::Decode(format!("Failed to parse {}: {}", path, e)))?;
+    decode_logs(&logs)
+}
+
+fn parse_csv_event(line: &str) -> Result<RecordedEvent, &'static str> {
+    let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+    if fields.len() != 10 {
+        return Err("expected 10 columns");
+    }
+    let int = |field: &str| field.parse::<i32>().map_err(|_| "invalid tick");
+    let uint = |field: &str| field.parse::<u128>().map_err(|_| "invalid amount");
+
+    let event = match fields[3] {
+        "Swap" => PoolEvent::Swap {
+            sqrt_price_x96: parse_u256(fields[4]).ok_or("invalid sqrt_price_x96")?,
+            liquidity: uint(fields[5])?,
+            tick: int(fields[6])?,
+        },
+        "Mint" => PoolEvent::Mint {
+            tick_lower: int(fields[7])?,
+            tick_upper: int(fields[8])?,
+            amount: uint(fields[9])?,
+        },
+        "Burn" => PoolEvent::Burn {
+            tick_lower: int(fields[7])?,
+            tick_upper: int(fields[8])?,
+            amount: uint(fields[9])?,
+        },
+        _ => return Err("kind must be Swap, Mint or Burn"),
+    };
+
+    Ok(RecordedEvent {
+        block: fields[0].parse().map_err(|_| "invalid block")?,
+        log_index: fields[1].parse().map_err(|_| "invalid log index")?,
+        pool: fields[2].parse().map_err(|_| "invalid pool address")?,
+        event,
+    })
+}
+
+fn parse_u256(field: &str) -> Option<U256> {
+    match field.strip_prefix("0x") {
+        Some(hex) => U256::from_str_radix(hex, 16).ok(),
+        None => U256::from_dec_str(field).ok(),
+    }
+}
+
+/// Decodes the `Swap`, `Mint` and `Burn` logs among `logs`, skipping any
+/// other event and logs removed by a reorg.
+pub fn decode_logs(logs: &[Log]) -> error::Result<Vec<RecordedEvent>> {
+    let pool_abi = load_abi("./pool_abi.json")?;
+    let invalid = |e: String| Error::Decode(format!("Invalid pool log: {}", e));
+
+    let mut events = Vec::new();
+    for log in logs {
+        if log.removed == Some(true) {
+            continue;
+        }
+        let Some(abi_event) = pool_abi.events().find(|event| {
+            matches!(event.name.as_str(), "Swap" | "Mint" | "Burn")
+                && log.topics.first() == Some(&event.signature())
+        }) else {
+            continue;
+        };
+
+        let parsed = abi_event
+            .parse_log(RawLog {
+                topics: log.topics.clone(),
+                data: log.data.0.clone(),
+            })
+            .map_err(|e| invalid(e.to_string()))?;
+        let param = |name: &str| -> error::Result<U256> {
+            match parsed.params.iter().find(|param| param.name == name) {
+                Some(param) => match param.value {
+                    Token::Uint(value) | Token::Int(value) => Ok(value),
+                    _ => Err(invalid(format!("{} is not a number", name))),
+                },
+                None => Err(invalid(format!("missing {}", name))),
+            }
+        };
+        // int24 values arrive sign-extended to 256 bits.
+        let tick = |name: &str| param(name).map(|value| value.low_u32() as i32);
+
+        let event = match abi_event.name.as_str() {
+            "Swap" => PoolEvent::Swap {
+                sqrt_price_x96: param("sqrtPriceX96")?,
+                liquidity: param("liquidity")?.low_u128(),
+                tick: tick("tick")?,
+            },
+            "Mint" => PoolEvent::Mint {
+                tick_lower: tick("tickLower")?,
+                tick_upper: tick("tickUpper")?,
+                amount: param("amount")?.low_u128(),
+            },
+            _ => PoolEvent::Burn {
+                tick_lower: tick("tickLower")?,
+                tick_upper: tick("tickUpper")?,
+                amount: param("amount")?.low_u128(),
+            },
+        };
+
+        events.push(RecordedEvent {
+            block: log.block_number.unwrap_or_default().as_u64(),
+            log_index: log.log_index.unwrap_or_default().low_u64(),
+            pool: log.address,
+            event,
+        });
+    }
+    Ok(events)
+}
+
+/// Strategy settings a backtest runs with, taken from `Config`.
+pub struct BacktestParams {
+    pub master: Address,
+    pub amount_in: U256,
+    pub max_hops: usize,
+    pub minimum_profit: f64,
+    pub slippage_percent: f64,
+    pub flash_pool: Option<Address>,
+    pub gas_cost_in_master: f64,
+    pub labels: HashMap<Address, String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BacktestReport {
+    pub first_block: u64,
+    pub last_block: u64,
+    pub blocks: usize,
+    pub events: usize,
+    /// Trades that landed and cleared `minProfit`.
+    pub hits: usize,
+    pub ledger: PaperLedger,
+    /// Best routes that were profitable before the slippage buffer but
+    /// rejected by the thresholds.
+    pub missed: Vec<(U64, ProfitBreakdown)>,
+}
+
+impl BacktestReport {
+    pub fn hit_rate(&self) -> f64 {
+        if self.ledger.trades.is_empty() {
+            return 0.0;
+        }
+        self.hits as f64 / self.ledger.trades.len() as f64 * 100.0
+    }
+}
+
+impl fmt::Display for BacktestReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Backtest of blocks {} to {}: {} blocks with {} events",
+            self.first_block, self.last_block, self.blocks, self.events
+        )?;
+        writeln!(
+            f,
+            "{} trades, {} hits ({:.1}% hit rate)",
+            self.ledger.trades.len(),
+            self.hits,
+            self.hit_rate()
+        )?;
+        writeln!(f, "{}", self.ledger)?;
+        write!(f, "{} missed opportunities", self.missed.len())?;
+
+        let mut missed: Vec<&(U64, ProfitBreakdown)> = self.missed.iter().collect();
+        missed.sort_by(|a, b| {
+            (b.1.net_profit + b.1.slippage_buffer)
+                .total_cmp(&(a.1.net_profit + a.1.slippage_buffer))
+        });
+        for (block, profit) in missed.into_iter().take(MISSED_OPPORTUNITIES_LISTED) {
+            write!(f, "\n  block {}: {}", block, profit)?;
+        }
+        Ok(())
+    }
+}
+
+/// Replays the events block by block over the snapshots, running the same
+/// route search and profit model as live trading after every block. A trade
+/// found in one block lands in the next one after that block's own events,
+/// reverting like the executor would if it no longer clears `minProfit`.
+pub fn run_backtest(
+    data: BacktestData,
+    mut events: Vec<RecordedEvent>,
+    params: &BacktestParams,
+) -> Result<BacktestReport, &'static str> {
+    let discovered: Vec<DiscoveredPool> = data
+        .pools
+        .iter()
+        .map(|pool| DiscoveredPool {
+            protocol: Protocol::UniswapV3,
+            address: pool.address,
+            token0: pool.token0,
+            token1: pool.token1,
+            fee: pool.fee,
+            liquidity: pool.liquidity,
+            sqrt_price_x96: pool.sqrt_price_x96,
+        })
+        .collect();
+    let mut pools: HashMap<Address, Pool> = data
+        .pools
+        .into_iter()
+        .map(|pool| (pool.address, Pool::V3(pool)))
+        .collect();
+    let flash_fee_pips = params
+        .flash_pool
+        .and_then(|address| pools.get(&address))
+        .map_or(0, Pool::fee);
+
+    let routes: Vec<Route> = PoolGraph::new(&discovered)
+        .cycles_from(params.master, params.max_hops)
+        .into_iter()
+        .filter(|route| {
+            params
+                .flash_pool
+                .is_none_or(|flash| route.pools.iter().all(|pool| pool.address != flash))
+        })
+        .collect();
+    if routes.is_empty() {
+        return Err("no round trip can be built from the recorded pools");
+    }
+
+    events.retain(|event| event.block > data.block && pools.contains_key(&event.pool));
+    events.sort_by_key(|event| (event.block, event.log_index));
+
+    let backtest = Backtest {
+        routes,
+        params,
+        master_decimals: data.master_decimals,
+        flash_fee_pips,
+    };
+    let mut report = BacktestReport {
+        first_block: data.block,
+        last_block: data.block,
+        blocks: 1,
+        events: events.len(),
+        ..Default::default()
+    };
+
+    let mut pending = backtest.detect(&pools, data.block, &mut report);
+    for block_events in events.chunk_by(|a, b| a.block == b.block) {
+        let block = block_events[0].block;
+        for event in block_events {
+            if let Some(Pool::V3(pool)) = pools.get_mut(&event.pool) {
+                pool.apply(&event.event)?;
+            }
+        }
+        report.blocks += 1;
+        report.last_block = block;
+
+        if let Some(route) = pending.take() {
+            backtest.execute(&mut pools, route, block, &mut report);
+        }
+        pending = backtest.detect(&pools, block, &mut report);
+    }
+    if let Some(route) = pending {
+        backtest.execute(&mut pools, route, report.last_block, &mut report);
+    }
+
+    Ok(report)
+}
+
+struct Backtest<'a> {
+    routes: Vec<Route>,
+    params: &'a BacktestParams,
+    master_decimals: u8,
+    flash_fee_pips: u32,
+}
+
+impl Backtest<'_> {
+    fn simulate(
+        &self,
+        pools: &HashMap<Address, Pool>,
+        route: &Route,
+    ) -> Result<(ProfitBreakdown, Vec<SwapSimulation>), &'static str> {
+        let route_pools: Vec<&Pool> = route
+            .pools
+            .iter()
+            .map(|pool| &pools[&pool.address])
+            .collect();
+        let legs = simulate_route(&route_pools, self.params.master, self.params.amount_in)?;
+        let profit = ProfitBreakdown::new(
+            route.describe(&self.params.labels),
+            &legs,
+            self.master_decimals,
+            self.flash_fee_pips,
+            self.params.gas_cost_in_master,
+            self.params.slippage_percent,
+        );
+        Ok((profit, legs))
+    }
+
+    /// Index of the best route if it clears the thresholds. Routes that
+    /// would only have paid without the slippage buffer are counted as
+    /// missed.
+    fn detect(
+        &self,
+        pools: &HashMap<Address, Pool>,
+        block: u64,
+        report: &mut BacktestReport,
+    ) -> Option<usize> {
+        let (index, profit) = self
+            .routes
+            .iter()
+            .enumerate()
+            .filter_map(|(index, route)| {
+                self.simulate(pools, route)
+                    .ok()
+                    .map(|(profit, _)| (index, profit))
+            })
+            .max_by(|a, b| a.1.net_profit.total_cmp(&b.1.net_profit))?;
+
+        if profit.is_profitable(self.params.minimum_profit) {
+            Some(index)
+        } else {
+            if profit.net_profit + profit.slippage_buffer > 0.0 {
+                report.missed.push((U64::from(block), profit));
+            }
+            None
+        }
+    }
+
+    /// Sends the route against the current state. The executor reverts
+    /// unless the surplus over the loan covers `minimum_profit` plus gas,
+    /// and a reverted trade still pays for its gas.
+    fn execute(
+        &self,
+        pools: &mut HashMap<Address, Pool>,
+        route_index: usize,
+        block: u64,
+        report: &mut BacktestReport,
+    ) {
+        let route = &self.routes[route_index];
+        let gas_cost = self.params.gas_cost_in_master;
+        let mut trade = PaperTrade {
+            route: route.describe(&self.params.labels),
+            amount_in: 0.0,
+            amount_out: 0.0,
+            gas_used: None,
+            gas_cost,
+            realized_profit: -gas_cost,
+            reverted: None,
+        };
+
+        match self.simulate(pools, route) {
+            Ok((profit, legs)) => {
+                trade.amount_in = profit.amount_in;
+                trade.amount_out = profit.amount_out;
+                let surplus = profit.net_profit + profit.gas_cost + profit.slippage_buffer;
+                if surplus >= self.params.minimum_profit.max(0.0) + gas_cost {
+                    trade.realized_profit = surplus - gas_cost;
+                    report.hits += 1;
+                    for (pool, leg) in route.pools.iter().zip(&legs) {
+                        if let Some(pool) = pools.get_mut(&pool.address) {
+                            pool.apply_swap(leg);
+                        }
+                    }
+                } else {
+                    trade.reverted = Some("profit below minimum".into());
+                }
+            }
+            Err(e) => trade.reverted = Some(e.to_string()),
+        }
+
+        report.ledger.record(U64::from(block), trade);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v3_math::get_sqrt_ratio_at_tick;
+    use web3::{
+        ethabi::{encode, Event},
+        types::{Bytes, H256},
+    };
+
+    const LIQUIDITY: u128 = 1_000_000_000_000_000_000_000;
+
+    fn master() -> Address {
+        Address::from_low_u64_be(1)
+    }
+
+    fn token() -> Address {
+        Address::from_low_u64_be(2)
+    }
+
+    /// A 0.05% master / token pool at `tick` with one position over
+    /// [-1000, 1000].
+    fn pool(address: u64, tick: i32) -> PoolState {
+        let mut pool = PoolState {
+            address: Address::from_low_u64_be(address),
+            token0: master(),
+            token1: token(),
+            fee: 500,
+            tick_spacing: 10,
+            sqrt_price_x96: get_sqrt_ratio_at_tick(tick).unwrap(),
+            tick,
+            liquidity: 0,
+            tick_bitmap: [(-1, U256::zero()), (0, U256::zero())].into(),
+            liquidity_net: HashMap::new(),
+        };
+        pool.apply(&PoolEvent::Mint {
+            tick_lower: -1000,
+            tick_upper: 1000,
+            amount: LIQUIDITY,
+        })
+        .unwrap();
+        pool
+    }
+
+    /// Pool 0xa at tick 0 and pool 0xb at tick 200, where the master token
+    /// buys 2% more of the token.
+    fn data() -> BacktestData {
+        BacktestData {
+            block: 100,
+            master_decimals: 18,
+            gas_cost_in_master: 0.000001,
+            pools: vec![pool(0xa, 0), pool(0xb, 200)],
+            events: Vec::new(),
+            logs: Vec::new(),
+        }
+    }
+
+    fn params(minimum_profit: f64) -> BacktestParams {
+        BacktestParams {
+            master: master(),
+            amount_in: U256::exp10(15),
+            max_hops: 2,
+            minimum_profit,
+            slippage_percent: 0.0,
+            flash_pool: None,
+            gas_cost_in_master: 0.000001,
+            labels: [(master(), "master".into()), (token(), "token".into())].into(),
+        }
+    }
+
+    /// Pool 0xb is arbitraged back to tick 0 by someone else in block 101,
+    /// moves to tick 200 again in 102 and gains a position in 103.
+    const EVENTS_CSV: &str = "\
+block,log_index,pool,kind,sqrt_price_x96,liquidity,tick,tick_lower,tick_upper,amount
+101,3,0x000000000000000000000000000000000000000b,Swap,79228162514264337593543950336,1000000000000000000000,0,,,
+102,0,0x000000000000000000000000000000000000000b,Swap,0x102929d5a0656650806703cf3,1000000000000000000000,200,,,
+103,1,0x000000000000000000000000000000000000000b,Mint,,,,-100,100,5000
+99,0,0x000000000000000000000000000000000000000b,Burn,,,,-1000,1000,1
+103,0,0x000000000000000000000000000000000000000c,Burn,,,,-1000,1000,1
+";
+
+    fn write_temp(name: &str, data: &str) -> String {
+        let path = std::env::temp_dir().join(format!("backtest-{}-{}", std::process::id(), name));
+        fs::write(&path, data).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    fn csv_events() -> Vec<RecordedEvent> {
+        let path = write_temp("events.csv", EVENTS_CSV);
+        let events = load_events(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        events
+    }
+
+    #[test]
+    fn loaders() {
+        let events = csv_events();
+        assert_eq!(events.len(), 5);
+        assert_eq!(
+            events[1],
+            RecordedEvent {
+                block: 102,
+                log_index: 0,
+                pool: Address::from_low_u64_be(0xb),
+                event: PoolEvent::Swap {
+                    sqrt_price_x96: get_sqrt_ratio_at_tick(200).unwrap(),
+                    liquidity: LIQUIDITY,
+                    tick: 200,
+                },
+            }
+        );
+        assert_eq!(
+            events[2].event,
+            PoolEvent::Mint {
+                tick_lower: -100,
+                tick_upper: 100,
+                amount: 5000,
+            }
+        );
+
+        let bad = write_temp(
+            "bad.csv",
+            "header\n101,0,0xb,Swap,1,2,3,,,\n101,0,0xb,Sync,,,,,,\n",
+        );
+        match load_events(&bad) {
+            Err(Error::Decode(message)) => {
+                assert!(message.ends_with("line 2: invalid pool address"))
+            }
+            other => panic!("expected a decode error, got {:?}", other),
+        }
+        fs::remove_file(&bad).unwrap();
+
+        let json = write_temp("events.json", &serde_json::to_string(&events).unwrap());
+        assert_eq!(load_events(&json).unwrap(), events);
+        fs::remove_file(&json).unwrap();
+    }
+
+    fn word(value: U256) -> H256 {
+        let mut bytes = [0u8; 32];
+        value.to_big_endian(&mut bytes);
+        H256(bytes)
+    }
+
+    /// Two's complement of a negative int256, as the pool emits it.
+    fn int(value: i128) -> U256 {
+        if value < 0 {
+            !U256::from(value.unsigned_abs()) + 1
+        } else {
+            U256::from(value)
+        }
+    }
+
+    fn log(event: &Event, mut topics: Vec<H256>, data: &[Token], log_index: u64) -> Log {
+        topics.insert(0, event.signature());
+        Log {
+            address: Address::from_low_u64_be(0xa),
+            topics,
+            data: Bytes(encode(data)),
+            block_hash: None,
+            block_number: Some(101.into()),
+            transaction_hash: None,
+            transaction_index: None,
+            log_index: Some(log_index.into()),
+            transaction_log_index: None,
+            log_type: None,
+            removed: None,
+        }
+    }
+
+    #[test]
+    fn raw_logs() {
+        let abi = load_abi("./pool_abi.json").unwrap();
+        let owner = word(U256::from(0x0e));
+        let swap = log(
+            abi.event("Swap").unwrap(),
+            vec![owner, owner],
+            &[
+                Token::Int(int(-500_000_000_000_000_000)),
+                Token::Int(int(1_000_000_000_000_000_000)),
+                Token::Uint(get_sqrt_ratio_at_tick(-887).unwrap()),
+                Token::Uint(LIQUIDITY.into()),
+                Token::Int(int(-887)),
+            ],
+            0,
+        );
+        let position = [
+            Token::Uint(7.into()),
+            Token::Uint(U256::exp10(15)),
+            Token::Uint(U256::exp10(15)),
+        ];
+        let mint = log(
+            abi.event("Mint").unwrap(),
+            vec![owner, word(int(-120)), word(int(60))],
+            &[
+                &[Token::Address(Address::from_low_u64_be(0x0e))],
+                &position[..],
+            ]
+            .concat(),
+            1,
+        );
+        let burn = log(
+            abi.event("Burn").unwrap(),
+            vec![owner, word(int(-120)), word(int(60))],
+            &position,
+            2,
+        );
+        let removed = Log {
+            removed: Some(true),
+            ..mint.clone()
+        };
+        let other = log(
+            abi.event("Collect").unwrap(),
+            vec![owner, word(int(-120)), word(int(60))],
+            &[
+                Token::Address(Address::zero()),
+                Token::Uint(1.into()),
+                Token::Uint(1.into()),
+            ],
+            3,
+        );
+
+        let events = decode_logs(&[swap, removed, mint, other, burn]).unwrap();
+        let kinds: Vec<PoolEvent> = events.iter().map(|event| event.event).collect();
+        assert_eq!(
+            kinds,
+            [
+                PoolEvent::Swap {
+                    sqrt_price_x96: get_sqrt_ratio_at_tick(-887).unwrap(),
+                    liquidity: LIQUIDITY,
+                    tick: -887,
+                },
+                PoolEvent::Mint {
+                    tick_lower: -120,
+                    tick_upper: 60,
+                    amount: 7,
+                },
+                PoolEvent::Burn {
+                    tick_lower: -120,
+                    tick_upper: 60,
+                    amount: 7,
+                },
+            ]
+        );
+        assert_eq!(events[2].block, 101);
+        assert_eq!(events[2].log_index, 2);
+
+        // Positions move liquidity_net at both ends, and are in range.
+        let mut state = pool(0xa, 0);
+        state.apply(&events[1].event).unwrap();
+        assert_eq!(state.liquidity_net[&-120], 7);
+        assert_eq!(state.liquidity_net[&60], -7);
+        assert_eq!(state.liquidity, LIQUIDITY + 7);
+        state.apply(&events[2].event).unwrap();
+        assert!(!state.liquidity_net.contains_key(&-120));
+        assert_eq!(state.liquidity, LIQUIDITY);
+        state.apply(&events[0].event).unwrap();
+        assert_eq!(state.tick, -887);
+    }
+
+    /// The opportunity seen at the snapshot is gone by the time it lands in
+    /// block 101 and reverts; the one reopened in 102 lands in 103, and is
+    /// found and taken again after it.
+    #[test]
+    fn report() {
+        let report = run_backtest(data(), csv_events(), &params(0.0)).unwrap();
+        let blocks: Vec<u64> = report
+            .ledger
+            .trades
+            .iter()
+            .map(|(block, _)| block.as_u64())
+            .collect();
+        assert_eq!(blocks, [101, 103, 103]);
+        let (_, reverted) = &report.ledger.trades[0];
+        assert_eq!(reverted.reverted.as_deref(), Some("profit below minimum"));
+        assert_eq!(reverted.realized_profit, -0.000001);
+        for (_, trade) in &report.ledger.trades[1..] {
+            assert_eq!(trade.reverted, None);
+            // The surplus over the loan, less gas.
+            let surplus = trade.amount_out - trade.amount_in;
+            assert!((trade.realized_profit - (surplus - 0.000001)).abs() < 1e-12);
+            assert!((trade.realized_profit - 0.0000182).abs() < 1e-7);
+        }
+        assert_eq!(report.hits, 2);
+        assert!(report.missed.is_empty());
+        assert_eq!(
+            report.to_string(),
+            "Backtest of blocks 100 to 103: 4 blocks with 3 events\n\
+             3 trades, 2 hits (66.7% hit rate)\n\
+             Paper balance 0.000035 (+0.000035) after 3 trades, 1 reverted, 0.000003 spent on gas\n\
+             0 missed opportunities"
+        );
+    }
+
+    /// Above the profit the route makes, every block it is open in is a
+    /// missed opportunity and nothing is traded.
+    #[test]
+    fn missed_opportunities() {
+        let report = run_backtest(data(), csv_events(), &params(0.001)).unwrap();
+        assert!(report.ledger.trades.is_empty());
+        assert_eq!(report.hit_rate(), 0.0);
+        let blocks: Vec<U64> = report.missed.iter().map(|(block, _)| *block).collect();
+        assert_eq!(blocks, [100.into(), 102.into(), 103.into()]);
+        let missed = "master -0.05%-> token -0.05%-> master: in 0.001000 out 0.001019 | \
+             gross 0.000020 fees 0.000001 gas 0.000001 slippage 0.000000 | net 0.000018";
+        assert_eq!(
+            report.to_string(),
+            format!(
+                "Backtest of blocks 100 to 103: 4 blocks with 3 events\n\
+                 0 trades, 0 hits (0.0% hit rate)\n\
+                 Paper balance 0.000000 (+0.000000) after 0 trades, 0 reverted, 0.000000 spent on gas\n\
+                 3 missed opportunities\n  \
+                 block 100: {missed}\n  \
+                 block 102: {missed}\n  \
+                 block 103: {missed}"
+            )
+        );
+    }
+}
@@ -0,0 +1,189 @@
+use crate::{dex::Protocol, discovery::pool_label, ledger::PaperTrade, profit::ProfitBreakdown};
+use eframe::egui::{
+    self,
+    plot::{Legend, Line, Plot, PlotPoints},
+};
+use std::collections::{BTreeMap, VecDeque};
+use web3::types::{Address, H256, U64};
+
+/// Price points kept per pool for the chart.
+const PRICE_HISTORY_LEN: usize = 500;
+/// Rows kept in the opportunity table.
+const OPPORTUNITY_HISTORY_LEN: usize = 200;
+
+/// Price of one pool as read by an evaluation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PoolPrice {
+    pub pool: Address,
+    /// "token0 / token1", the pools of one pair share it.
+    pub pair: String,
+    pub protocol: Protocol,
+    pub fee: u32,
+    pub price: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// Profitable, but there was no executor or wallet to send it with.
+    Detected,
+    Paper(PaperTrade),
+    Sent(H256),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpportunityRecord {
+    pub block: U64,
+    pub profit: ProfitBreakdown,
+    pub outcome: Outcome,
+}
+
+/// What the live panel shows, filled in after every evaluation.
+#[derive(Debug, Clone, Default)]
+pub struct Dashboard {
+    pub prices: Vec<PoolPrice>,
+    /// (block, price) points of every pool seen.
+    pub price_history: BTreeMap<Address, VecDeque<[f64; 2]>>,
+    pub opportunities: VecDeque<OpportunityRecord>,
+}
+
+impl Dashboard {
+    pub fn record_prices(&mut self, block: U64, prices: Vec<PoolPrice>) {
+        for price in &prices {
+            let history = self.price_history.entry(price.pool).or_default();
+            if history.len() == PRICE_HISTORY_LEN {
+                history.pop_front();
+            }
+            history.push_back([block.as_u64() as f64, price.price]);
+        }
+        self.prices = prices;
+    }
+
+    pub fn record_opportunity(&mut self, opportunity: OpportunityRecord) {
+        if self.opportunities.len() == OPPORTUNITY_HISTORY_LEN {
+            self.opportunities.pop_front();
+        }
+        self.opportunities.push_back(opportunity);
+    }
+
+    /// Difference between the highest and lowest price of each pair in
+    /// percent of the lowest, for pairs with more than one pool.
+    pub fn spreads(&self) -> BTreeMap<&str, f64> {
+        let mut ranges: BTreeMap<&str, (f64, f64, usize)> = BTreeMap::new();
+        for price in &self.prices {
+            let (low, high, pools) =
+                ranges
+                    .entry(&price.pair)
+                    .or_insert((f64::INFINITY, f64::NEG_INFINITY, 0));
+            *low = low.min(price.price);
+            *high = high.max(price.price);
+            *pools += 1;
+        }
+
+        ranges
+            .into_iter()
+            .filter(|(_, (low, _, pools))| *pools > 1 && *low > 0.0)
+            .map(|(pair, (low, high, _))| (pair, (high - low) / low * 100.0))
+            .collect()
+    }
+
+    pub fn show(&self, ui: &mut egui::Ui, last_profit: Option<&ProfitBreakdown>) {
+        let spreads = self.spreads();
+
+        ui.heading("Pools");
+        egui::Grid::new("pool_prices")
+            .num_columns(4)
+            .striped(true)
+            .show(ui, |ui| {
+                ui.strong("Pair");
+                ui.strong("Fee");
+                ui.strong("Pool");
+                ui.strong("Price");
+                ui.end_row();
+                for price in &self.prices {
+                    ui.label(&price.pair);
+                    ui.label(pool_label(price.protocol, price.fee));
+                    ui.label(format!("{:?}", price.pool));
+                    ui.label(format!("{:.8}", price.price));
+                    ui.end_row();
+                }
+            });
+        for (pair, spread) in &spreads {
+            ui.label(format!("{} spread: {:.4}%", pair, spread));
+        }
+
+        if let Some(profit) = last_profit {
+            ui.heading("Last evaluation");
+            egui::Grid::new("last_profit")
+                .num_columns(2)
+                .show(ui, |ui| {
+                    for (label, value) in [
+                        ("In", profit.amount_in),
+                        ("Out", profit.amount_out),
+                        ("Gross profit", profit.gross_profit),
+                        ("Pool fees", profit.pool_fees),
+                        ("Gas", profit.gas_cost),
+                        ("Slippage buffer", profit.slippage_buffer),
+                        ("Net profit", profit.net_profit),
+                    ] {
+                        ui.label(label);
+                        ui.label(format!("{:.6}", value));
+                        ui.end_row();
+                    }
+                });
+            ui.label(&profit.route);
+        }
+
+        ui.heading("Prices");
+        let mut pairs: BTreeMap<&str, Vec<&PoolPrice>> = BTreeMap::new();
+        for price in &self.prices {
+            pairs.entry(&price.pair).or_default().push(price);
+        }
+        for (pair, pools) in pairs {
+            ui.label(pair);
+            Plot::new(pair)
+                .height(160.0)
+                .legend(Legend::default())
+                .show(ui, |plot_ui| {
+                    for pool in pools {
+                        if let Some(history) = self.price_history.get(&pool.pool) {
+                            let points: PlotPoints = history.iter().copied().collect();
+                            plot_ui
+                                .line(Line::new(points).name(pool_label(pool.protocol, pool.fee)));
+                        }
+                    }
+                });
+        }
+
+        ui.heading("Opportunities");
+        egui::ScrollArea::vertical()
+            .id_source("opportunities")
+            .max_height(200.0)
+            .stick_to_bottom(true)
+            .show(ui, |ui| {
+                egui::Grid::new("opportunities")
+                    .num_columns(4)
+                    .striped(true)
+                    .show(ui, |ui| {
+                        ui.strong("Block");
+                        ui.strong("Route");
+                        ui.strong("Net profit");
+                        ui.strong("Outcome");
+                        ui.end_row();
+                        for opportunity in &self.opportunities {
+                            ui.label(opportunity.block.to_string());
+                            ui.label(&opportunity.profit.route);
+                            ui.label(format!("{:.6}", opportunity.profit.net_profit));
+                            ui.label(match &opportunity.outcome {
+                                Outcome::Detected => String::from("not executed"),
+                                Outcome::Paper(trade) => match &trade.reverted {
+                                    Some(reason) => format!("paper, reverted: {}", reason),
+                                    None => format!("paper, {:+.6}", trade.realized_profit),
+                                },
+                                Outcome::Sent(tx_hash) => format!("sent {:?}", tx_hash),
+                            });
+                            ui.end_row();
+                        }
+                    });
+            });
+    }
+}
@@ -0,0 +1,217 @@
+use crate::{
+    discovery::{discover_pair, discover_pools, DiscoveredPool},
+    error,
+    fixed_point::sqrt_price_x96_from_reserves,
+    rpc::FailoverHttp,
+    v2_pair::PairState,
+    v3_pool::{PoolState, SwapSimulation},
+};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use web3::{
+    types::{Address, U256},
+    Web3,
+};
+
+/// How a pool is read, simulated and swapped on.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Protocol {
+    /// Concentrated liquidity, swapped with `swap` and a callback.
+    #[default]
+    UniswapV3,
+    /// Constant product, paid up front and swapped with `swap`.
+    UniswapV2,
+}
+
+impl Protocol {
+    /// Value of the executor's `Leg.protocol`.
+    pub fn id(self) -> u8 {
+        match self {
+            Protocol::UniswapV3 => 0,
+            Protocol::UniswapV2 => 1,
+        }
+    }
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Protocol::UniswapV3 => write!(f, "Uniswap V3"),
+            Protocol::UniswapV2 => write!(f, "Uniswap V2"),
+        }
+    }
+}
+
+/// An exchange pools are discovered on: a factory and the protocol its
+/// pools speak.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Dex {
+    /// Uniswap V3 or a fork, with a pool per pair and fee tier.
+    UniswapV3 {
+        factory: Address,
+        fee_tiers: Vec<u32>,
+    },
+    /// Uniswap V2 or a fork such as SushiSwap, PancakeSwap or QuickSwap,
+    /// with one pair per token pair at the fee the fork charges.
+    UniswapV2 { factory: Address, fee: u32 },
+}
+
+impl Dex {
+    /// Every pool of the exchange between the two tokens that has liquidity.
+    pub async fn discover(
+        &self,
+        web3: &Web3<FailoverHttp>,
+        token_a: Address,
+        token_b: Address,
+    ) -> error::Result<Vec<DiscoveredPool>> {
+        match self {
+            Dex::UniswapV3 { factory, fee_tiers } => {
+                discover_pools(web3, *factory, token_a, token_b, fee_tiers).await
+            }
+            Dex::UniswapV2 { factory, fee } => {
+                Ok(discover_pair(web3, *factory, token_a, token_b, *fee)
+                    .await?
+                    .into_iter()
+                    .collect())
+            }
+        }
+    }
+}
+
+/// Snapshot of a pool of either protocol, for simulating routes across
+/// them.
+#[derive(Debug, Clone)]
+pub enum Pool {
+    V3(PoolState),
+    V2(PairState),
+}
+
+impl Pool {
+    /// Reads the state of a discovered pool, with `words_each_side` bitmap
+    /// words around the current tick for V3.
+    pub async fn fetch(
+        web3: &Web3<FailoverHttp>,
+        pool: &DiscoveredPool,
+        words_each_side: i16,
+    ) -> error::Result<Pool> {
+        Ok(match pool.protocol {
+            Protocol::UniswapV3 => {
+                Pool::V3(PoolState::fetch(web3, pool.address, words_each_side).await?)
+            }
+            Protocol::UniswapV2 => Pool::V2(PairState::fetch(web3, pool.address, pool.fee).await?),
+        })
+    }
+
+    pub fn protocol(&self) -> Protocol {
+        match self {
+            Pool::V3(_) => Protocol::UniswapV3,
+            Pool::V2(_) => Protocol::UniswapV2,
+        }
+    }
+
+    pub fn address(&self) -> Address {
+        match self {
+            Pool::V3(pool) => pool.address,
+            Pool::V2(pair) => pair.address,
+        }
+    }
+
+    pub fn token0(&self) -> Address {
+        match self {
+            Pool::V3(pool) => pool.token0,
+            Pool::V2(pair) => pair.token0,
+        }
+    }
+
+    pub fn token1(&self) -> Address {
+        match self {
+            Pool::V3(pool) => pool.token1,
+            Pool::V2(pair) => pair.token1,
+        }
+    }
+
+    /// Swap fee in hundredths of a basis point.
+    pub fn fee(&self) -> u32 {
+        match self {
+            Pool::V3(pool) => pool.fee,
+            Pool::V2(pair) => pair.fee,
+        }
+    }
+
+    /// Direction of a swap that pays `token_in` into the pool.
+    pub fn zero_for_one(&self, token_in: Address) -> Result<bool, &'static str> {
+        match self {
+            Pool::V3(pool) => pool.zero_for_one(token_in),
+            Pool::V2(pair) => pair.zero_for_one(token_in),
+        }
+    }
+
+    /// Simulates selling `amount_in` of `token_in` with no price limit.
+    pub fn simulate_exact_input(
+        &self,
+        token_in: Address,
+        amount_in: U256,
+    ) -> Result<SwapSimulation, &'static str> {
+        match self {
+            Pool::V3(pool) => pool.simulate_exact_input(token_in, amount_in),
+            Pool::V2(pair) => pair.simulate_exact_input(token_in, amount_in),
+        }
+    }
+
+    /// Current price as a V3 `sqrtPriceX96`, derived from the reserves of a
+    /// V2 pair.
+    pub fn sqrt_price_x96(&self) -> Result<U256, &'static str> {
+        match self {
+            Pool::V3(pool) => Ok(pool.sqrt_price_x96),
+            Pool::V2(pair) => sqrt_price_x96_from_reserves(pair.reserve0, pair.reserve1),
+        }
+    }
+
+    /// In-range liquidity, or the square root of the product of the reserves
+    /// of a V2 pair.
+    pub fn liquidity(&self) -> u128 {
+        match self {
+            Pool::V3(pool) => pool.liquidity,
+            Pool::V2(pair) => (pair.reserve0 * pair.reserve1).integer_sqrt().low_u128(),
+        }
+    }
+
+    /// Applies the result of a simulated swap, as if it had been sent.
+    pub fn apply_swap(&mut self, simulation: &SwapSimulation) {
+        match self {
+            Pool::V3(pool) => pool.apply_swap(simulation),
+            Pool::V2(pair) => pair.apply_swap(simulation),
+        }
+    }
+}
+
+/// Chains exact-input simulations through `pools`, feeding each leg's output
+/// into the next. The route must end in the token it started with.
+pub fn simulate_route(
+    pools: &[&Pool],
+    token_in: Address,
+    amount_in: U256,
+) -> Result<Vec<SwapSimulation>, &'static str> {
+    let mut token = token_in;
+    let mut amount = amount_in;
+    let mut legs = Vec::with_capacity(pools.len());
+
+    for pool in pools {
+        let leg = pool.simulate_exact_input(token, amount)?;
+        if !leg.is_complete() {
+            return Err("pool cannot fill the full amount within the fetched ticks");
+        }
+        token = if leg.zero_for_one {
+            pool.token1()
+        } else {
+            pool.token0()
+        };
+        amount = leg.amount_out;
+        legs.push(leg);
+    }
+
+    if token != token_in {
+        return Err("route does not end in the token it started with");
+    }
+    Ok(legs)
+}
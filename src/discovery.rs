@@ -0,0 +1,227 @@
+use crate::{
+    dex::Protocol,
+    error::{self, Error},
+    fixed_point::sqrt_price_x96_from_reserves,
+    rpc::FailoverHttp,
+};
+use futures::future::try_join_all;
+use std::fs::File;
+use web3::{
+    contract::{Contract, Options},
+    types::{Address, BlockNumber, FilterBuilder, U256},
+    Web3,
+};
+
+/// Tiers every canonical Uniswap V3 deployment ships with. Checked even when
+/// the node refuses to serve the factory's full log history.
+pub const DEFAULT_FEE_TIERS: [u32; 4] = [100, 500, 3000, 10000];
+
+/// A pool found on a factory for a token pair, at one fee tier for V3.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DiscoveredPool {
+    pub protocol: Protocol,
+    pub address: Address,
+    pub token0: Address,
+    pub token1: Address,
+    pub fee: u32,
+    /// For a V2 pair, the square root of the product of its reserves.
+    pub liquidity: u128,
+    pub sqrt_price_x96: U256,
+}
+
+impl DiscoveredPool {
+    /// The token received for paying `token_in` into the pool.
+    pub fn other_token(&self, token_in: Address) -> Address {
+        if token_in == self.token0 {
+            self.token1
+        } else {
+            self.token0
+        }
+    }
+}
+
+pub fn fee_label(fee: u32) -> String {
+    format!("{}%", fee as f64 / 10_000.0)
+}
+
+/// The fee, marked with the protocol unless it is V3.
+pub fn pool_label(protocol: Protocol, fee: u32) -> String {
+    match protocol {
+        Protocol::UniswapV3 => fee_label(fee),
+        Protocol::UniswapV2 => format!("V2 {}", fee_label(fee)),
+    }
+}
+
+pub fn load_abi(path: &str) -> error::Result<web3::ethabi::Contract> {
+    let file =
+        File::open(path).map_err(|e| Error::Abi(format!("Failed to open {}: {}", path, e)))?;
+    web3::ethabi::Contract::load(file)
+        .map_err(|e| Error::Abi(format!("Failed to load {}: {}", path, e)))
+}
+
+/// Fee tiers the factory accepts: the standard ones plus any announced with
+/// `FeeAmountEnabled`, keeping those `feeAmountTickSpacing` confirms.
+pub async fn enabled_fee_tiers(
+    web3: &Web3<FailoverHttp>,
+    factory_address: Address,
+) -> error::Result<Vec<u32>> {
+    let factory_abi = load_abi("./factory_abi.json")?;
+    let fee_amount_enabled = factory_abi.event("FeeAmountEnabled")?.signature();
+
+    let filter = FilterBuilder::default()
+        .address(vec![factory_address])
+        .topics(Some(vec![fee_amount_enabled]), None, None, None)
+        .from_block(BlockNumber::Earliest)
+        .to_block(BlockNumber::Latest)
+        .build();
+
+    let mut candidates: Vec<u32> = DEFAULT_FEE_TIERS.to_vec();
+    match web3.eth().logs(filter).await {
+        Ok(logs) => candidates.extend(
+            logs.iter()
+                .filter_map(|log| log.topics.get(1))
+                .map(|topic| U256::from_big_endian(topic.as_bytes()).low_u32()),
+        ),
+        Err(e) => println!(
+            "Could not read FeeAmountEnabled logs, checking standard fee tiers only: {}",
+            e
+        ),
+    }
+    candidates.sort_unstable();
+    candidates.dedup();
+
+    let factory_contract = Contract::new(web3.eth(), factory_address, factory_abi);
+    let tick_spacings: Vec<i32> = try_join_all(candidates.iter().map(|fee| {
+        factory_contract.query(
+            "feeAmountTickSpacing",
+            (*fee,),
+            None,
+            Options::default(),
+            None,
+        )
+    }))
+    .await?;
+
+    Ok(candidates
+        .into_iter()
+        .zip(tick_spacings)
+        .filter(|(_, tick_spacing)| *tick_spacing > 0)
+        .map(|(fee, _)| fee)
+        .collect())
+}
+
+/// Every initialized pool for the pair across `fee_tiers`, most liquid first.
+/// Pools without in-range liquidity are left out since nothing can be
+/// swapped through them.
+pub async fn discover_pools(
+    web3: &Web3<FailoverHttp>,
+    factory_address: Address,
+    token_a: Address,
+    token_b: Address,
+    fee_tiers: &[u32],
+) -> error::Result<Vec<DiscoveredPool>> {
+    let factory_contract =
+        Contract::new(web3.eth(), factory_address, load_abi("./factory_abi.json")?);
+    let pool_abi = load_abi("./pool_abi.json")?;
+
+    let pool_addresses: Vec<Address> = try_join_all(fee_tiers.iter().map(|fee| {
+        factory_contract.query(
+            "getPool",
+            (token_a, token_b, *fee),
+            None,
+            Options::default(),
+            None,
+        )
+    }))
+    .await?;
+
+    // Pools sort their tokens by address.
+    let (token0, token1) = if token_a < token_b {
+        (token_a, token_b)
+    } else {
+        (token_b, token_a)
+    };
+
+    let mut pools = Vec::new();
+    for (fee, address) in fee_tiers.iter().zip(pool_addresses) {
+        if address.is_zero() {
+            continue;
+        }
+
+        let pool_contract = Contract::new(web3.eth(), address, pool_abi.clone());
+        let liquidity: u128 = pool_contract
+            .query("liquidity", (), None, Options::default(), None)
+            .await?;
+        let slot0: (U256, i32, u16, u16, u16, u8, bool) = pool_contract
+            .query("slot0", (), None, Options::default(), None)
+            .await?;
+
+        if liquidity > 0 {
+            pools.push(DiscoveredPool {
+                protocol: Protocol::UniswapV3,
+                address,
+                token0,
+                token1,
+                fee: *fee,
+                liquidity,
+                sqrt_price_x96: slot0.0,
+            });
+        }
+    }
+
+    pools.sort_by_key(|pool| std::cmp::Reverse(pool.liquidity));
+    Ok(pools)
+}
+
+/// The V2 pair for the tokens on `factory_address`, if it exists and holds
+/// reserves of both. `fee` is what the factory's fork charges.
+pub async fn discover_pair(
+    web3: &Web3<FailoverHttp>,
+    factory_address: Address,
+    token_a: Address,
+    token_b: Address,
+    fee: u32,
+) -> error::Result<Option<DiscoveredPool>> {
+    let factory_contract = Contract::new(
+        web3.eth(),
+        factory_address,
+        load_abi("./v2_factory_abi.json")?,
+    );
+    let address: Address = factory_contract
+        .query(
+            "getPair",
+            (token_a, token_b),
+            None,
+            Options::default(),
+            None,
+        )
+        .await?;
+    if address.is_zero() {
+        return Ok(None);
+    }
+
+    let pair_contract = Contract::new(web3.eth(), address, load_abi("./v2_pair_abi.json")?);
+    let (reserve0, reserve1, _): (U256, U256, u32) = pair_contract
+        .query("getReserves", (), None, Options::default(), None)
+        .await?;
+    if reserve0.is_zero() || reserve1.is_zero() {
+        return Ok(None);
+    }
+
+    // Pairs sort their tokens by address like V3 pools.
+    let (token0, token1) = if token_a < token_b {
+        (token_a, token_b)
+    } else {
+        (token_b, token_a)
+    };
+    Ok(Some(DiscoveredPool {
+        protocol: Protocol::UniswapV2,
+        address,
+        token0,
+        token1,
+        fee,
+        liquidity: (reserve0 * reserve1).integer_sqrt().low_u128(),
+        sqrt_price_x96: sqrt_price_x96_from_reserves(reserve0, reserve1)
+            .map_err(Error::Simulation)?,
+    }))
+}
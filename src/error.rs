@@ -0,0 +1,91 @@
+use std::fmt;
+use web3::{
+    ethabi::{self, ParamType},
+    types::Bytes,
+};
+
+/// Selector of `Error(string)`, the revert payload of `require` and
+/// `revert` with a message.
+const ERROR_STRING_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Missing or malformed settings, config file or keystore.
+    Config(String),
+    /// The node could not be reached or answered with an error.
+    Rpc(web3::Error),
+    /// An ABI file that does not load or a call that does not encode.
+    Abi(String),
+    /// A response, log or recording that does not decode.
+    Decode(String),
+    /// The off-chain swap model could not price a route.
+    Simulation(&'static str),
+    /// Signing or broadcasting a transaction failed.
+    Execution(String),
+    /// The node reports the transaction would revert, with its reason.
+    Revert(String),
+    /// The wallet cannot pay for what it is about to do.
+    InsufficientFunds(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Config(message) => write!(f, "Config error: {}", message),
+            Error::Rpc(err) => write!(f, "{}", err),
+            Error::Abi(message) => write!(f, "ABI error: {}", message),
+            Error::Decode(message) => write!(f, "Decoding error: {}", message),
+            Error::Simulation(message) => write!(f, "Simulation failed: {}", message),
+            Error::Execution(message) => write!(f, "Execution failed: {}", message),
+            Error::Revert(reason) => write!(f, "Reverted: {}", reason),
+            Error::InsufficientFunds(message) => write!(f, "Insufficient funds: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<web3::Error> for Error {
+    fn from(err: web3::Error) -> Error {
+        match err {
+            web3::Error::Rpc(rpc) if rpc.message.contains("revert") => {
+                let reason = rpc
+                    .data
+                    .as_ref()
+                    .and_then(|data| serde_json::from_value::<Bytes>(data.clone()).ok())
+                    .and_then(|data| revert_reason(&data.0))
+                    .unwrap_or(rpc.message);
+                Error::Revert(reason)
+            }
+            web3::Error::Decoder(message) => Error::Decode(message),
+            err => Error::Rpc(err),
+        }
+    }
+}
+
+impl From<web3::contract::Error> for Error {
+    fn from(err: web3::contract::Error) -> Error {
+        match err {
+            web3::contract::Error::Api(err) => err.into(),
+            web3::contract::Error::Abi(err) => Error::Abi(err.to_string()),
+            err => Error::Decode(err.to_string()),
+        }
+    }
+}
+
+impl From<ethabi::Error> for Error {
+    fn from(err: ethabi::Error) -> Error {
+        Error::Abi(err.to_string())
+    }
+}
+
+/// Message of an `Error(string)` revert payload.
+fn revert_reason(data: &[u8]) -> Option<String> {
+    let payload = data.strip_prefix(&ERROR_STRING_SELECTOR)?;
+    match ethabi::decode(&[ParamType::String], payload).ok()?.pop()? {
+        ethabi::Token::String(reason) => Some(reason),
+        _ => None,
+    }
+}
@@ -0,0 +1,164 @@
+use crate::{
+    dex::{Pool, Protocol},
+    discovery::load_abi,
+    error::{self, Error},
+    fixed_point::sqrt_price_limit_x96,
+    rpc::FailoverHttp,
+    v3_pool::{SwapSimulation, MAX_AMOUNT_SPECIFIED},
+};
+use web3::{
+    ethabi::Token,
+    types::{Address, Bytes, CallRequest, TransactionParameters, U256},
+    Web3,
+};
+
+/// One swap of `FlashArbitrageExecutor.execute`, mirroring the contract's
+/// `Leg` struct.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExecutorLeg {
+    pub pool: Address,
+    pub protocol: Protocol,
+    pub zero_for_one: bool,
+    /// Only enforced by V3 pools.
+    pub sqrt_price_limit_x96: U256,
+    /// Fee a V2 pair's output is computed with, in hundredths of a basis
+    /// point.
+    pub fee: u32,
+}
+
+impl ExecutorLeg {
+    /// Leg paying `token_in` into `pool` as `simulation` did. The direction
+    /// comes from the pool's `token0`/`token1`, and the price limit lets the
+    /// pool move `slippage_percent` past where the simulated swap left it.
+    /// A leg the price has run away from stops at the limit, and the
+    /// contract reverts the route instead of keeping the unspent input. Its
+    /// `minProfit` check still guards the route as a whole.
+    pub fn new(
+        pool: &Pool,
+        token_in: Address,
+        simulation: &SwapSimulation,
+        slippage_percent: f64,
+    ) -> Result<ExecutorLeg, &'static str> {
+        let zero_for_one = pool.zero_for_one(token_in)?;
+        if zero_for_one != simulation.zero_for_one {
+            return Err("simulation swapped the other way");
+        }
+        Ok(ExecutorLeg {
+            pool: pool.address(),
+            protocol: pool.protocol(),
+            zero_for_one,
+            sqrt_price_limit_x96: sqrt_price_limit_x96(
+                simulation.sqrt_price_x96_after,
+                zero_for_one,
+                slippage_percent,
+            )?,
+            fee: pool.fee(),
+        })
+    }
+
+    /// Legs of a route simulated with `simulate_route` from `token_in`.
+    pub fn for_route(
+        pools: &[&Pool],
+        token_in: Address,
+        simulations: &[SwapSimulation],
+        slippage_percent: f64,
+    ) -> Result<Vec<ExecutorLeg>, &'static str> {
+        let mut token = token_in;
+        pools
+            .iter()
+            .zip(simulations)
+            .map(|(pool, simulation)| {
+                let leg = ExecutorLeg::new(pool, token, simulation, slippage_percent)?;
+                token = if leg.zero_for_one {
+                    pool.token1()
+                } else {
+                    pool.token0()
+                };
+                Ok(leg)
+            })
+            .collect()
+    }
+
+    fn to_token(self) -> Token {
+        Token::Tuple(vec![
+            Token::Address(self.pool),
+            Token::Uint(U256::from(self.protocol.id())),
+            Token::Bool(self.zero_for_one),
+            Token::Uint(self.sqrt_price_limit_x96),
+            Token::Uint(U256::from(self.fee)),
+        ])
+    }
+}
+
+/// Arguments of a single `execute` call covering every leg of a route.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArbitrageCall {
+    /// Pool the master token is borrowed from. Must not appear in `legs`.
+    pub flash_pool: Address,
+    pub token: Address,
+    pub amount: U256,
+    pub legs: Vec<ExecutorLeg>,
+    /// Smallest surplus over the loan and flash fee the route must return.
+    pub min_profit: U256,
+}
+
+impl ArbitrageCall {
+    pub fn tokens(&self) -> Vec<Token> {
+        vec![
+            Token::Address(self.flash_pool),
+            Token::Address(self.token),
+            Token::Uint(self.amount),
+            Token::Array(self.legs.iter().map(|leg| leg.to_token()).collect()),
+            Token::Uint(self.min_profit),
+        ]
+    }
+
+    /// ABI-encoded calldata for `execute`. The contract swaps `amount` as
+    /// a positive `amountSpecified`, so it must fit an `int256`.
+    pub fn encode(&self) -> error::Result<Vec<u8>> {
+        if self.amount.is_zero() || self.amount > MAX_AMOUNT_SPECIFIED {
+            return Err(Error::Simulation("amount must be a positive int256"));
+        }
+        let executor_abi = load_abi("./executor_abi.json")?;
+        Ok(executor_abi
+            .function("execute")?
+            .encode_input(&self.tokens())?)
+    }
+}
+
+/// The unsigned `execute` transaction. Nonce and fees are set when it is
+/// signed.
+pub fn arbitrage_transaction(
+    executor_address: Address,
+    call: &ArbitrageCall,
+    gas_limit: u64,
+) -> error::Result<TransactionParameters> {
+    Ok(TransactionParameters {
+        to: Some(executor_address),
+        gas: U256::from(gas_limit),
+        data: Bytes(call.encode()?),
+        ..Default::default()
+    })
+}
+
+/// Runs the `execute` call through `eth_call` and `eth_estimateGas` from
+/// `from` without broadcasting anything, returning the gas it would use. A
+/// call that would revert fails with `Error::Revert`.
+pub async fn dry_run_arbitrage(
+    web3: &Web3<FailoverHttp>,
+    executor_address: Address,
+    call: &ArbitrageCall,
+    gas_limit: u64,
+    from: Address,
+) -> error::Result<U256> {
+    let request = CallRequest {
+        from: Some(from),
+        to: Some(executor_address),
+        gas: Some(U256::from(gas_limit)),
+        data: Some(Bytes(call.encode()?)),
+        ..Default::default()
+    };
+
+    web3.eth().call(request.clone(), None).await?;
+    Ok(web3.eth().estimate_gas(request, None).await?)
+}
@@ -0,0 +1,308 @@
+use crate::v3_math::{
+    mul_div, mul_div_rounding_up, u256_as_f64, MAX_SQRT_RATIO, MIN_SQRT_RATIO, Q96,
+};
+use web3::types::U256;
+
+/// 2^128
+pub const Q128: U256 = U256([0, 0, 1, 0]);
+/// 2^192
+pub const Q192: U256 = U256([0, 0, 0, 1]);
+
+/// Largest power of ten a `U256` holds.
+pub const MAX_U256_DECIMALS: u8 = 77;
+/// Millionths, the unit slippage is rounded to.
+const PIPS: u64 = 1_000_000;
+
+/// Raw token1 per token0 price of a `sqrtPriceX96`, as a Q128.128.
+pub fn price_x128(sqrt_price_x96: U256) -> Result<U256, &'static str> {
+    mul_div(sqrt_price_x96, sqrt_price_x96, U256::one() << 64)
+}
+
+/// What `base_amount` of one token of a pool is worth in the other at
+/// `sqrt_price_x96`, rounded down. Mirrors Uniswap's
+/// `OracleLibrary.getQuoteAtTick`, which keeps full precision by squaring
+/// into a Q128.192 only while that cannot overflow.
+pub fn quote(
+    sqrt_price_x96: U256,
+    base_amount: U256,
+    base_is_token0: bool,
+) -> Result<U256, &'static str> {
+    if sqrt_price_x96 <= U256::from(u128::MAX) {
+        let ratio_x192 = sqrt_price_x96 * sqrt_price_x96;
+        if base_is_token0 {
+            mul_div(ratio_x192, base_amount, Q192)
+        } else {
+            mul_div(Q192, base_amount, ratio_x192)
+        }
+    } else {
+        let ratio_x128 = price_x128(sqrt_price_x96)?;
+        if base_is_token0 {
+            mul_div(ratio_x128, base_amount, Q128)
+        } else {
+            mul_div(Q128, base_amount, ratio_x128)
+        }
+    }
+}
+
+/// `sqrtPriceX96` of a constant-product pair holding `reserve0` and
+/// `reserve1`, rounded down.
+pub fn sqrt_price_x96_from_reserves(reserve0: U256, reserve1: U256) -> Result<U256, &'static str> {
+    if reserve0.is_zero() {
+        return Err("pair has no liquidity");
+    }
+    U256::try_from((reserve1.full_mul(Q192) / reserve0).integer_sqrt())
+        .map_err(|_| "price out of range")
+}
+
+/// Whole token1 per whole token0 at `sqrt_price_x96`.
+pub fn sqrt_price_x96_to_price(sqrt_price_x96: U256, decimals0: u8, decimals1: u8) -> f64 {
+    let ratio = u256_as_f64(sqrt_price_x96) / u256_as_f64(Q96);
+    ratio * ratio * 10_f64.powi(decimals0 as i32 - decimals1 as i32)
+}
+
+/// Price limit for a swap in the `zero_for_one` direction that lets the
+/// pool move `slippage_percent` past `sqrt_price_x96`, kept strictly inside
+/// the range the pool accepts. Selling token0 lowers the price, so the
+/// limit is below it; selling token1 puts it above.
+pub fn sqrt_price_limit_x96(
+    sqrt_price_x96: U256,
+    zero_for_one: bool,
+    slippage_percent: f64,
+) -> Result<U256, &'static str> {
+    if !slippage_percent.is_finite() || slippage_percent < 0.0 {
+        return Err("slippage must be a non-negative number");
+    }
+    let slippage_pips = (slippage_percent * 1e4).round().min(PIPS as f64) as u64;
+
+    // The square root of the price moves by the square root of the factor,
+    // taken as a Q64.96.
+    if zero_for_one {
+        let sqrt_factor_x96 = ((U256::from(PIPS - slippage_pips) << 192) / PIPS).integer_sqrt();
+        let limit = mul_div_rounding_up(sqrt_price_x96, sqrt_factor_x96, Q96)?;
+        Ok(limit.max(MIN_SQRT_RATIO + 1))
+    } else {
+        let sqrt_factor_x96 = ((U256::from(PIPS + slippage_pips) << 192) / PIPS).integer_sqrt();
+        let limit = mul_div(sqrt_price_x96, sqrt_factor_x96, Q96)?;
+        Ok(limit.min(MAX_SQRT_RATIO - 1))
+    }
+}
+
+/// `amount` whole tokens in the smallest units of a token with `decimals`,
+/// rounded to the nearest unit. Works on the exact binary value of the
+/// float, so nothing is lost beyond what `f64` already cannot represent.
+pub fn to_token_units(amount: f64, decimals: u8) -> Result<U256, &'static str> {
+    if !amount.is_finite() || amount < 0.0 {
+        return Err("amount must be a non-negative number");
+    }
+    if decimals > MAX_U256_DECIMALS {
+        return Err("too many decimals");
+    }
+    if amount == 0.0 {
+        return Ok(U256::zero());
+    }
+
+    // amount = mantissa * 2^exponent
+    let bits = amount.to_bits();
+    let biased_exponent = ((bits >> 52) & 0x7ff) as i32;
+    let fraction = bits & ((1 << 52) - 1);
+    let (mantissa, exponent) = if biased_exponent == 0 {
+        (fraction, -1074)
+    } else {
+        (fraction | (1 << 52), biased_exponent - 1075)
+    };
+
+    let scaled = U256::from(mantissa)
+        .checked_mul(U256::exp10(decimals as usize))
+        .ok_or("amount overflows uint256")?;
+    if exponent >= 0 {
+        if scaled.bits() + exponent as usize > 256 {
+            return Err("amount overflows uint256");
+        }
+        return Ok(scaled << exponent as usize);
+    }
+
+    let shift = exponent.unsigned_abs() as usize;
+    if shift > 256 {
+        return Ok(U256::zero());
+    }
+    let half = U256::one() << (shift - 1);
+    let (units, remainder) = if shift == 256 {
+        (U256::zero(), scaled)
+    } else {
+        (scaled >> shift, scaled & ((U256::one() << shift) - 1))
+    };
+    Ok(if remainder >= half { units + 1 } else { units })
+}
+
+/// `amount` smallest units of a token with `decimals` in whole tokens.
+/// Whole and fractional parts are converted apart, so large balances keep
+/// their fraction.
+pub fn from_token_units(amount: U256, decimals: u8) -> f64 {
+    if decimals > MAX_U256_DECIMALS {
+        return u256_as_f64(amount) / 10_f64.powi(decimals as i32);
+    }
+    let (whole, fraction) = amount.div_mod(U256::exp10(decimals as usize));
+    u256_as_f64(whole) + u256_as_f64(fraction) / 10_f64.powi(decimals as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v3_math::{
+        get_sqrt_ratio_at_tick, get_tick_at_sqrt_ratio, mul_div_rounding_up, MAX_SQRT_RATIO,
+        MAX_TICK, MIN_SQRT_RATIO, MIN_TICK,
+    };
+    use proptest::prelude::*;
+
+    fn u256(value: &str) -> U256 {
+        U256::from_dec_str(value).unwrap()
+    }
+
+    /// Uniswap's `encodePriceSqrt(reserve1, reserve0)` test helper.
+    fn encode_price_sqrt(reserve1: u64, reserve0: u64) -> U256 {
+        ((U256::from(reserve1) << 192) / reserve0).integer_sqrt()
+    }
+
+    #[test]
+    fn encoded_prices() {
+        // Quoting reserve0 gives just under reserve1 where the square
+        // root was rounded down.
+        for (reserve1, reserve0, expected, quoted) in [
+            (1, 1, "79228162514264337593543950336", 1),
+            (100, 1, "792281625142643375935439503360", 100),
+            (1, 100, "7922816251426433759354395033", 0),
+            (121, 100, "87150978765690771352898345369", 120),
+        ] {
+            let sqrt_price_x96 = encode_price_sqrt(reserve1, reserve0);
+            assert_eq!(sqrt_price_x96, u256(expected));
+            assert_eq!(
+                sqrt_price_x96_from_reserves(U256::from(reserve0), U256::from(reserve1)).unwrap(),
+                sqrt_price_x96
+            );
+            let price = reserve1 as f64 / reserve0 as f64;
+            assert!(
+                (sqrt_price_x96_to_price(sqrt_price_x96, 18, 18) - price).abs() < price * 1e-12
+            );
+            assert_eq!(
+                quote(sqrt_price_x96, U256::from(reserve0), true).unwrap(),
+                U256::from(quoted)
+            );
+        }
+    }
+
+    /// A USDC (6 decimals) / WETH (18 decimals) pool at 2000 USDC per WETH.
+    /// USDC is token0, so its raw price is WETH wei per USDC unit.
+    #[test]
+    fn mixed_decimals() {
+        let sqrt_price_x96 = encode_price_sqrt(1_000_000_000_000, 2000);
+        let price = sqrt_price_x96_to_price(sqrt_price_x96, 6, 18);
+        assert!((price.recip() - 2000.0).abs() < 1e-9);
+
+        let usdc = quote(sqrt_price_x96, U256::exp10(18), false).unwrap();
+        assert!(usdc >= U256::from(1_999_999_999u64) && usdc <= U256::from(2_000_000_000u64));
+        assert!((from_token_units(usdc, 6) - 2000.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn token_units() {
+        assert_eq!(to_token_units(0.0001, 18).unwrap(), U256::exp10(14));
+        assert_eq!(to_token_units(1.5, 6).unwrap(), U256::from(1_500_000));
+        assert_eq!(to_token_units(0.1 + 0.2, 6).unwrap(), U256::from(300_000));
+        assert_eq!(to_token_units(1e-7, 6).unwrap(), U256::zero());
+        assert_eq!(
+            to_token_units(123_456_789.123_456, 18).unwrap(),
+            u256("123456789123456001281738281")
+        );
+        assert!(to_token_units(-1.0, 18).is_err());
+        assert!(to_token_units(f64::NAN, 18).is_err());
+        assert!(to_token_units(1e60, 18).is_err());
+        assert_eq!(from_token_units(U256::from(1_500_000), 6), 1.5);
+    }
+
+    #[test]
+    fn price_limits() {
+        assert_eq!(sqrt_price_limit_x96(Q96, true, 0.0).unwrap(), Q96);
+        assert_eq!(sqrt_price_limit_x96(Q96, false, 0.0).unwrap(), Q96);
+        // 1% on the price is about 0.5% on its square root.
+        let below = sqrt_price_limit_x96(Q96, true, 1.0).unwrap();
+        assert!((sqrt_price_x96_to_price(below, 18, 18) - 0.99).abs() < 1e-9);
+        let above = sqrt_price_limit_x96(Q96, false, 1.0).unwrap();
+        assert!((sqrt_price_x96_to_price(above, 18, 18) - 1.01).abs() < 1e-9);
+        assert_eq!(
+            sqrt_price_limit_x96(Q96, true, 100.0).unwrap(),
+            MIN_SQRT_RATIO + 1
+        );
+        assert_eq!(
+            sqrt_price_limit_x96(MAX_SQRT_RATIO - 2, false, 1.0).unwrap(),
+            MAX_SQRT_RATIO - 1
+        );
+        assert!(sqrt_price_limit_x96(Q96, true, -1.0).is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn price_limits_stay_in_range(
+            tick in MIN_TICK + 1..MAX_TICK,
+            zero_for_one: bool,
+            slippage_percent in 0.0..100.0f64,
+        ) {
+            let sqrt_price_x96 = get_sqrt_ratio_at_tick(tick).unwrap();
+            let limit = sqrt_price_limit_x96(sqrt_price_x96, zero_for_one, slippage_percent).unwrap();
+            prop_assert!(limit > MIN_SQRT_RATIO && limit < MAX_SQRT_RATIO);
+            if zero_for_one {
+                prop_assert!(limit <= sqrt_price_x96);
+            } else {
+                prop_assert!(limit >= sqrt_price_x96);
+            }
+        }
+
+        #[test]
+        fn tick_round_trips(tick in MIN_TICK..MAX_TICK) {
+            let sqrt_price_x96 = get_sqrt_ratio_at_tick(tick).unwrap();
+            prop_assert_eq!(get_tick_at_sqrt_ratio(sqrt_price_x96).unwrap(), tick);
+            prop_assert!(get_sqrt_ratio_at_tick(tick + 1).unwrap() > sqrt_price_x96);
+        }
+
+        #[test]
+        fn tick_brackets_sqrt_price(raw in any::<[u64; 3]>()) {
+            let sqrt_price_x96 = MIN_SQRT_RATIO
+                + U256([raw[0], raw[1], raw[2], 0]) % (MAX_SQRT_RATIO - MIN_SQRT_RATIO);
+            let tick = get_tick_at_sqrt_ratio(sqrt_price_x96).unwrap();
+            prop_assert!(get_sqrt_ratio_at_tick(tick).unwrap() <= sqrt_price_x96);
+            prop_assert!(get_sqrt_ratio_at_tick(tick + 1).unwrap() > sqrt_price_x96);
+        }
+
+        #[test]
+        fn mul_div_rounds(a in any::<[u64; 4]>(), b in any::<u128>(), d in 1..u128::MAX) {
+            let (a, b, d) = (U256(a), U256::from(b), U256::from(d));
+            if let Ok(down) = mul_div(a, b, d) {
+                let exact = a.full_mul(b);
+                prop_assert_eq!(exact / d, down.into());
+                let up = mul_div_rounding_up(a, b, d).unwrap();
+                prop_assert_eq!(up - down, U256::from(!(exact % d).is_zero() as u8));
+            }
+        }
+
+        #[test]
+        fn quote_matches_price(tick in -400_000..400_000i32, amount in 1u64..u64::MAX) {
+            let sqrt_price_x96 = get_sqrt_ratio_at_tick(tick).unwrap();
+            let expected = amount as f64 * sqrt_price_x96_to_price(sqrt_price_x96, 0, 0);
+            let quoted = u256_as_f64(quote(sqrt_price_x96, U256::from(amount), true).unwrap());
+            prop_assert!((quoted - expected).abs() <= expected * 1e-12 + 1.0);
+        }
+
+        #[test]
+        fn token_units_round_trip(units in 0..1u64 << 48, decimals in 0u8..=36) {
+            let units = U256::from(units);
+            let amount = from_token_units(units, decimals);
+            prop_assert_eq!(to_token_units(amount, decimals).unwrap(), units);
+        }
+
+        #[test]
+        fn token_units_are_nearest(amount in 0.0..1e12f64, decimals in 0u8..=18) {
+            let units = to_token_units(amount, decimals).unwrap();
+            let scaled = amount * 10_f64.powi(decimals as i32);
+            prop_assert!((u256_as_f64(units) - scaled).abs() <= 0.5 + scaled * 1e-15);
+        }
+    }
+}
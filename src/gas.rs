@@ -0,0 +1,435 @@
+use crate::{error, rpc::FailoverHttp, v3_math::u256_as_f64};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use web3::{
+    types::{BlockId, BlockNumber, TransactionParameters, U256, U64},
+    Web3,
+};
+
+/// Blocks of `eth_feeHistory` the priority fee is derived from.
+const FEE_HISTORY_BLOCKS: u64 = 20;
+/// Reward percentiles requested from `eth_feeHistory`, the middle one for
+/// `FeeStrategy::Percentile` and the last for `FeeStrategy::Aggressive`.
+const REWARD_PERCENTILES: [f64; 2] = [50.0, 90.0];
+/// Multiples of the next base fee `max_fee_per_gas` leaves room for, so
+/// the transaction stays valid through a few full blocks.
+const BASE_FEE_HEADROOM: u64 = 2;
+const AGGRESSIVE_BASE_FEE_HEADROOM: u64 = 3;
+const EIP1559_TX_TYPE: u64 = 2;
+/// How much a replacement outbids the transaction it replaces by. Nodes
+/// want at least 10% more on every fee field.
+const REPLACEMENT_BUMP_PERCENT: u64 = 12;
+const WEI_PER_GWEI: f64 = 1e9;
+
+/// How the priority fee of a transaction is chosen.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FeeStrategy {
+    /// `priority_fee_gwei` from the config, which is the whole gas price on
+    /// chains without a base fee.
+    Fixed,
+    /// Median priority fee paid over recent blocks.
+    #[default]
+    Percentile,
+    /// Highest 90th percentile priority fee over recent blocks, with more
+    /// room for the base fee to rise.
+    Aggressive,
+}
+
+impl fmt::Display for FeeStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FeeStrategy::Fixed => write!(f, "Fixed"),
+            FeeStrategy::Percentile => write!(f, "Percentile"),
+            FeeStrategy::Aggressive => write!(f, "Aggressive"),
+        }
+    }
+}
+
+/// Fee settings taken from `Config`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FeeSettings {
+    pub strategy: FeeStrategy,
+    pub priority_fee_gwei: f64,
+    /// Cap on the fee per gas, 0 for none.
+    pub max_fee_gwei: f64,
+    /// Whether the chain takes type-2 transactions.
+    pub eip1559: bool,
+}
+
+impl FeeSettings {
+    /// `max_fee_gwei` in wei, `None` when uncapped.
+    pub fn cap(&self) -> Option<U256> {
+        (self.max_fee_gwei > 0.0).then(|| gwei_to_wei(self.max_fee_gwei))
+    }
+}
+
+/// Fees for the next transaction, in wei per gas.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FeeQuote {
+    /// Base fee of the next block, `None` when sending legacy transactions.
+    pub base_fee: Option<U256>,
+    pub max_priority_fee: U256,
+    /// `maxFeePerGas` of a type-2 transaction, the gas price of a legacy
+    /// one.
+    pub max_fee: U256,
+}
+
+impl FeeQuote {
+    /// What one unit of gas is expected to cost once included.
+    pub fn gas_price(&self) -> U256 {
+        match self.base_fee {
+            Some(base_fee) => self
+                .max_fee
+                .min(base_fee.saturating_add(self.max_priority_fee)),
+            None => self.max_fee,
+        }
+    }
+
+    /// Cost of `gas_units` at this price, in whole native tokens.
+    pub fn cost_in_native(&self, gas_units: u64) -> f64 {
+        u256_as_f64(self.gas_price().saturating_mul(U256::from(gas_units))) / 1e18
+    }
+
+    /// Cost of `gas_units` at the highest price the transaction allows,
+    /// which nodes want the sender to hold before accepting it.
+    pub fn max_cost_in_native(&self, gas_units: u64) -> f64 {
+        u256_as_f64(self.max_fee.saturating_mul(U256::from(gas_units))) / 1e18
+    }
+
+    /// Sets the fee fields of a transaction to be signed.
+    pub fn apply(&self, tx: &mut TransactionParameters) {
+        if self.base_fee.is_some() {
+            tx.transaction_type = Some(U64::from(EIP1559_TX_TYPE));
+            tx.max_fee_per_gas = Some(self.max_fee);
+            tx.max_priority_fee_per_gas = Some(self.max_priority_fee);
+        } else {
+            tx.gas_price = Some(self.max_fee);
+        }
+    }
+
+    /// Fees for a transaction replacing one sent at `previous`: the higher
+    /// of these and enough of a bump for nodes to accept the replacement.
+    pub fn replacing(&self, previous: &FeeQuote) -> FeeQuote {
+        let bump = |wei: U256| wei + wei * REPLACEMENT_BUMP_PERCENT / 100;
+        let max_fee = self.max_fee.max(bump(previous.max_fee));
+        FeeQuote {
+            base_fee: self.base_fee,
+            max_priority_fee: self
+                .max_priority_fee
+                .max(bump(previous.max_priority_fee))
+                .min(max_fee),
+            max_fee,
+        }
+    }
+}
+
+impl fmt::Display for FeeQuote {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let gwei = |wei: U256| u256_as_f64(wei) / WEI_PER_GWEI;
+        match self.base_fee {
+            Some(base_fee) => write!(
+                f,
+                "base fee {:.3} gwei, priority fee {:.3} gwei, max fee {:.3} gwei",
+                gwei(base_fee),
+                gwei(self.max_priority_fee),
+                gwei(self.max_fee)
+            ),
+            None => write!(f, "gas price {:.3} gwei", gwei(self.max_fee)),
+        }
+    }
+}
+
+/// Prices the next transaction from `eth_feeHistory`, or from
+/// `eth_gasPrice` on nodes without it. Fails when the cap leaves nothing
+/// above the base fee, as such a transaction would never be included.
+pub async fn fetch_fee_quote(
+    web3: &Web3<FailoverHttp>,
+    settings: &FeeSettings,
+) -> error::Result<FeeQuote> {
+    let history = web3
+        .eth()
+        .fee_history(
+            U256::from(FEE_HISTORY_BLOCKS),
+            BlockNumber::Latest,
+            Some(REWARD_PERCENTILES.to_vec()),
+        )
+        .await;
+
+    let (next_base_fee, priority_fee) = match history {
+        Ok(history) => {
+            let next_base_fee = history.base_fee_per_gas.last().copied().unwrap_or_default();
+            let rewards = history.reward.unwrap_or_default();
+            let priority_fee = match settings.strategy {
+                FeeStrategy::Fixed => gwei_to_wei(settings.priority_fee_gwei),
+                FeeStrategy::Percentile => median(rewards.iter().filter_map(|r| r.first())),
+                FeeStrategy::Aggressive => rewards
+                    .iter()
+                    .filter_map(|r| r.last())
+                    .max()
+                    .copied()
+                    .unwrap_or_default(),
+            };
+            (next_base_fee, priority_fee)
+        }
+        Err(err) => {
+            println!("eth_feeHistory failed, pricing from eth_gasPrice: {}", err);
+            let gas_price = web3.eth().gas_price().await?;
+            let base_fee = web3
+                .eth()
+                .block(BlockId::Number(BlockNumber::Latest))
+                .await?
+                .and_then(|block| block.base_fee_per_gas)
+                .unwrap_or_default();
+            let priority_fee = match settings.strategy {
+                FeeStrategy::Fixed => gwei_to_wei(settings.priority_fee_gwei),
+                _ => gas_price.saturating_sub(base_fee),
+            };
+            (base_fee, priority_fee)
+        }
+    };
+
+    let cap = settings.cap();
+    if cap.is_some_and(|cap| cap < next_base_fee) {
+        return Err(error::Error::Execution(format!(
+            "base fee of {:.3} gwei is above the max fee cap",
+            u256_as_f64(next_base_fee) / WEI_PER_GWEI
+        )));
+    }
+
+    let quote = if settings.eip1559 && !next_base_fee.is_zero() {
+        let headroom = match settings.strategy {
+            FeeStrategy::Aggressive => AGGRESSIVE_BASE_FEE_HEADROOM,
+            _ => BASE_FEE_HEADROOM,
+        };
+        let max_fee = next_base_fee
+            .saturating_mul(U256::from(headroom))
+            .saturating_add(priority_fee);
+        let max_fee = cap.map_or(max_fee, |cap| max_fee.min(cap));
+        FeeQuote {
+            base_fee: Some(next_base_fee),
+            max_priority_fee: priority_fee.min(max_fee),
+            max_fee,
+        }
+    } else {
+        let gas_price = next_base_fee.saturating_add(priority_fee);
+        FeeQuote {
+            base_fee: None,
+            max_priority_fee: priority_fee,
+            max_fee: cap.map_or(gas_price, |cap| gas_price.min(cap)),
+        }
+    };
+    Ok(quote)
+}
+
+fn gwei_to_wei(gwei: f64) -> U256 {
+    U256::from((gwei.max(0.0) * WEI_PER_GWEI).round() as u128)
+}
+
+fn median<'a>(values: impl Iterator<Item = &'a U256>) -> U256 {
+    let mut values: Vec<U256> = values.copied().collect();
+    values.sort_unstable();
+    values.get(values.len() / 2).copied().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_rpc::MockRpc;
+    use serde_json::{json, Value};
+
+    fn gwei(gwei: u64) -> U256 {
+        U256::exp10(9) * gwei
+    }
+
+    /// A node whose fee history ends on `base_fee` gwei, with 50th and 90th
+    /// percentile rewards of 1/5, 2/8 and 3/4 gwei.
+    fn node(base_fee: u64) -> (MockRpc, Web3<FailoverHttp>) {
+        let mock = MockRpc::start(move |method, _| match method {
+            "eth_feeHistory" => Ok(json!({
+                "oldestBlock": "0x61",
+                "baseFeePerGas": [gwei(9), gwei(9), gwei(9), gwei(base_fee)],
+                "gasUsedRatio": [0.5, 0.5, 0.5],
+                "reward": [[gwei(1), gwei(5)], [gwei(2), gwei(8)], [gwei(3), gwei(4)]],
+            })),
+            _ => Err(format!("unexpected {}", method)),
+        });
+        let web3 = Web3::new(FailoverHttp::new(std::slice::from_ref(&mock.url)).unwrap());
+        (mock, web3)
+    }
+
+    fn settings(strategy: FeeStrategy) -> FeeSettings {
+        FeeSettings {
+            strategy,
+            priority_fee_gwei: 1.5,
+            max_fee_gwei: 0.0,
+            eip1559: true,
+        }
+    }
+
+    #[tokio::test]
+    async fn strategies() {
+        let (mock, web3) = node(10);
+        let quote = |settings| {
+            let web3 = web3.clone();
+            async move { fetch_fee_quote(&web3, &settings).await.unwrap() }
+        };
+
+        assert_eq!(
+            quote(settings(FeeStrategy::Fixed)).await,
+            FeeQuote {
+                base_fee: Some(gwei(10)),
+                max_priority_fee: U256::from(1_500_000_000u64),
+                max_fee: U256::from(21_500_000_000u64),
+            }
+        );
+        assert_eq!(
+            quote(settings(FeeStrategy::Percentile)).await,
+            FeeQuote {
+                base_fee: Some(gwei(10)),
+                max_priority_fee: gwei(2),
+                max_fee: gwei(22),
+            }
+        );
+        assert_eq!(
+            quote(settings(FeeStrategy::Aggressive)).await,
+            FeeQuote {
+                base_fee: Some(gwei(10)),
+                max_priority_fee: gwei(8),
+                max_fee: gwei(38),
+            }
+        );
+
+        let calls = mock.calls("eth_feeHistory");
+        assert_eq!(calls.len(), 3);
+        assert_eq!(calls[0], json!(["0x14", "latest", [50.0, 90.0]]));
+    }
+
+    #[tokio::test]
+    async fn legacy_chains() {
+        // A chain configured without EIP-1559 gets a gas price.
+        let (_mock, web3) = node(10);
+        let legacy = FeeSettings {
+            eip1559: false,
+            ..settings(FeeStrategy::Percentile)
+        };
+        let quote = fetch_fee_quote(&web3, &legacy).await.unwrap();
+        assert_eq!(
+            quote,
+            FeeQuote {
+                base_fee: None,
+                max_priority_fee: gwei(2),
+                max_fee: gwei(12),
+            }
+        );
+        let mut tx = TransactionParameters::default();
+        quote.apply(&mut tx);
+        assert_eq!(
+            (tx.gas_price, tx.transaction_type, tx.max_fee_per_gas),
+            (Some(gwei(12)), None, None)
+        );
+
+        // So does one whose blocks carry no base fee.
+        let (_mock, web3) = node(0);
+        let quote = fetch_fee_quote(&web3, &settings(FeeStrategy::Fixed))
+            .await
+            .unwrap();
+        assert_eq!(quote.base_fee, None);
+        assert_eq!(quote.max_fee, U256::from(1_500_000_000u64));
+
+        // Type 2 otherwise.
+        let (_mock, web3) = node(10);
+        let mut tx = TransactionParameters::default();
+        fetch_fee_quote(&web3, &settings(FeeStrategy::Percentile))
+            .await
+            .unwrap()
+            .apply(&mut tx);
+        assert_eq!(
+            (tx.gas_price, tx.transaction_type, tx.max_fee_per_gas),
+            (None, Some(U64::from(EIP1559_TX_TYPE)), Some(gwei(22)))
+        );
+        assert_eq!(tx.max_priority_fee_per_gas, Some(gwei(2)));
+    }
+
+    #[tokio::test]
+    async fn max_fee_cap() {
+        let (_mock, web3) = node(10);
+        let capped = |strategy, max_fee_gwei, eip1559| FeeSettings {
+            max_fee_gwei,
+            eip1559,
+            ..settings(strategy)
+        };
+
+        let quote = fetch_fee_quote(&web3, &capped(FeeStrategy::Aggressive, 15.0, true))
+            .await
+            .unwrap();
+        assert_eq!((quote.max_fee, quote.max_priority_fee), (gwei(15), gwei(8)));
+        assert_eq!(quote.gas_price(), gwei(15));
+
+        // A cap right at the base fee still quotes.
+        let quote = fetch_fee_quote(&web3, &capped(FeeStrategy::Percentile, 10.0, true))
+            .await
+            .unwrap();
+        assert_eq!((quote.max_fee, quote.max_priority_fee), (gwei(10), gwei(2)));
+
+        let quote = fetch_fee_quote(&web3, &capped(FeeStrategy::Percentile, 11.0, false))
+            .await
+            .unwrap();
+        assert_eq!(quote.max_fee, gwei(11));
+
+        // Under the cap nothing changes.
+        let quote = fetch_fee_quote(&web3, &capped(FeeStrategy::Percentile, 100.0, true))
+            .await
+            .unwrap();
+        assert_eq!(quote.max_fee, gwei(22));
+
+        assert!(matches!(
+            fetch_fee_quote(&web3, &capped(FeeStrategy::Percentile, 9.0, true)).await,
+            Err(error::Error::Execution(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_gas_price() {
+        let mock = MockRpc::start(|method, _| match method {
+            "eth_gasPrice" => Ok(json!(gwei(13))),
+            "eth_getBlockByNumber" => Ok(block(gwei(10))),
+            _ => Err("the method eth_feeHistory does not exist".into()),
+        });
+        let web3 = Web3::new(FailoverHttp::new(std::slice::from_ref(&mock.url)).unwrap());
+
+        let quote = fetch_fee_quote(&web3, &settings(FeeStrategy::Percentile))
+            .await
+            .unwrap();
+        assert_eq!(
+            quote,
+            FeeQuote {
+                base_fee: Some(gwei(10)),
+                max_priority_fee: gwei(3),
+                max_fee: gwei(23),
+            }
+        );
+    }
+
+    fn block(base_fee: U256) -> Value {
+        let zero = format!("0x{}", "0".repeat(64));
+        json!({
+            "hash": zero,
+            "parentHash": zero,
+            "sha3Uncles": zero,
+            "miner": format!("0x{}", "0".repeat(40)),
+            "stateRoot": zero,
+            "transactionsRoot": zero,
+            "receiptsRoot": zero,
+            "number": "0x64",
+            "gasUsed": "0x0",
+            "gasLimit": "0x1c9c380",
+            "baseFeePerGas": base_fee,
+            "extraData": "0x",
+            "logsBloom": format!("0x{}", "0".repeat(512)),
+            "timestamp": "0x0",
+            "difficulty": "0x0",
+            "uncles": [],
+            "transactions": [],
+        })
+    }
+}
@@ -0,0 +1,113 @@
+use crate::{
+    error::{self, Error},
+    priv_key_to_pub_key,
+};
+use eth_keystore::KeystoreError;
+use hex::FromHex;
+use std::{fs, path::Path};
+use web3::{signing::SecretKey, types::Address};
+
+/// Directory imported keys are written to, next to `config.json`.
+pub const KEYSTORE_DIR: &str = "keystore";
+
+/// Encrypts a hex private key into a V3 keystore (scrypt, AES-128-CTR) in
+/// `dir`, named after its address. Returns the file's path and the address.
+pub fn import_key(
+    private_key: &str,
+    password: &str,
+    dir: &str,
+) -> error::Result<(String, Address)> {
+    if password.is_empty() {
+        return Err(Error::Config("Keystore password must not be empty".into()));
+    }
+
+    let private_key = private_key.trim().trim_start_matches("0x").to_string();
+    let address = priv_key_to_pub_key(&private_key).map_err(|e| Error::Config(e.to_string()))?;
+    let key_bytes = Vec::from_hex(&private_key)
+        .map_err(|e| Error::Config(format!("Invalid private key: {}", e)))?;
+
+    fs::create_dir_all(dir)
+        .map_err(|e| Error::Config(format!("Failed to create {}: {}", dir, e)))?;
+    let name = format!("{:?}.json", address);
+    eth_keystore::encrypt_key(
+        dir,
+        &mut rand::thread_rng(),
+        key_bytes,
+        password,
+        Some(&name),
+    )
+    .map_err(|e| Error::Config(format!("Failed to write keystore: {}", e)))?;
+
+    Ok((
+        Path::new(dir).join(name).to_string_lossy().into_owned(),
+        address,
+    ))
+}
+
+/// Decrypts the keystore at `path`. The key is only ever held in memory.
+pub fn unlock(path: &str, password: &str) -> error::Result<SecretKey> {
+    let key_bytes = eth_keystore::decrypt_key(path, password).map_err(|e| match e {
+        KeystoreError::MacMismatch => Error::Config("Wrong keystore password".into()),
+        e => Error::Config(format!("Failed to read keystore {}: {}", path, e)),
+    })?;
+
+    SecretKey::from_slice(&key_bytes)
+        .map_err(|e| Error::Config(format!("Keystore holds an invalid key: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+
+    fn dir(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("keystore-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        dir.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn import_then_unlock() {
+        let dir = dir("round-trip");
+        let (path, address) = import_key(KEY, "hunter2", &dir).unwrap();
+        assert_eq!(
+            address,
+            "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23"
+                .parse::<Address>()
+                .unwrap()
+        );
+        assert_eq!(path, format!("{}/{:?}.json", dir, address));
+        // The key never reaches the disk in clear text.
+        assert!(!fs::read_to_string(&path)
+            .unwrap()
+            .contains(KEY.trim_start_matches("0x")));
+
+        let key = unlock(&path, "hunter2").unwrap();
+        assert_eq!(
+            key,
+            SecretKey::from_slice(&Vec::from_hex(&KEY[2..]).unwrap()).unwrap()
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn wrong_password() {
+        let dir = dir("wrong-password");
+        let (path, _) = import_key(KEY, "hunter2", &dir).unwrap();
+        assert!(matches!(
+            unlock(&path, "hunter3"),
+            Err(Error::Config(message)) if message == "Wrong keystore password"
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        let dir = dir("bad-input");
+        assert!(import_key(KEY, "", &dir).is_err());
+        assert!(import_key("0x1234", "hunter2", &dir).is_err());
+        assert!(unlock(&format!("{}/missing.json", dir), "hunter2").is_err());
+        assert!(!Path::new(&dir).exists());
+    }
+}
@@ -0,0 +1,87 @@
+use std::fmt;
+use web3::types::U64;
+
+/// A trade dry-run mode would have sent. Amounts are in whole master
+/// tokens.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaperTrade {
+    pub route: String,
+    pub amount_in: f64,
+    pub amount_out: f64,
+    /// Gas `eth_estimateGas` reported, `None` when no executor was
+    /// configured to check the call against.
+    pub gas_used: Option<u64>,
+    pub gas_cost: f64,
+    /// Change in the master balance had the trade been sent.
+    pub realized_profit: f64,
+    /// Why `eth_call` says the transaction would have reverted.
+    pub reverted: Option<String>,
+}
+
+impl fmt::Display for PaperTrade {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: in {:.6} out {:.6} gas {:.6}",
+            self.route, self.amount_in, self.amount_out, self.gas_cost
+        )?;
+        if let Some(gas_used) = self.gas_used {
+            write!(f, " ({} units)", gas_used)?;
+        }
+        match &self.reverted {
+            Some(reason) => write!(f, " | reverted: {}", reason),
+            None => write!(f, " | profit {:+.6}", self.realized_profit),
+        }
+    }
+}
+
+/// Virtual master token balance moved by paper trades instead of real
+/// transactions.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PaperLedger {
+    pub starting_balance: f64,
+    pub balance: f64,
+    pub gas_spent: f64,
+    pub trades: Vec<(U64, PaperTrade)>,
+}
+
+impl PaperLedger {
+    pub fn new(starting_balance: f64) -> PaperLedger {
+        PaperLedger {
+            starting_balance,
+            balance: starting_balance,
+            ..Default::default()
+        }
+    }
+
+    pub fn record(&mut self, block: U64, trade: PaperTrade) {
+        self.balance += trade.realized_profit;
+        self.gas_spent += trade.gas_cost;
+        self.trades.push((block, trade));
+    }
+
+    pub fn profit(&self) -> f64 {
+        self.balance - self.starting_balance
+    }
+
+    pub fn reverted(&self) -> usize {
+        self.trades
+            .iter()
+            .filter(|(_, trade)| trade.reverted.is_some())
+            .count()
+    }
+}
+
+impl fmt::Display for PaperLedger {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Paper balance {:.6} ({:+.6}) after {} trades, {} reverted, {:.6} spent on gas",
+            self.balance,
+            self.profit(),
+            self.trades.len(),
+            self.reverted(),
+            self.gas_spent
+        )
+    }
+}
@@ -1,250 +1,930 @@
+mod allowance;
+mod backtest;
+mod dashboard;
+mod dex;
+mod discovery;
+mod error;
+mod executor;
+mod fixed_point;
+mod gas;
+mod keystore;
+mod ledger;
+#[cfg(test)]
+mod mock_rpc;
+mod nonce;
+mod profit;
+mod registry;
+mod relay;
+mod routing;
+mod rpc;
+mod subscription;
+mod v2_pair;
+mod v3_math;
+mod v3_pool;
+mod validation;
+mod wallet;
+
+use allowance::{ApprovalPolicy, Approver};
+use dashboard::{Dashboard, OpportunityRecord, Outcome, PoolPrice};
+use dex::{simulate_route, Dex, Pool, Protocol};
+use discovery::{
+    discover_pools, enabled_fee_tiers, load_abi, pool_label, DiscoveredPool, DEFAULT_FEE_TIERS,
+};
 use eframe::egui;
+use error::Error;
+use executor::{arbitrage_transaction, dry_run_arbitrage, ArbitrageCall, ExecutorLeg};
+use fixed_point::{from_token_units, quote, sqrt_price_x96_to_price, to_token_units};
+use futures::future::try_join_all;
+use gas::{fetch_fee_quote, FeeQuote, FeeSettings, FeeStrategy};
 use hex::FromHex;
-use reqwest::Client;
+use keystore::KEYSTORE_DIR;
+use ledger::{PaperLedger, PaperTrade};
+use nonce::NonceManager;
+use profit::ProfitBreakdown;
+use registry::{tx_link, ChainInfo, KnownDex, Registry, USER_REGISTRY_PATH};
+use relay::{load_auth_key, Relay, AUTH_KEY_PATH};
+use routing::{PoolGraph, Route};
+use rpc::FailoverHttp;
 use secp256k1::{PublicKey, Secp256k1};
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     fmt,
     fs::{self, File},
-    io::{Error, Write},
+    io::Write,
+    path::Path,
     str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
+use subscription::PoolWatcher;
 use tiny_keccak::{Hasher, Keccak};
+use tokio::{sync::watch, task::JoinHandle};
+use v2_pair::DEFAULT_V2_FEE;
+use v3_pool::PoolState;
+use wallet::{check_funds, fetch_balances, Balances, Inventory};
 use web3::{
     contract::{Contract, Options},
-    ethabi::Token,
     signing::SecretKey,
     transports::Http,
-    types::{Address, TransactionReceipt, H160, H256, U256},
+    types::{Address, BlockNumber, FilterBuilder, H160, H256, U256, U64},
     Web3,
 };
 
 #[tokio::main]
 async fn main() -> Result<(), eframe::Error> {
+    let args: Vec<String> = std::env::args().collect();
+
+    match args.get(1).map(String::as_str) {
+        Some("headless") => {
+            let config_path = config_path_arg(&args, "headless");
+            if let Err(err) = run_headless(&config_path).await {
+                eprintln!("Arbitrage failed: {}", err);
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some("backtest") => {
+            let config_path = config_path_arg(&args, "backtest");
+            let Some(data_path) = flag_arg(&args, "--data") else {
+                eprintln!(
+                    "Usage: arbitrage-bot backtest --data <file.json> [--events <file.csv|json>] [--config <path>]"
+                );
+                std::process::exit(2);
+            };
+            let events_path = flag_arg(&args, "--events");
+            if let Err(err) = run_backtest(&config_path, &data_path, events_path.as_deref()) {
+                eprintln!("Backtest failed: {}", err);
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some("record") => {
+            let config_path = config_path_arg(&args, "record");
+            let Some(blocks) = flag_arg(&args, "--blocks").and_then(|n| n.parse().ok()) else {
+                eprintln!(
+                    "Usage: arbitrage-bot record --blocks <n> [--out <file.json>] [--config <path>]"
+                );
+                std::process::exit(2);
+            };
+            let out_path = flag_arg(&args, "--out").unwrap_or_else(|| String::from(BACKTEST_PATH));
+            if let Err(err) = run_record(&config_path, blocks, &out_path).await {
+                eprintln!("Recording failed: {}", err);
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some("revoke-approvals") => {
+            let config_path = config_path_arg(&args, "revoke-approvals");
+            if let Err(err) = run_revoke_approvals(&config_path).await {
+                eprintln!("Revoking approvals failed: {}", err);
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some("import-key") => {
+            let config_path = config_path_arg(&args, "import-key");
+            if let Err(err) = run_import_key(&config_path) {
+                eprintln!("Import failed: {}", err);
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        _ => {}
+    }
+
     eframe::run_native(
         "Arbitrage Bot",
         eframe::NativeOptions {
             drag_and_drop_support: false,
-            initial_window_size: Some(egui::vec2(800.0, 600.0)),
+            initial_window_size: Some(egui::vec2(1280.0, 720.0)),
             ..Default::default()
         },
         Box::new(|_| Box::new(App::new())),
     )
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
-enum Chain {
-    #[default]
-    Ethereum,
-    Binance,
-    Polygon,
+fn config_path_arg(args: &[String], command: &str) -> String {
+    match args.iter().position(|arg| arg == "--config") {
+        Some(index) => match args.get(index + 1) {
+            Some(path) => path.clone(),
+            None => {
+                eprintln!("Usage: arbitrage-bot {} [--config <path>]", command);
+                std::process::exit(2);
+            }
+        },
+        None => String::from(CONFIG_PATH),
+    }
+}
+
+/// Value following `flag`, if it was given one.
+fn flag_arg(args: &[String], flag: &str) -> Option<String> {
+    let index = args.iter().position(|arg| arg == flag)?;
+    args.get(index + 1).cloned()
+}
+
+const CONFIG_PATH: &str = "config.json";
+/// Schema version written to `config.json`. Files without one are version 0.
+const CONFIG_VERSION: u32 = 2;
+const BACKTEST_PATH: &str = "backtest.json";
+/// Polls once per block time of the chain.
+const DEFAULT_POLL_INTERVAL_SECS: u64 = 0;
+const TICK_BITMAP_WORDS_EACH_SIDE: i16 = 1;
+/// Recordings cover many blocks of price movement, so they fetch more of
+/// the bitmap than a single evaluation needs.
+const RECORDED_TICK_BITMAP_WORDS_EACH_SIDE: i16 = 4;
+const DEFAULT_MAX_HOPS: usize = 3;
+const DEFAULT_PRIORITY_FEE_GWEI: f64 = 1.5;
+const DEFAULT_RELAY_TARGET_BLOCKS: u64 = 3;
+const RANKED_ROUTES_LOGGED: usize = 5;
+const RPC_HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// Blocks a sent transaction may stay pending before it is sped up.
+const STUCK_TX_BLOCKS: u64 = 3;
+/// Speed-ups of one transaction before it is cancelled instead.
+const MAX_SPEED_UPS: u32 = 3;
+/// How often wallet balances are read again while nothing is traded.
+const WALLET_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Name a chain is defined under in `chains`, such as `Ethereum` or
+/// `Base`. The registry of known contracts is keyed by it too.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(transparent)]
+struct Chain(String);
+
+impl Chain {
+    fn new(name: &str) -> Chain {
+        Chain(name.to_string())
+    }
+}
+
+impl Default for Chain {
+    fn default() -> Chain {
+        Chain::new("Ethereum")
+    }
 }
 
 impl fmt::Display for Chain {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            Chain::Ethereum => write!(f, "Ethereum"),
-            Chain::Binance => write!(f, "Binance"),
-            Chain::Polygon => write!(f, "Polygon"),
-        }
+        write!(f, "{}", self.0)
     }
 }
 
+/// An EVM chain the bot can run on.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+struct ChainConfig {
+    /// EIP-155 id, checked against `eth_chainId` when connecting.
+    chain_id: u64,
+    /// HTTP endpoints, tried fastest first.
+    rpc_urls: Vec<String>,
+    /// With a WebSocket endpoint the bot re-evaluates when a watched pool
+    /// trades instead of polling.
+    #[serde(default)]
+    ws_url: String,
+    /// Symbol of the token gas is paid in, such as ETH or BNB.
+    native_symbol: String,
+    /// Whether the chain takes type-2 transactions. BNB Chain keeps a zero
+    /// base fee, so legacy gas prices are what its validators expect.
+    eip1559: bool,
+    /// Average seconds between blocks.
+    block_time_secs: f64,
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 struct Config {
+    /// Schema version the file was written with, see `CONFIG_MIGRATIONS`.
+    #[serde(default)]
+    version: u32,
+    /// Which of `chains` to run on.
     chain: Chain,
+    #[serde(default = "default_chains")]
+    chains: BTreeMap<Chain, ChainConfig>,
     contract_address: String,
-    private_key: String,
+    /// Uniswap V2-style factories searched for pairs next to the V3 factory
+    /// at `contract_address`.
+    #[serde(default)]
+    v2_factories: Vec<V2Factory>,
+    /// V3 keystore holding the wallet key. Only the encrypted file is ever
+    /// written; the key is decrypted into memory at start.
+    #[serde(default)]
+    keystore_path: String,
     public_key: Address,
     token_address_master: String,
-    token_address_1: String,
-    token_address_2: String,
+    /// Tokens routes may pass through on the way back to the master token.
+    #[serde(default)]
+    tokens: Vec<String>,
+    /// Longest route searched, in swaps. Two is a round trip between fee
+    /// tiers of one pair, three is triangular.
+    #[serde(default = "default_max_hops")]
+    max_hops: usize,
     gas_limit: u64,
     slippage_threshhold: f64,
     minimum_profit: f64,
     amount_to_trade: f64,
+    /// Seconds between polls without a WebSocket. 0 polls once per block
+    /// time of the chain.
+    #[serde(default = "default_poll_interval_secs")]
+    poll_interval_secs: u64,
+    #[serde(default)]
+    fee_strategy: FeeStrategy,
+    /// Priority fee of the `Fixed` strategy, in gwei.
+    #[serde(default = "default_priority_fee_gwei")]
+    priority_fee_gwei: f64,
+    /// Highest fee per gas ever offered, in gwei. 0 leaves it uncapped.
+    #[serde(default)]
+    max_fee_gwei: f64,
+    /// Token gas is paid in, wrapped (WETH, WBNB, WMATIC). Empty when it is
+    /// the master token itself.
+    #[serde(default)]
+    wrapped_native_address: String,
+    /// Block explorer sent transactions are linked to. Empty logs bare
+    /// hashes.
+    #[serde(default)]
+    explorer_url: String,
+    /// Deployed `FlashArbitrageExecutor`. Nothing is executed while empty.
+    #[serde(default)]
+    executor_address: String,
+    /// V3 pool holding the master token that the executor borrows from. It
+    /// cannot be one of the pools on the route.
+    #[serde(default)]
+    flash_pool_address: String,
+    /// Contract that pulls trade inputs from the wallet with `transferFrom`,
+    /// such as a router. It is approved for the trade amount of the master
+    /// token once at start; the executor borrows through its flash loan and
+    /// is never approved.
+    #[serde(default)]
+    router_address: String,
+    /// How much the router is approved for on tokens it falls short on.
+    #[serde(default)]
+    approval_policy: ApprovalPolicy,
+    /// Simulate every transaction with `eth_call` and `eth_estimateGas` and
+    /// book the outcome on a paper ledger instead of broadcasting it.
+    #[serde(default)]
+    dry_run: bool,
+    /// Flashbots-style relay transactions are sent to as private bundles.
+    /// Empty broadcasts them to the public mempool.
+    #[serde(default)]
+    relay_url: String,
+    /// Consecutive blocks a bundle is sent for before giving up on it.
+    #[serde(default = "default_relay_target_blocks")]
+    relay_target_blocks: u64,
+}
+
+/// A Uniswap V2 fork's factory, such as SushiSwap's, PancakeSwap's on BNB
+/// Chain or QuickSwap's on Polygon.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+struct V2Factory {
+    address: String,
+    /// Swap fee in hundredths of a basis point, 3000 for Uniswap's 0.3% and
+    /// 2500 for PancakeSwap's 0.25%.
+    #[serde(default = "default_v2_fee")]
+    fee: u32,
+}
+
+fn default_v2_fee() -> u32 {
+    DEFAULT_V2_FEE
+}
+
+fn default_poll_interval_secs() -> u64 {
+    DEFAULT_POLL_INTERVAL_SECS
+}
+
+fn default_priority_fee_gwei() -> f64 {
+    DEFAULT_PRIORITY_FEE_GWEI
+}
+
+fn default_relay_target_blocks() -> u64 {
+    DEFAULT_RELAY_TARGET_BLOCKS
+}
+
+fn default_max_hops() -> usize {
+    DEFAULT_MAX_HOPS
+}
+
+/// Chains defined out of the box, each with a public RPC endpoint except
+/// Ethereum, which expects a local node.
+fn default_chains() -> BTreeMap<Chain, ChainConfig> {
+    [
+        ("Ethereum", 1, "http://127.0.0.1:8545", "ETH", true, 12.0),
+        (
+            "Binance",
+            56,
+            "https://bsc-dataseed.bnbchain.org",
+            "BNB",
+            false,
+            0.75,
+        ),
+        (
+            "Polygon",
+            137,
+            "https://polygon.blockpi.network/v1/rpc/public",
+            "POL",
+            true,
+            2.0,
+        ),
+    ]
+    .into_iter()
+    .map(
+        |(name, chain_id, rpc_url, native_symbol, eip1559, block_time_secs)| {
+            let chain = ChainConfig {
+                chain_id,
+                rpc_urls: vec![rpc_url.to_string()],
+                ws_url: String::new(),
+                native_symbol: native_symbol.to_string(),
+                eip1559,
+                block_time_secs,
+            };
+            (Chain::new(name), chain)
+        },
+    )
+    .collect()
 }
 
 impl Config {
-    fn default() -> Self {
-        Config {
-            chain: Chain::Ethereum,
-            contract_address: String::new(),
-            private_key: String::new(),
-            public_key: Address::default(),
-            token_address_master: String::new(),
-            token_address_1: String::new(),
-            token_address_2: String::new(),
-            gas_limit: 0,
-            slippage_threshhold: 0.0,
-            minimum_profit: 0.0,
-            amount_to_trade: 0.0,
+    /// Definition of the chain the bot runs on.
+    fn chain_config(&self) -> error::Result<&ChainConfig> {
+        self.chains
+            .get(&self.chain)
+            .ok_or_else(|| Error::Config(format!("chain {} is not defined", self.chain)))
+    }
+
+    fn fee_settings(&self) -> error::Result<FeeSettings> {
+        Ok(FeeSettings {
+            strategy: self.fee_strategy,
+            priority_fee_gwei: self.priority_fee_gwei,
+            max_fee_gwei: self.max_fee_gwei,
+            eip1559: self.chain_config()?.eip1559,
+        })
+    }
+
+    /// `poll_interval_secs`, or the block time of the chain when it is 0.
+    fn poll_interval(&self) -> Duration {
+        let block_time = self
+            .chains
+            .get(&self.chain)
+            .and_then(|chain| Duration::try_from_secs_f64(chain.block_time_secs).ok());
+        match block_time {
+            Some(block_time) if self.poll_interval_secs == 0 => block_time,
+            _ => Duration::from_secs(self.poll_interval_secs.max(1)),
         }
     }
+
+    /// The V3 factory with `fee_tiers` followed by every V2 factory.
+    fn dexes(&self, fee_tiers: &[u32]) -> error::Result<Vec<Dex>> {
+        let mut dexes = vec![Dex::UniswapV3 {
+            factory: token_string_to_h160(&self.contract_address)?,
+            fee_tiers: fee_tiers.to_vec(),
+        }];
+        for factory in &self.v2_factories {
+            dexes.push(Dex::UniswapV2 {
+                factory: token_string_to_h160(&factory.address)?,
+                fee: factory.fee,
+            });
+        }
+        Ok(dexes)
+    }
+
+    fn ws_url(&self) -> Option<&str> {
+        self.chains
+            .get(&self.chain)
+            .map(|chain| chain.ws_url.as_str())
+            .filter(|url| !url.is_empty())
+    }
 }
 
 struct TempValues {
     temp_private_key_input: String,
+    temp_keystore_password: String,
     temp_token_address_input_master: String,
-    temp_token_address_input_1: String,
-    temp_token_address_input_2: String,
+    temp_tokens: String,
+    temp_max_hops: String,
     temp_selected_chain: Chain,
+    temp_chains: BTreeMap<Chain, ChainInputs>,
+    /// Name typed in to add a chain.
+    temp_new_chain: String,
     temp_contract_address: String,
+    temp_v2_factories: String,
     temp_gas_limit: String,
     temp_slippage_threshhold: String,
     temp_minimum_profit: String,
     temp_amount_to_trade: String,
+    temp_poll_interval_secs: String,
+    temp_fee_strategy: FeeStrategy,
+    temp_priority_fee_gwei: String,
+    temp_max_fee_gwei: String,
+    temp_wrapped_native_address: String,
+    temp_explorer_url: String,
+    temp_executor_address: String,
+    temp_flash_pool_address: String,
+    temp_router_address: String,
+    temp_approval_policy: ApprovalPolicy,
+    temp_relay_url: String,
+    temp_relay_target_blocks: String,
+    temp_dry_run: bool,
 }
 
 impl TempValues {
     fn default() -> Self {
         TempValues {
-            temp_private_key_input: String::from(
-                "0000000000000000000000000000000000000000000000000000000000000000",
-            ),
+            temp_private_key_input: String::new(),
+            temp_keystore_password: String::new(),
             temp_token_address_input_master: String::from("0x..."),
-            temp_token_address_input_1: String::from("0x..."),
-            temp_token_address_input_2: String::from("0x..."),
+            temp_tokens: String::new(),
+            temp_max_hops: DEFAULT_MAX_HOPS.to_string(),
             temp_selected_chain: Chain::default(),
+            temp_chains: chain_inputs(&default_chains()),
+            temp_new_chain: String::new(),
             temp_contract_address: String::from("0x..."),
+            temp_v2_factories: String::new(),
             temp_gas_limit: String::from("0"),
             temp_slippage_threshhold: String::from("0"),
             temp_minimum_profit: String::from("0"),
             temp_amount_to_trade: String::from("0.0"),
+            temp_poll_interval_secs: DEFAULT_POLL_INTERVAL_SECS.to_string(),
+            temp_fee_strategy: FeeStrategy::default(),
+            temp_priority_fee_gwei: DEFAULT_PRIORITY_FEE_GWEI.to_string(),
+            temp_max_fee_gwei: String::from("0"),
+            temp_wrapped_native_address: String::new(),
+            temp_explorer_url: String::new(),
+            temp_executor_address: String::new(),
+            temp_flash_pool_address: String::new(),
+            temp_router_address: String::new(),
+            temp_approval_policy: ApprovalPolicy::default(),
+            temp_relay_url: String::new(),
+            temp_relay_target_blocks: DEFAULT_RELAY_TARGET_BLOCKS.to_string(),
+            temp_dry_run: false,
         }
     }
 
     fn new(config: Config) -> Self {
         TempValues {
-            temp_private_key_input: config.private_key,
+            temp_private_key_input: String::new(),
+            temp_keystore_password: String::new(),
             temp_token_address_input_master: config.token_address_master,
-            temp_token_address_input_1: config.token_address_1,
-            temp_token_address_input_2: config.token_address_2,
+            temp_tokens: config.tokens.join("\n"),
+            temp_max_hops: config.max_hops.to_string(),
             temp_selected_chain: config.chain,
+            temp_chains: chain_inputs(&config.chains),
+            temp_new_chain: String::new(),
             temp_contract_address: config.contract_address,
+            temp_v2_factories: v2_factory_inputs(&config.v2_factories),
             temp_gas_limit: config.gas_limit.to_string(),
             temp_slippage_threshhold: config.slippage_threshhold.to_string(),
             temp_minimum_profit: config.minimum_profit.to_string(),
             temp_amount_to_trade: config.amount_to_trade.to_string(),
+            temp_poll_interval_secs: config.poll_interval_secs.to_string(),
+            temp_fee_strategy: config.fee_strategy,
+            temp_priority_fee_gwei: config.priority_fee_gwei.to_string(),
+            temp_max_fee_gwei: config.max_fee_gwei.to_string(),
+            temp_wrapped_native_address: config.wrapped_native_address,
+            temp_explorer_url: config.explorer_url,
+            temp_executor_address: config.executor_address,
+            temp_flash_pool_address: config.flash_pool_address,
+            temp_router_address: config.router_address,
+            temp_approval_policy: config.approval_policy,
+            temp_relay_url: config.relay_url,
+            temp_relay_target_blocks: config.relay_target_blocks.to_string(),
+            temp_dry_run: config.dry_run,
+        }
+    }
+
+    /// Fills in the factory of a registry DEX: the V3 factory field, or a
+    /// new V2 factory line unless it is already listed.
+    fn use_known_dex(&mut self, dex: &KnownDex) {
+        match dex.protocol {
+            Protocol::UniswapV3 => self.temp_contract_address = dex.factory.clone(),
+            Protocol::UniswapV2 => {
+                let listed = self.temp_v2_factories.lines().any(|line| {
+                    line.split_whitespace()
+                        .next()
+                        .is_some_and(|address| address.eq_ignore_ascii_case(&dex.factory))
+                });
+                if !listed {
+                    if !self.temp_v2_factories.trim().is_empty() {
+                        self.temp_v2_factories.push('\n');
+                    }
+                    self.temp_v2_factories
+                        .push_str(&format!("{} {}", dex.factory, dex.fee));
+                }
+            }
+        }
+    }
+
+    /// Fills in the registry's wrapped native token and explorer of a chain.
+    fn use_chain_info(&mut self, info: &ChainInfo) {
+        self.temp_wrapped_native_address = info.wrapped_native.clone();
+        self.temp_explorer_url = info.explorer_url.clone();
+    }
+}
+
+/// One `address fee` line per factory for the Settings text box.
+fn v2_factory_inputs(factories: &[V2Factory]) -> String {
+    factories
+        .iter()
+        .map(|factory| format!("{} {}", factory.address, factory.fee))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Factories from the Settings text box, one address per line with an
+/// optional fee after it.
+fn parse_v2_factories(input: &str) -> Option<Vec<V2Factory>> {
+    input
+        .lines()
+        .map(str::split_whitespace)
+        .filter_map(|mut fields| {
+            let address = fields.next()?.to_string();
+            Some(match (fields.next(), fields.next()) {
+                (None, _) => Some(V2Factory {
+                    address,
+                    fee: DEFAULT_V2_FEE,
+                }),
+                (Some(fee), None) => fee.parse().ok().map(|fee| V2Factory { address, fee }),
+                (Some(_), Some(_)) => None,
+            })
+        })
+        .collect()
+}
+
+/// Settings text boxes of one chain definition.
+#[derive(Debug, Clone)]
+struct ChainInputs {
+    chain_id: String,
+    /// One URL per line.
+    rpc_urls: String,
+    ws_url: String,
+    native_symbol: String,
+    eip1559: bool,
+    block_time_secs: String,
+}
+
+impl ChainInputs {
+    /// Inputs of a chain added in Settings, with its id when the registry
+    /// knows it.
+    fn new(chain_id: Option<u64>) -> Self {
+        ChainInputs {
+            chain_id: chain_id.map(|id| id.to_string()).unwrap_or_default(),
+            rpc_urls: String::new(),
+            ws_url: String::new(),
+            native_symbol: String::new(),
+            eip1559: true,
+            block_time_secs: String::new(),
+        }
+    }
+
+    fn from_config(chain: &ChainConfig) -> Self {
+        ChainInputs {
+            chain_id: chain.chain_id.to_string(),
+            rpc_urls: chain.rpc_urls.join("\n"),
+            ws_url: chain.ws_url.clone(),
+            native_symbol: chain.native_symbol.clone(),
+            eip1559: chain.eip1559,
+            block_time_secs: chain.block_time_secs.to_string(),
         }
     }
+
+    /// The chain definition, or `None` when the chain id or block time is
+    /// not a number.
+    fn parse(&self) -> Option<ChainConfig> {
+        Some(ChainConfig {
+            chain_id: self.chain_id.trim().parse().ok()?,
+            rpc_urls: self
+                .rpc_urls
+                .lines()
+                .map(str::trim)
+                .filter(|url| !url.is_empty())
+                .map(String::from)
+                .collect(),
+            ws_url: self.ws_url.trim().to_string(),
+            native_symbol: self.native_symbol.trim().to_string(),
+            eip1559: self.eip1559,
+            block_time_secs: self.block_time_secs.trim().parse().ok()?,
+        })
+    }
+}
+
+fn chain_inputs(chains: &BTreeMap<Chain, ChainConfig>) -> BTreeMap<Chain, ChainInputs> {
+    chains
+        .iter()
+        .map(|(chain, definition)| (chain.clone(), ChainInputs::from_config(definition)))
+        .collect()
 }
 
 struct App {
     selected_chain: Chain,
-    private_key_input: String,
+    chains: BTreeMap<Chain, ChainConfig>,
+    keystore_path: String,
+    /// Decrypted wallet key, held in memory only.
+    wallet_key: Option<SecretKey>,
+    unlock_password: String,
     public_key: Address,
     token_address_input_master: String,
-    token_address_input_1: String,
-    token_address_input_2: String,
+    tokens: Vec<String>,
+    max_hops: usize,
     contract_address: String,
+    v2_factories: Vec<V2Factory>,
+    wrapped_native_address: String,
+    explorer_url: String,
+    executor_address: String,
+    flash_pool_address: String,
+    router_address: String,
+    approval_policy: ApprovalPolicy,
+    dry_run: bool,
+    relay_url: String,
+    relay_target_blocks: u64,
+    /// Known contracts offered in Settings.
+    registry: Registry,
     temp: TempValues,
     account_text_dropped: bool,
-    invalid_address_popup: bool,
     show_gas_limit_error: bool,
     show_slippage_threshhold_error: bool,
     show_minimum_profit_error: bool,
     show_amount_to_trade_error: bool,
+    show_poll_interval_error: bool,
+    show_max_hops_error: bool,
+    show_rpc_url_error: bool,
+    show_chain_error: bool,
+    show_fee_error: bool,
+    show_relay_error: bool,
+    show_v2_factories_error: bool,
     invalid_private_key: bool,
+    keystore_error: Option<String>,
+    config_error: Option<String>,
+    registry_error: Option<String>,
     gas_limit: u64,
     slippage_threshhold: f64,
     minimum_profit: f64,
     amount_to_trade: f64,
+    poll_interval_secs: u64,
+    fee_strategy: FeeStrategy,
+    priority_fee_gwei: f64,
+    max_fee_gwei: f64,
+    arbitrage_task: Option<ArbitrageTask>,
+    arbitrage_status: Arc<Mutex<ArbitrageStatus>>,
+}
+
+struct ArbitrageTask {
+    stop_tx: watch::Sender<bool>,
+    handle: JoinHandle<()>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum RunState {
+    #[default]
+    Idle,
+    Running,
+    Stopped,
+}
+
+#[derive(Debug, Clone, Default)]
+struct ArbitrageStatus {
+    state: RunState,
+    evaluations: u64,
+    last_block: Option<U64>,
+    last_error: Option<String>,
+    last_profit: Option<ProfitBreakdown>,
+    paper_ledger: Option<PaperLedger>,
+    wallet: Option<Inventory>,
+    dashboard: Dashboard,
+}
+
+impl fmt::Display for ArbitrageStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.state {
+            RunState::Idle => write!(f, "Idle")?,
+            RunState::Running => write!(f, "Running: {} evaluations", self.evaluations)?,
+            RunState::Stopped => write!(f, "Stopped after {} evaluations", self.evaluations)?,
+        }
+        if let Some(block) = self.last_block {
+            write!(f, " (last block {})", block)?;
+        }
+        Ok(())
+    }
 }
 
 impl App {
     fn default() -> Self {
         App {
-            selected_chain: Chain::Ethereum,
-            private_key_input: String::new(),
+            selected_chain: Chain::default(),
+            chains: default_chains(),
+            keystore_path: String::new(),
+            wallet_key: None,
+            unlock_password: String::new(),
             public_key: Address::default(),
             token_address_input_master: String::new(),
-            token_address_input_1: String::new(),
-            token_address_input_2: String::new(),
+            tokens: Vec::new(),
+            max_hops: DEFAULT_MAX_HOPS,
             contract_address: String::new(),
+            v2_factories: Vec::new(),
+            wrapped_native_address: String::new(),
+            explorer_url: String::new(),
+            executor_address: String::new(),
+            flash_pool_address: String::new(),
+            router_address: String::new(),
+            approval_policy: ApprovalPolicy::default(),
+            dry_run: false,
+            relay_url: String::new(),
+            relay_target_blocks: DEFAULT_RELAY_TARGET_BLOCKS,
+            registry: Registry::new(),
             temp: TempValues::default(),
             account_text_dropped: false,
-            invalid_address_popup: false,
             show_gas_limit_error: false,
             show_slippage_threshhold_error: false,
             show_minimum_profit_error: false,
             invalid_private_key: false,
+            keystore_error: None,
+            config_error: None,
+            registry_error: None,
             show_amount_to_trade_error: false,
+            show_poll_interval_error: false,
+            show_max_hops_error: false,
+            show_rpc_url_error: false,
+            show_chain_error: false,
+            show_fee_error: false,
+            show_relay_error: false,
+            show_v2_factories_error: false,
             gas_limit: 0,
             slippage_threshhold: 0.0,
             minimum_profit: 0.0,
             amount_to_trade: 0.0,
+            poll_interval_secs: DEFAULT_POLL_INTERVAL_SECS,
+            fee_strategy: FeeStrategy::default(),
+            priority_fee_gwei: DEFAULT_PRIORITY_FEE_GWEI,
+            max_fee_gwei: 0.0,
+            arbitrage_task: None,
+            arbitrage_status: Arc::new(Mutex::new(ArbitrageStatus::default())),
         }
     }
 
     fn new() -> Self {
         let current_config = get_config();
 
-        match current_config {
+        let mut app = match current_config {
             Ok(config) => {
                 let config2 = config.clone();
                 App {
                     selected_chain: config.chain,
-                    private_key_input: config.private_key,
+                    chains: config.chains,
+                    keystore_path: config.keystore_path,
+                    wallet_key: None,
+                    unlock_password: String::new(),
                     public_key: config.public_key,
                     token_address_input_master: config.token_address_master,
-                    token_address_input_1: config.token_address_1,
-                    token_address_input_2: config.token_address_2,
+                    tokens: config.tokens,
+                    max_hops: config.max_hops,
                     contract_address: config.contract_address,
+                    v2_factories: config.v2_factories,
+                    wrapped_native_address: config.wrapped_native_address,
+                    explorer_url: config.explorer_url,
+                    executor_address: config.executor_address,
+                    flash_pool_address: config.flash_pool_address,
+                    router_address: config.router_address,
+                    approval_policy: config.approval_policy,
+                    dry_run: config.dry_run,
+                    relay_url: config.relay_url,
+                    relay_target_blocks: config.relay_target_blocks,
+                    registry: Registry::new(),
                     temp: TempValues::new(config2),
                     account_text_dropped: false,
-                    invalid_address_popup: false,
                     show_gas_limit_error: false,
                     show_slippage_threshhold_error: false,
                     show_minimum_profit_error: false,
                     invalid_private_key: false,
+                    keystore_error: None,
+                    config_error: None,
+                    registry_error: None,
                     show_amount_to_trade_error: false,
+                    show_poll_interval_error: false,
+                    show_max_hops_error: false,
+                    show_rpc_url_error: false,
+                    show_chain_error: false,
+                    show_fee_error: false,
+                    show_relay_error: false,
+                    show_v2_factories_error: false,
                     gas_limit: config.gas_limit,
                     slippage_threshhold: config.slippage_threshhold,
                     minimum_profit: config.minimum_profit,
                     amount_to_trade: config.amount_to_trade,
+                    poll_interval_secs: config.poll_interval_secs,
+                    fee_strategy: config.fee_strategy,
+                    priority_fee_gwei: config.priority_fee_gwei,
+                    max_fee_gwei: config.max_fee_gwei,
+                    arbitrage_task: None,
+                    arbitrage_status: Arc::new(Mutex::new(ArbitrageStatus::default())),
                 }
             }
-            Err(_) => return App::default(),
-        }
-    }
-
-    fn get_config() -> Config {
-        let current_config = get_config();
-
-        match current_config {
-            Ok(config) => Config {
-                chain: config.chain,
-                contract_address: config.contract_address,
-                private_key: config.private_key,
-                public_key: config.public_key,
-                token_address_master: config.token_address_master,
-                token_address_1: config.token_address_1,
-                token_address_2: config.token_address_2,
-                gas_limit: config.gas_limit,
-                slippage_threshhold: config.slippage_threshhold,
-                minimum_profit: config.minimum_profit,
-                amount_to_trade: config.amount_to_trade,
+            // A first run has no config yet; anything else is worth saying.
+            Err(err) => App {
+                config_error: Path::new(CONFIG_PATH).exists().then(|| err.to_string()),
+                ..App::default()
             },
-            Err(_) => return Config::default(),
+        };
+        // Offer a key left in clear text by an older config for encryption
+        // on the next Save.
+        if let Some(private_key) = plaintext_private_key(CONFIG_PATH) {
+            app.temp.temp_private_key_input = private_key;
+        }
+        match registry::load(USER_REGISTRY_PATH) {
+            Ok(registry) => app.registry = registry,
+            Err(err) => app.registry_error = Some(err.to_string()),
         }
+        app
     }
 }
 
 impl eframe::App for App {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        egui::SidePanel::right("dashboard")
+            .resizable(true)
+            .default_width(560.0)
+            .show(ctx, |ui| {
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    let status = self.arbitrage_status.lock().unwrap();
+                    if let Some(wallet) = &status.wallet {
+                        wallet.show(ui);
+                    }
+                    status.dashboard.show(ui, status.last_profit.as_ref());
+                });
+            });
+
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.vertical_centered(|ui| {
                 ui.spacing_mut().item_spacing.y = 20.0;
 
                 ui.group(|ui| {
                     ui.spacing_mut().item_spacing.y = 20.0;
+                    if self.keystore_path.is_empty() {
+                        ui.label("No wallet keystore, opportunities are only simulated");
+                    } else if self.wallet_key.is_some() {
+                        ui.label(format!("Wallet {:?} unlocked", self.public_key));
+                    } else {
+                        ui.horizontal(|ui| {
+                            ui.label(format!("Keystore password for {:?}: ", self.public_key));
+                            ui.add(
+                                egui::TextEdit::singleline(&mut self.unlock_password)
+                                    .password(true),
+                            );
+                        });
+                    }
                     if ui.button("Start Arbitrage").clicked() {
                         begin_arbitrage(self);
                     }
                     if ui.button("Stop Arbitrage").clicked() {
-                        println!("Button 2 was pressed!");
+                        stop_arbitrage(self);
+                    }
+
+                    let status = self.arbitrage_status.lock().unwrap().clone();
+                    ui.label(status.to_string());
+                    if let Some(ledger) = &status.paper_ledger {
+                        ui.label(ledger.to_string());
+                        if let Some((block, trade)) = ledger.trades.last() {
+                            ui.label(format!("Last paper trade (block {}): {}", block, trade));
+                        }
+                    }
+                    if let Some(err) = &status.last_error {
+                        ui.label(format!("Last error: {}", err));
+                    }
+                    if status.state == RunState::Running {
+                        ctx.request_repaint_after(Duration::from_millis(500));
                     }
                 });
 
@@ -259,38 +939,187 @@ impl eframe::App for App {
                         ui.horizontal(|ui| {
                             ui.add_space(indent);
                             egui::ComboBox::from_label("Select a chain")
-                                .selected_text(self.temp.temp_selected_chain.clone().to_string())
+                                .selected_text(self.temp.temp_selected_chain.to_string())
                                 .show_ui(ui, |ui| {
-                                    ui.selectable_value(
-                                        &mut self.temp.temp_selected_chain,
-                                        Chain::Ethereum,
-                                        "Ethereum",
-                                    );
-                                    ui.selectable_value(
-                                        &mut self.temp.temp_selected_chain,
-                                        Chain::Polygon,
-                                        "Polygon",
-                                    );
-                                    ui.selectable_value(
-                                        &mut self.temp.temp_selected_chain,
-                                        Chain::Binance,
-                                        "Binance",
-                                    );
+                                    for chain in self.temp.temp_chains.keys() {
+                                        ui.selectable_value(
+                                            &mut self.temp.temp_selected_chain,
+                                            chain.clone(),
+                                            chain.to_string(),
+                                        );
+                                    }
                                 });
                         });
 
+                        ui.horizontal(|ui| {
+                            ui.label("New chain: ");
+                            ui.add(
+                                egui::TextEdit::singleline(&mut self.temp.temp_new_chain)
+                                    .desired_width(120.0),
+                            );
+                            let name = Chain::new(self.temp.temp_new_chain.trim());
+                            if ui.button("Add Chain").clicked()
+                                && !name.0.is_empty()
+                                && !self.temp.temp_chains.contains_key(&name)
+                            {
+                                let chain_id = self.registry.get(&name).map(|info| info.chain_id);
+                                self.temp
+                                    .temp_chains
+                                    .insert(name.clone(), ChainInputs::new(chain_id));
+                                self.temp.temp_selected_chain = name;
+                                self.temp.temp_new_chain.clear();
+                            }
+                            if ui.button("Remove Chain").clicked()
+                                && self.temp.temp_chains.len() > 1
+                            {
+                                self.temp
+                                    .temp_chains
+                                    .remove(&self.temp.temp_selected_chain);
+                                if let Some(chain) = self.temp.temp_chains.keys().next() {
+                                    self.temp.temp_selected_chain = chain.clone();
+                                }
+                            }
+                        });
+
+                        if let Some(inputs) =
+                            self.temp.temp_chains.get_mut(&self.temp.temp_selected_chain)
+                        {
+                            ui.horizontal(|ui| {
+                                ui.label("Chain ID: ");
+                                ui.add(
+                                    egui::TextEdit::singleline(&mut inputs.chain_id)
+                                        .desired_width(90.0),
+                                );
+                                ui.label("Native Symbol: ");
+                                ui.add(
+                                    egui::TextEdit::singleline(&mut inputs.native_symbol)
+                                        .desired_width(90.0),
+                                );
+                                ui.label("Block Time (seconds): ");
+                                ui.add(
+                                    egui::TextEdit::singleline(&mut inputs.block_time_secs)
+                                        .desired_width(90.0),
+                                );
+                                ui.checkbox(&mut inputs.eip1559, "EIP-1559");
+                            });
+
+                            ui.label(format!(
+                                "{} RPC URLs (one per line): ",
+                                self.temp.temp_selected_chain
+                            ));
+                            ui.text_edit_multiline(&mut inputs.rpc_urls);
+
+                            ui.label(format!(
+                                "{} WebSocket URL (blank to poll over HTTP): ",
+                                self.temp.temp_selected_chain
+                            ));
+                            ui.text_edit_singleline(&mut inputs.ws_url);
+                        }
+
+                        if let Some(info) = self.registry.get(&self.temp.temp_selected_chain) {
+                            ui.collapsing(
+                                format!(
+                                    "Known {} contracts (chain id {})",
+                                    self.temp.temp_selected_chain, info.chain_id
+                                ),
+                                |ui| {
+                                    for dex in &info.dexes {
+                                        ui.horizontal(|ui| {
+                                            if ui.button("Use").clicked() {
+                                                self.temp.use_known_dex(dex);
+                                            }
+                                            ui.label(format!(
+                                                "{} ({}): factory {}",
+                                                dex.name, dex.protocol, dex.factory
+                                            ));
+                                        });
+                                        for (label, address) in
+                                            [("router", &dex.router), ("quoter", &dex.quoter)]
+                                        {
+                                            if !address.is_empty() {
+                                                ui.label(format!("    {} {}", label, address));
+                                            }
+                                        }
+                                    }
+                                    ui.horizontal(|ui| {
+                                        if ui.button("Use").clicked() {
+                                            self.temp.use_chain_info(info);
+                                        }
+                                        ui.label(format!(
+                                            "Wrapped native {}, explorer {}",
+                                            info.wrapped_native, info.explorer_url
+                                        ));
+                                    });
+                                },
+                            );
+                        }
+
                         ui.label("Exchange Factory Contract Address :");
                         ui.text_edit_singleline(&mut self.temp.temp_contract_address);
 
+                        ui.label(
+                            "V2 Factories (one address per line, optionally followed by its fee, 3000 for 0.3%): ",
+                        );
+                        ui.text_edit_multiline(&mut self.temp.temp_v2_factories);
+
                         ui.label("Address of the token to arbitrage: ");
                         ui.text_edit_singleline(&mut self.temp.temp_token_address_input_master);
 
-                        ui.label("Pairs to check Master Token price against");
+                        ui.label("Tokens to route through (one address per line): ");
+                        ui.text_edit_multiline(&mut self.temp.temp_tokens);
+
+                        ui.horizontal(|ui| {
+                            ui.label("Max Hops: ");
+                            ui.add(
+                                egui::TextEdit::singleline(&mut self.temp.temp_max_hops)
+                                    .desired_width(90.0),
+                            );
+                        });
+
+                        ui.label(
+                            "Wrapped Native Token Address (blank if it is the master token): ",
+                        );
+                        ui.text_edit_singleline(&mut self.temp.temp_wrapped_native_address);
+
+                        ui.label("Block Explorer URL (blank to log bare transaction hashes): ");
+                        ui.text_edit_singleline(&mut self.temp.temp_explorer_url);
+
+                        ui.label("Arbitrage Executor Contract Address (blank to only simulate): ");
+                        ui.text_edit_singleline(&mut self.temp.temp_executor_address);
+
+                        ui.label("Flash Loan Pool Address (must hold the master token): ");
+                        ui.text_edit_singleline(&mut self.temp.temp_flash_pool_address);
+
+                        ui.label(
+                            "Router Address (approved once for the trade amount, blank for none): ",
+                        );
+                        ui.text_edit_singleline(&mut self.temp.temp_router_address);
+
+                        egui::ComboBox::from_label("Router Token Approval")
+                            .selected_text(self.temp.temp_approval_policy.to_string())
+                            .show_ui(ui, |ui| {
+                                for policy in [ApprovalPolicy::Exact, ApprovalPolicy::Infinite] {
+                                    ui.selectable_value(
+                                        &mut self.temp.temp_approval_policy,
+                                        policy,
+                                        policy.to_string(),
+                                    );
+                                }
+                            });
+
+                        ui.checkbox(
+                            &mut self.temp.temp_dry_run,
+                            "Dry run (simulate transactions, never broadcast)",
+                        );
+
+                        ui.label("Bundle Relay URL (blank to broadcast publicly): ");
                         ui.horizontal(|ui| {
-                            ui.label("Token Address 1: ");
-                            ui.text_edit_singleline(&mut self.temp.temp_token_address_input_1);
-                            ui.label("Token Address 2: ");
-                            ui.text_edit_singleline(&mut self.temp.temp_token_address_input_2);
+                            ui.text_edit_singleline(&mut self.temp.temp_relay_url);
+                            ui.label("Target Blocks: ");
+                            ui.add(
+                                egui::TextEdit::singleline(&mut self.temp.temp_relay_target_blocks)
+                                    .desired_width(90.0),
+                            );
                         });
 
                         ui.horizontal(|ui| {
@@ -317,35 +1146,119 @@ impl eframe::App for App {
                         });
 
                         ui.horizontal(|ui| {
-                            ui.label("Wallet Private Key: ");
-                            ui.text_edit_singleline(&mut self.temp.temp_private_key_input);
+                            ui.label("Poll Interval (seconds, 0 for every block): ");
+                            ui.add(
+                                egui::TextEdit::singleline(&mut self.temp.temp_poll_interval_secs)
+                                    .desired_width(90.0),
+                            );
+                        });
+
+                        ui.horizontal(|ui| {
+                            egui::ComboBox::from_label("Fee Strategy")
+                                .selected_text(self.temp.temp_fee_strategy.to_string())
+                                .show_ui(ui, |ui| {
+                                    for strategy in [
+                                        FeeStrategy::Fixed,
+                                        FeeStrategy::Percentile,
+                                        FeeStrategy::Aggressive,
+                                    ] {
+                                        ui.selectable_value(
+                                            &mut self.temp.temp_fee_strategy,
+                                            strategy,
+                                            strategy.to_string(),
+                                        );
+                                    }
+                                });
+                            ui.label("Priority Fee (gwei, Fixed): ");
+                            ui.add(
+                                egui::TextEdit::singleline(&mut self.temp.temp_priority_fee_gwei)
+                                    .desired_width(90.0),
+                            );
+                            ui.label("Max Fee Cap (gwei, 0 for none): ");
+                            ui.add(
+                                egui::TextEdit::singleline(&mut self.temp.temp_max_fee_gwei)
+                                    .desired_width(90.0),
+                            );
+                        });
+
+                        ui.horizontal(|ui| {
+                            ui.label("Import Private Key: ");
+                            ui.add(
+                                egui::TextEdit::singleline(&mut self.temp.temp_private_key_input)
+                                    .password(true),
+                            );
+                            ui.label("Keystore Password: ");
+                            ui.add(
+                                egui::TextEdit::singleline(&mut self.temp.temp_keystore_password)
+                                    .password(true),
+                            );
                         });
 
                         if ui.button("Save").clicked() {
+                            self.show_gas_limit_error = false;
+                            self.show_slippage_threshhold_error = false;
+                            self.show_minimum_profit_error = false;
+                            self.show_amount_to_trade_error = false;
+                            self.show_poll_interval_error = false;
+                            self.show_max_hops_error = false;
+                            self.show_rpc_url_error = false;
+                            self.show_chain_error = false;
+                            self.show_fee_error = false;
+                            self.show_relay_error = false;
+                            self.show_v2_factories_error = false;
                             if !self.temp.temp_private_key_input.is_empty() {
-                                self.private_key_input = self.temp.temp_private_key_input.clone();
-                                let pub_key = priv_key_to_pub_key(&self.private_key_input);
-                                if pub_key.is_ok() {
-                                    self.public_key = pub_key.unwrap();
-                                } else {
-                                    self.invalid_private_key = true;
-                                }
+                                import_wallet_key(self);
                             }
                             if !self.temp.temp_token_address_input_master.is_empty() {
                                 self.token_address_input_master =
                                     self.temp.temp_token_address_input_master.clone();
                             }
-                            if !self.temp.temp_token_address_input_1.is_empty() {
-                                self.token_address_input_1 =
-                                    self.temp.temp_token_address_input_1.clone();
-                            }
-                            if !self.temp.temp_token_address_input_2.is_empty() {
-                                self.token_address_input_2 =
-                                    self.temp.temp_token_address_input_2.clone();
+                            self.tokens = self
+                                .temp
+                                .temp_tokens
+                                .lines()
+                                .map(str::trim)
+                                .filter(|token| !token.is_empty())
+                                .map(String::from)
+                                .collect();
+                            if !self.temp.temp_max_hops.is_empty() {
+                                match self.temp.temp_max_hops.parse::<usize>() {
+                                    Ok(num) if num >= 2 => {
+                                        self.max_hops = num;
+                                    }
+                                    _ => {
+                                        self.show_max_hops_error = true;
+                                    }
+                                }
                             }
                             if !self.temp.temp_contract_address.is_empty() {
                                 self.contract_address = self.temp.temp_contract_address.clone();
                             }
+                            match parse_v2_factories(&self.temp.temp_v2_factories) {
+                                Some(factories) => self.v2_factories = factories,
+                                None => self.show_v2_factories_error = true,
+                            }
+                            self.wrapped_native_address =
+                                self.temp.temp_wrapped_native_address.trim().to_string();
+                            self.explorer_url = self.temp.temp_explorer_url.trim().to_string();
+                            self.executor_address =
+                                self.temp.temp_executor_address.trim().to_string();
+                            self.flash_pool_address =
+                                self.temp.temp_flash_pool_address.trim().to_string();
+                            self.router_address = self.temp.temp_router_address.trim().to_string();
+                            self.approval_policy = self.temp.temp_approval_policy;
+                            self.dry_run = self.temp.temp_dry_run;
+                            self.relay_url = self.temp.temp_relay_url.trim().to_string();
+                            if !self.temp.temp_relay_target_blocks.is_empty() {
+                                match self.temp.temp_relay_target_blocks.parse::<u64>() {
+                                    Ok(num) if num > 0 => {
+                                        self.relay_target_blocks = num;
+                                    }
+                                    _ => {
+                                        self.show_relay_error = true;
+                                    }
+                                }
+                            }
 
                             if !self.temp.temp_gas_limit.is_empty() {
                                 match self.temp.temp_gas_limit.parse::<u64>() {
@@ -387,34 +1300,113 @@ impl eframe::App for App {
                                     }
                                 }
                             }
+                            if !self.temp.temp_poll_interval_secs.is_empty() {
+                                match self.temp.temp_poll_interval_secs.parse::<u64>() {
+                                    Ok(num) => {
+                                        self.poll_interval_secs = num;
+                                    }
+                                    Err(_) => {
+                                        self.show_poll_interval_error = true;
+                                    }
+                                }
+                            }
+
+                            self.fee_strategy = self.temp.temp_fee_strategy;
+                            match (
+                                self.temp.temp_priority_fee_gwei.trim().parse::<f64>(),
+                                self.temp.temp_max_fee_gwei.trim().parse::<f64>(),
+                            ) {
+                                (Ok(priority_fee), Ok(max_fee)) => {
+                                    self.priority_fee_gwei = priority_fee;
+                                    self.max_fee_gwei = max_fee;
+                                }
+                                _ => {
+                                    self.show_fee_error = true;
+                                }
+                            }
+
+                            let mut chains = BTreeMap::new();
+                            for (chain, inputs) in &self.temp.temp_chains {
+                                match inputs.parse() {
+                                    Some(definition)
+                                        if definition
+                                            .rpc_urls
+                                            .iter()
+                                            .all(|url| Http::new(url).is_ok())
+                                            && (definition.ws_url.is_empty()
+                                                || definition.ws_url.starts_with("ws://")
+                                                || definition.ws_url.starts_with("wss://")) =>
+                                    {
+                                        chains.insert(chain.clone(), definition);
+                                    }
+                                    Some(_) => self.show_rpc_url_error = true,
+                                    None => self.show_chain_error = true,
+                                }
+                            }
+                            if !self.show_rpc_url_error && !self.show_chain_error {
+                                self.chains = chains;
+                            }
 
-                            self.selected_chain = self.temp.temp_selected_chain;
+                            self.selected_chain = self.temp.temp_selected_chain.clone();
 
                             let config = Config {
+                                version: CONFIG_VERSION,
                                 chain: self.selected_chain.clone(),
+                                chains: self.chains.clone(),
                                 contract_address: self.contract_address.clone(),
-                                private_key: self.private_key_input.clone(),
-                                public_key: self.public_key.clone(),
+                                v2_factories: self.v2_factories.clone(),
+                                keystore_path: self.keystore_path.clone(),
+                                public_key: self.public_key,
                                 token_address_master: self.token_address_input_master.clone(),
-                                token_address_1: self.token_address_input_1.clone(),
-                                token_address_2: self.token_address_input_2.clone(),
-                                gas_limit: self.gas_limit.clone(),
-                                slippage_threshhold: self.slippage_threshhold.clone(),
-                                minimum_profit: self.minimum_profit.clone(),
-                                amount_to_trade: self.amount_to_trade.clone(),
+                                tokens: self.tokens.clone(),
+                                max_hops: self.max_hops,
+                                gas_limit: self.gas_limit,
+                                slippage_threshhold: self.slippage_threshhold,
+                                minimum_profit: self.minimum_profit,
+                                amount_to_trade: self.amount_to_trade,
+                                poll_interval_secs: self.poll_interval_secs,
+                                fee_strategy: self.fee_strategy,
+                                priority_fee_gwei: self.priority_fee_gwei,
+                                max_fee_gwei: self.max_fee_gwei,
+                                wrapped_native_address: self.wrapped_native_address.clone(),
+                                explorer_url: self.explorer_url.clone(),
+                                executor_address: self.executor_address.clone(),
+                                flash_pool_address: self.flash_pool_address.clone(),
+                                router_address: self.router_address.clone(),
+                                approval_policy: self.approval_policy,
+                                dry_run: self.dry_run,
+                                relay_url: self.relay_url.clone(),
+                                relay_target_blocks: self.relay_target_blocks,
                             };
-                            write_config(config);
+                            let parse_failed = self.show_gas_limit_error
+                                || self.show_slippage_threshhold_error
+                                || self.show_minimum_profit_error
+                                || self.show_amount_to_trade_error
+                                || self.show_poll_interval_error
+                                || self.show_max_hops_error
+                                || self.show_rpc_url_error
+                                || self.show_chain_error
+                                || self.show_fee_error
+                                || self.show_relay_error
+                                || self.show_v2_factories_error;
+                            let errors = config.validate();
+                            if !errors.is_empty() {
+                                self.config_error = Some(field_errors(CONFIG_PATH, &errors));
+                            }
+                            // Nothing is saved while a field does not parse or
+                            // validate, nor over a plaintext key that has not
+                            // been moved into a keystore yet.
+                            if errors.is_empty()
+                                && !parse_failed
+                                && self.temp.temp_private_key_input.is_empty()
+                            {
+                                if let Err(err) = write_config(config) {
+                                    self.config_error = Some(err.to_string());
+                                }
+                            }
                         }
                     }
                 });
-                if self.invalid_address_popup {
-                    egui::Window::new("Invalid Address").show(ctx, |ui| {
-                        ui.label("One or more addresses are invalid.");
-                        if ui.button("Close").clicked() {
-                            self.invalid_address_popup = false;
-                        }
-                    });
-                }
 
                 if self.show_gas_limit_error {
                     egui::Window::new("Invalid Gas Number").show(ctx, |ui| {
@@ -448,421 +1440,1571 @@ impl eframe::App for App {
                         }
                     });
                 }
-                if self.invalid_private_key {
-                    egui::Window::new("Invalid Private Key").show(ctx, |ui| {
-                        ui.label("Provided Private Key is not valid");
+                if self.show_poll_interval_error {
+                    egui::Window::new("Invalid Poll Interval").show(ctx, |ui| {
+                        ui.label("Poll Interval must be a whole number of seconds");
                         if ui.button("Close").clicked() {
-                            self.invalid_private_key = false;
+                            self.show_poll_interval_error = false;
                         }
                     });
                 }
-            });
-        });
-    }
-}
-
-fn get_config() -> Result<Config, Error> {
-    let data = fs::read_to_string("config.json")?;
-    let config: Config = serde_json::from_str(&data)?;
-
-    Ok(config)
-}
-
-fn write_config(config: Config) {
-    let json_data = serde_json::to_string_pretty(&config).expect("Failed to serialize to JSON");
-    let mut file = File::create("config.json").expect("Failed to open file");
-    file.write_all(json_data.as_bytes())
-        .expect("Failed to write data");
-}
-
-fn begin_arbitrage(app: &mut App) {
-    let config: Config = App::get_config();
-
-    let transport: Http = match config.chain {
-        Chain::Ethereum => web3::transports::http::Http::new( "http://127.0.0.1:8545"
-            /*"https://mainnet.infura.io/v3/f679762894d44f4e88b1a37fbf30282b"*/,
-        )
-        .unwrap(),
-        Chain::Polygon => {
-            web3::transports::http::Http::new("https://polygon.blockpi.network/v1/rpc/public")
-                .unwrap()
-        }
-        Chain::Binance => {
-            web3::transports::http::Http::new("https://bsc-dataseed.bnbchain.org").unwrap()
-        }
-    };
-    let web3: Web3<Http> = web3::Web3::new(transport);
-
-    let valid_bools: HashMap<&String, bool> = check_valid_addresses(vec![
-        &config.contract_address,
-        &config.token_address_master,
-        &config.token_address_1,
-        &config.token_address_2,
-    ]);
-
-    if valid_bools.values().any(|&val| !val) {
-        app.invalid_address_popup = true;
-        return;
-    }
-
-    tokio::spawn(async move {
-        match arbitrage(config, web3).await {
-            Ok(_) => println!("Arbitrage completed successfully"),
-            Err(err) => eprintln!("Arbitrage failed: {}", err),
-        }
-    });
-}
-
-async fn arbitrage(config: Config, web3: Web3<Http>) -> web3::Result<()> {
-    let (price_pair_1_f64, price_pair_1, pool_address_1) =
-        get_price_and_pool_address(&web3, &config, 1).await?;
-    let (price_pair_2_f64, price_pair_2, pool_address_2) =
-        get_price_and_pool_address(&web3, &config, 2).await?;
-
-    let profitable = is_arbitrage_profitable(
-        &web3,
-        pool_address_1,
-        pool_address_2,
-        price_pair_1,
-        price_pair_2,
-        price_pair_1_f64,
-        price_pair_2_f64,
-        &config,
-    )
-    .await;
+                if self.show_max_hops_error {
+                    egui::Window::new("Invalid Max Hops").show(ctx, |ui| {
+                        ui.label("Max Hops must be a whole number of at least 2");
+                        if ui.button("Close").clicked() {
+                            self.show_max_hops_error = false;
+                        }
+                    });
+                }
+                if self.show_rpc_url_error {
+                    egui::Window::new("Invalid RPC URL").show(ctx, |ui| {
+                        ui.label("One or more RPC URLs could not be parsed");
+                        if ui.button("Close").clicked() {
+                            self.show_rpc_url_error = false;
+                        }
+                    });
+                }
+                if self.show_chain_error {
+                    egui::Window::new("Invalid Chain").show(ctx, |ui| {
+                        ui.label(
+                            "Chain ID must be a whole number and Block Time a number of seconds",
+                        );
+                        if ui.button("Close").clicked() {
+                            self.show_chain_error = false;
+                        }
+                    });
+                }
+                if self.show_v2_factories_error {
+                    egui::Window::new("Invalid V2 Factory").show(ctx, |ui| {
+                        ui.label("Each V2 factory line must be an address and at most one fee");
+                        if ui.button("Close").clicked() {
+                            self.show_v2_factories_error = false;
+                        }
+                    });
+                }
+                if self.show_relay_error {
+                    egui::Window::new("Invalid Target Blocks").show(ctx, |ui| {
+                        ui.label("Target Blocks must be a whole number above 0");
+                        if ui.button("Close").clicked() {
+                            self.show_relay_error = false;
+                        }
+                    });
+                }
+                if self.show_fee_error {
+                    egui::Window::new("Invalid Fee").show(ctx, |ui| {
+                        ui.label("Priority fee and max fee cap must be numbers of gwei");
+                        if ui.button("Close").clicked() {
+                            self.show_fee_error = false;
+                        }
+                    });
+                }
+                if let Some(err) = self.keystore_error.clone() {
+                    egui::Window::new("Keystore").show(ctx, |ui| {
+                        ui.label(err);
+                        if ui.button("Close").clicked() {
+                            self.keystore_error = None;
+                        }
+                    });
+                }
+                if let Some(err) = self.registry_error.clone() {
+                    egui::Window::new("Registry").show(ctx, |ui| {
+                        ui.label(err);
+                        if ui.button("Close").clicked() {
+                            self.registry_error = None;
+                        }
+                    });
+                }
+                if let Some(err) = self.config_error.clone() {
+                    egui::Window::new("Config").show(ctx, |ui| {
+                        ui.label(err);
+                        if ui.button("Close").clicked() {
+                            self.config_error = None;
+                        }
+                    });
+                }
+                if self.invalid_private_key {
+                    egui::Window::new("Invalid Private Key").show(ctx, |ui| {
+                        ui.label("Provided Private Key is not valid");
+                        if ui.button("Close").clicked() {
+                            self.invalid_private_key = false;
+                        }
+                    });
+                }
+            });
+        });
+    }
+}
 
-    //let tx_hash;
-    if profitable {
-        //tx_hash = execute_trade(&web3, &config, pool_address_1, price_pair_1).await;
-    } else {
-        println!("Exiting: Profit below threshold");
-        return Ok(());
+fn get_config() -> error::Result<Config> {
+    get_config_from(CONFIG_PATH)
+}
+
+fn get_config_from(path: &str) -> error::Result<Config> {
+    let invalid = |e: &dyn fmt::Display| Error::Config(format!("Failed to load {}: {}", path, e));
+    let data = fs::read_to_string(path).map_err(|e| invalid(&e))?;
+    let mut value: serde_json::Value = serde_json::from_str(&data).map_err(|e| invalid(&e))?;
+    if legacy_private_key(&value).is_some() {
+        eprintln!(
+            "{} holds a plaintext private_key, move it into a keystore with `arbitrage-bot import-key`",
+            path
+        );
     }
+    let version = migrate_config(&mut value)?;
+    if version < CONFIG_VERSION {
+        println!(
+            "{} migrated from version {} to {}, it is rewritten on the next save",
+            path, version, CONFIG_VERSION
+        );
+    }
+    let config: Config = serde_json::from_value(value).map_err(|e| invalid(&e))?;
 
-    // println!("Transaction 1 Hash: {:#?}", tx_hash);
+    Ok(config)
+}
 
-    Ok(())
+/// Loads the config at `path` and refuses it unless it validates.
+fn load_config(path: &str) -> error::Result<Config> {
+    let config = get_config_from(path)?;
+    let errors = config.validate();
+    if !errors.is_empty() {
+        return Err(Error::Config(field_errors(path, &errors)));
+    }
+    Ok(config)
 }
 
-async fn is_arbitrage_profitable(
-    web3: &Web3<Http>,
-    pool_address_a: H160,
-    pool_address_b: H160,
-    price_pair_1: U256,
-    price_pair_2: U256,
-    price_a_eth: f64,
-    price_b_eth: f64,
-    config: &Config,
-) -> bool {
-    let mut cost_b_to_a: f64;
-    let mut cost_a_to_b: f64;
+fn field_errors(path: &str, errors: &[validation::FieldError]) -> String {
+    let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+    format!("{} is invalid:\n{}", path, errors.join("\n"))
+}
+
+/// Upgrades from version `i` to `i + 1`, applied in order up to
+/// `CONFIG_VERSION`.
+const CONFIG_MIGRATIONS: [fn(&mut serde_json::Map<String, serde_json::Value>);
+    CONFIG_VERSION as usize] = [upgrade_token_pairs, upgrade_chains];
+
+/// Brings a config file's JSON up to `CONFIG_VERSION`, returning the
+/// version it was written with.
+fn migrate_config(value: &mut serde_json::Value) -> error::Result<u32> {
+    let object = value
+        .as_object_mut()
+        .ok_or_else(|| Error::Config("config must be a JSON object".into()))?;
+    let version = match object.get("version") {
+        None => 0,
+        Some(version) => version
+            .as_u64()
+            .and_then(|version| u32::try_from(version).ok())
+            .ok_or_else(|| Error::Config("version must be a number".into()))?,
+    };
+    if version > CONFIG_VERSION {
+        return Err(Error::Config(format!(
+            "version {} is newer than this bot understands ({})",
+            version, CONFIG_VERSION
+        )));
+    }
+
+    for migration in &CONFIG_MIGRATIONS[version as usize..] {
+        migration(object);
+    }
+    object.insert("version".into(), CONFIG_VERSION.into());
+    Ok(version)
+}
+
+/// Version 0 named exactly two tokens as `token_address_1` and
+/// `token_address_2`; they become the `tokens` list.
+fn upgrade_token_pairs(object: &mut serde_json::Map<String, serde_json::Value>) {
+    let legacy: Vec<serde_json::Value> = ["token_address_1", "token_address_2"]
+        .iter()
+        .filter_map(|key| object.remove(*key))
+        .filter(|token| token.as_str().is_some_and(|token| !token.is_empty()))
+        .collect();
+    if !legacy.is_empty() && !object.contains_key("tokens") {
+        object.insert("tokens".into(), serde_json::Value::Array(legacy));
+    }
+}
 
-    if let Ok(transaction_fee_a_to_b) =
-        estimate_swap_fee(web3, &config, pool_address_a, price_pair_1).await
+/// Version 1 ran on a fixed set of chains, with `rpc_urls` and `ws_urls`
+/// keyed by them; they move into the `chains` definitions, and a chain
+/// without RPC URLs keeps its default ones.
+fn upgrade_chains(object: &mut serde_json::Map<String, serde_json::Value>) {
+    let mut chains = default_chains();
+    if let Some(rpc_urls) = object
+        .remove("rpc_urls")
+        .and_then(|value| serde_json::from_value::<BTreeMap<Chain, Vec<String>>>(value).ok())
     {
-        let fee_multiplier_a_to_b = 1.0 - (transaction_fee_a_to_b / 100.0);
+        for (chain, urls) in rpc_urls {
+            match chains.get_mut(&chain) {
+                Some(chain) if !urls.is_empty() => chain.rpc_urls = urls,
+                _ => {}
+            }
+        }
+    }
+    if let Some(ws_urls) = object
+        .remove("ws_urls")
+        .and_then(|value| serde_json::from_value::<BTreeMap<Chain, String>>(value).ok())
+    {
+        for (chain, url) in ws_urls {
+            if let Some(chain) = chains.get_mut(&chain) {
+                chain.ws_url = url;
+            }
+        }
+    }
+    if !object.contains_key("chains") {
+        if let Ok(chains) = serde_json::to_value(chains) {
+            object.insert("chains".into(), chains);
+        }
+    }
+}
 
-        cost_a_to_b =
-            price_a_eth * (price_b_eth * fee_multiplier_a_to_b).recip() * fee_multiplier_a_to_b;
-        cost_a_to_b *= 1.0 + config.slippage_threshhold / 100.0;
-        cost_a_to_b += config.gas_limit as f64;
-    } else {
-        return false;
+/// `private_key` as written by versions that stored it unencrypted.
+fn plaintext_private_key(path: &str) -> Option<String> {
+    let value: serde_json::Value = serde_json::from_str(&fs::read_to_string(path).ok()?).ok()?;
+    legacy_private_key(&value)
+}
+
+/// The plaintext `private_key` of a config, unless it is missing or the
+/// all-zero placeholder older versions shipped with.
+fn legacy_private_key(config: &serde_json::Value) -> Option<String> {
+    config
+        .get("private_key")?
+        .as_str()
+        .filter(|key| {
+            key.trim()
+                .trim_start_matches("0x")
+                .chars()
+                .any(|c| c != '0')
+        })
+        .map(String::from)
+}
+
+fn write_config(config: Config) -> error::Result<()> {
+    write_config_to(CONFIG_PATH, config)
+}
+
+fn write_config_to(path: &str, config: Config) -> error::Result<()> {
+    let failed = |e: &dyn fmt::Display| Error::Config(format!("Failed to write {}: {}", path, e));
+    let json_data = serde_json::to_string_pretty(&config).map_err(|e| failed(&e))?;
+    let mut file = File::create(path).map_err(|e| failed(&e))?;
+    file.write_all(json_data.as_bytes()).map_err(|e| failed(&e))
+}
+
+fn begin_arbitrage(app: &mut App) {
+    if let Some(task) = &app.arbitrage_task {
+        if !task.handle.is_finished() {
+            return;
+        }
+    }
+
+    let config = match load_config(CONFIG_PATH) {
+        Ok(config) => config,
+        Err(err) => {
+            app.config_error = Some(err.to_string());
+            return;
+        }
+    };
+
+    if !config.keystore_path.is_empty() && app.wallet_key.is_none() {
+        match keystore::unlock(&config.keystore_path, &app.unlock_password) {
+            Ok(key) => app.wallet_key = Some(key),
+            Err(err) => {
+                app.keystore_error = Some(err.to_string());
+                return;
+            }
+        }
+        app.unlock_password.clear();
+    }
+    let wallet_key = app.wallet_key;
+
+    let (stop_tx, stop_rx) = watch::channel(false);
+    let status = app.arbitrage_status.clone();
+    let handle = tokio::spawn(async move {
+        match connect(&config).await {
+            Ok(web3) => run_arbitrage_loop(config, web3, wallet_key, stop_rx, status).await,
+            Err(err) => {
+                eprintln!("Not starting: {}", err);
+                let mut status = status.lock().unwrap();
+                status.state = RunState::Stopped;
+                status.last_error = Some(err.to_string());
+            }
+        }
+    });
+
+    app.arbitrage_task = Some(ArbitrageTask { stop_tx, handle });
+}
+
+/// Encrypts the key typed into Settings into a keystore and keeps it
+/// unlocked for this session.
+fn import_wallet_key(app: &mut App) {
+    let private_key = app.temp.temp_private_key_input.trim().to_string();
+    if priv_key_to_pub_key(&private_key.trim_start_matches("0x").to_string()).is_err() {
+        app.invalid_private_key = true;
+        return;
+    }
+
+    match keystore::import_key(&private_key, &app.temp.temp_keystore_password, KEYSTORE_DIR)
+        .and_then(|(path, address)| {
+            Ok((
+                keystore::unlock(&path, &app.temp.temp_keystore_password)?,
+                path,
+                address,
+            ))
+        }) {
+        Ok((key, path, address)) => {
+            app.keystore_path = path;
+            app.public_key = address;
+            app.wallet_key = Some(key);
+            app.temp.temp_private_key_input.clear();
+            app.temp.temp_keystore_password.clear();
+        }
+        Err(err) => app.keystore_error = Some(err.to_string()),
+    }
+}
+
+fn stop_arbitrage(app: &mut App) {
+    if let Some(task) = app.arbitrage_task.take() {
+        let _ = task.stop_tx.send(true);
     }
+}
+
+async fn run_arbitrage_loop(
+    config: Config,
+    web3: Web3<FailoverHttp>,
+    wallet_key: Option<SecretKey>,
+    mut stop_rx: watch::Receiver<bool>,
+    status: Arc<Mutex<ArbitrageStatus>>,
+) {
+    let interval = config.poll_interval();
+    let mut last_block: Option<U64> = None;
+    let nonces = NonceManager::new(config.public_key);
 
-    if let Ok(transaction_fee_b_to_a) =
-        estimate_swap_fee(web3, &config, pool_address_b, price_pair_2).await
     {
-        let fee_multiplier_b_to_a = 1.0 - (transaction_fee_b_to_a / 100.0);
-        cost_b_to_a =
-            price_b_eth * (price_a_eth * fee_multiplier_b_to_a).recip() * fee_multiplier_b_to_a;
+        let mut status = status.lock().unwrap();
+        *status = ArbitrageStatus {
+            state: RunState::Running,
+            ..Default::default()
+        };
+    }
 
-        cost_b_to_a *= 1.0 + config.slippage_threshhold / 100.0;
-        cost_b_to_a += config.gas_limit as f64;
+    // Only real transactions need the wallet to pay for them.
+    let trading = wallet_key.is_some() && !config.dry_run;
+    match check_wallet(&config, &web3, trading).await {
+        Ok(balances) => {
+            let inventory = Inventory::new(balances);
+            println!("{}", inventory);
+            status.lock().unwrap().wallet = Some(inventory);
+        }
+        Err(err @ Error::InsufficientFunds(_)) => {
+            eprintln!("Not starting: {}", err);
+            let mut status = status.lock().unwrap();
+            status.state = RunState::Stopped;
+            status.last_error = Some(err.to_string());
+            return;
+        }
+        Err(err) => eprintln!("Could not read the wallet: {}", err),
+    }
+    let mut next_wallet_refresh = Instant::now() + WALLET_REFRESH_INTERVAL;
+
+    match check_decimals(&config, &web3).await {
+        Ok(()) => {}
+        Err(err @ Error::Config(_)) => {
+            eprintln!("Not starting: {}", err);
+            let mut status = status.lock().unwrap();
+            status.state = RunState::Stopped;
+            status.last_error = Some(err.to_string());
+            return;
+        }
+        Err(err) => eprintln!("Could not read token decimals: {}", err),
+    }
+
+    // One relay for the whole run, so every bundle is signed by the same
+    // searcher.
+    let relay = if config.relay_url.is_empty() {
+        None
     } else {
-        return false;
+        match load_auth_key(AUTH_KEY_PATH).and_then(|key| Relay::new(&config.relay_url, key)) {
+            Ok(relay) => {
+                println!(
+                    "Bundles go to {}, signed by {:?}",
+                    config.relay_url,
+                    relay.auth_address()
+                );
+                Some(relay)
+            }
+            Err(err) => {
+                eprintln!("Not starting: {}", err);
+                let mut status = status.lock().unwrap();
+                status.state = RunState::Stopped;
+                status.last_error = Some(err.to_string());
+                return;
+            }
+        }
+    };
+
+    if let (true, Some(wallet_key)) = (trading, wallet_key.as_ref()) {
+        if let Err(err) = approve_router(&config, &web3, wallet_key, &nonces).await {
+            eprintln!("Approving the router failed: {}", err);
+        }
     }
 
-    let profitable_a_to_b = cost_a_to_b < 1.0 && (1.0 - cost_a_to_b) >= config.minimum_profit;
-    let profitable_b_to_a = cost_b_to_a < 1.0 && (1.0 - cost_b_to_a) >= config.minimum_profit;
+    if config.dry_run {
+        let master = token_string_to_h160(&config.token_address_master).ok();
+        let starting_balance = match (&status.lock().unwrap().wallet, master) {
+            (Some(wallet), Some(master)) => wallet.start.of(master),
+            _ => {
+                eprintln!("Could not read the master balance, paper trading from 0");
+                0.0
+            }
+        };
+        println!(
+            "Dry run: nothing is broadcast, paper trading from {:.6} master tokens",
+            starting_balance
+        );
+        status.lock().unwrap().paper_ledger = Some(PaperLedger::new(starting_balance));
+    }
+    let chain_id = config
+        .chain_config()
+        .map(|chain| chain.chain_id)
+        .unwrap_or_default();
+    let mut watcher = match config.ws_url() {
+        Some(url) => match PoolWatcher::connect(url, chain_id).await {
+            Ok(watcher) => {
+                println!("Arbitrage loop started, subscribed to {}", url);
+                Some(watcher)
+            }
+            Err(err @ Error::Config(_)) => {
+                eprintln!("Not starting: {}", err);
+                let mut status = status.lock().unwrap();
+                status.state = RunState::Stopped;
+                status.last_error = Some(err.to_string());
+                return;
+            }
+            Err(err) => {
+                eprintln!("WebSocket connection to {} failed: {}", url, err);
+                None
+            }
+        },
+        None => None,
+    };
+    if watcher.is_none() {
+        println!("Arbitrage loop started, polling every {:?}", interval);
+    }
+
+    check_rpc_health(&web3).await;
+    let mut last_health_check = Instant::now();
 
-    profitable_a_to_b || profitable_b_to_a
+    // Discovered on the first evaluation that gets that far, then kept for
+    // the rest of the run.
+    let mut universe = None;
+
+    loop {
+        if last_health_check.elapsed() >= RPC_HEALTH_CHECK_INTERVAL {
+            tokio::select! {
+                _ = stop_rx.changed() => break,
+                _ = check_rpc_health(&web3) => last_health_check = Instant::now(),
+            }
+        }
+        if Instant::now() >= next_wallet_refresh {
+            tokio::select! {
+                _ = stop_rx.changed() => break,
+                result = check_wallet(&config, &web3, trading) => {
+                    next_wallet_refresh = Instant::now() + WALLET_REFRESH_INTERVAL;
+                    let mut status = status.lock().unwrap();
+                    match result {
+                        Ok(balances) => {
+                            let wallet = status.wallet.get_or_insert_with(|| Inventory::new(balances.clone()));
+                            wallet.update(balances);
+                            println!("{}", wallet);
+                        }
+                        Err(err @ Error::InsufficientFunds(_)) => {
+                            eprintln!("Stopping: {}", err);
+                            status.last_error = Some(err.to_string());
+                            break;
+                        }
+                        Err(err) => eprintln!("Could not read the wallet: {}", err),
+                    }
+                }
+            }
+        }
+
+        tokio::select! {
+            _ = stop_rx.changed() => break,
+            result = evaluate_new_block(&config, &web3, &mut universe, wallet_key.as_ref(), &nonces, relay.as_ref(), last_block) => {
+                let watched_pools = {
+                    let mut status = status.lock().unwrap();
+                    match result {
+                        Ok(Some((block, evaluation))) => {
+                            last_block = Some(block);
+                            status.evaluations += 1;
+                            status.last_block = Some(block);
+                            status.last_error = None;
+                            status.dashboard.record_prices(block, evaluation.prices);
+                            if evaluation.profit.is_profitable(config.minimum_profit) {
+                                let outcome = match (&evaluation.paper_trade, evaluation.tx_hash) {
+                                    (Some(trade), _) => Outcome::Paper(trade.clone()),
+                                    (None, Some(tx_hash)) => {
+                                        next_wallet_refresh = Instant::now();
+                                        Outcome::Sent(tx_hash)
+                                    }
+                                    (None, None) => Outcome::Detected,
+                                };
+                                status.dashboard.record_opportunity(OpportunityRecord {
+                                    block,
+                                    profit: evaluation.profit.clone(),
+                                    outcome,
+                                });
+                            }
+                            status.last_profit = Some(evaluation.profit);
+                            if let (Some(ledger), Some(trade)) =
+                                (status.paper_ledger.as_mut(), evaluation.paper_trade)
+                            {
+                                ledger.record(block, trade);
+                                println!("{}", ledger);
+                            }
+                            Some(evaluation.pools)
+                        }
+                        Ok(None) => None,
+                        Err(err) => {
+                            eprintln!("Arbitrage evaluation failed: {}", err);
+                            status.last_error = Some(err.to_string());
+                            None
+                        }
+                    }
+                };
+
+                if let (Some(pool_watcher), Some(pools)) = (watcher.as_mut(), watched_pools) {
+                    if let Err(err) = pool_watcher.watch(&pools).await {
+                        eprintln!("Swap subscription failed, polling instead: {}", err);
+                        watcher = None;
+                    }
+                }
+            }
+        }
+
+        let change = match watcher.as_mut() {
+            Some(pool_watcher) => tokio::select! {
+                _ = stop_rx.changed() => break,
+                change = pool_watcher.next_change() => change,
+            },
+            None => tokio::select! {
+                _ = stop_rx.changed() => break,
+                _ = tokio::time::sleep(interval) => continue,
+            },
+        };
+        if let Err(err) = change {
+            eprintln!("WebSocket subscription failed, polling instead: {}", err);
+            watcher = None;
+        }
+    }
+
+    let mut status = status.lock().unwrap();
+    status.state = RunState::Stopped;
+    println!("Arbitrage loop stopped: {}", status);
+    if let Some(ledger) = &status.paper_ledger {
+        println!("{}", ledger);
+    }
 }
 
-async fn execute_trade(
-    web3: &Web3<Http>,
+/// Reads the wallet's balances of the native token and every configured
+/// token. With `trading` set it also has to afford one transaction.
+async fn check_wallet(
     config: &Config,
-    pool_address: Address,
-    price: f64,
-) -> Result<H256, web3::Error> {
-    let prvk = SecretKey::from_str(&config.private_key).unwrap();
-
-    let pool_file = File::open("./pool_abi.json").unwrap();
-    let pool_abi = web3::ethabi::Contract::load(pool_file).unwrap();
-    let pool_contract = Contract::new(web3.eth(), pool_address, pool_abi);
-
-    let amount: U256 = f64_to_u256(config.amount_to_trade);
-
-    let result: Result<H256, web3::Error> = pool_contract
-        .signed_call(
-            "swap",
-            (
-                Token::Address(config.public_key),
-                Token::Bool(true),
-                Token::Int(amount),
-                Token::Uint(calculate_sqrt_price_limit(price).into()),
-                Token::Bytes(Vec::new()),
-            ),
-            web3::contract::Options::default(),
-            &prvk,
-        )
-        .await
-        .map_err(|e| web3::Error::InvalidResponse(format!("Failed to send: {}", e)));
+    web3: &Web3<FailoverHttp>,
+    trading: bool,
+) -> error::Result<Balances> {
+    let tokens = token_universe(config)?;
+    let balances = fetch_balances(
+        web3,
+        config.public_key,
+        &config.chain_config()?.native_symbol,
+        &tokens,
+        &token_labels(&tokens),
+    )
+    .await?;
+    if trading {
+        let fee = fetch_fee_quote(web3, &config.fee_settings()?).await?;
+        check_funds(
+            &balances,
+            tokens[0],
+            config.amount_to_trade,
+            fee.max_cost_in_native(config.gas_limit),
+        )?;
+    }
+    Ok(balances)
+}
 
-    match &result {
-        Ok(tx_hash) => {
-            let bytes = tx_hash.0;
-            println!("Transaction hash as bytes: {:?}", bytes);
+/// Validates the config against the decimals its tokens report on chain.
+async fn check_decimals(config: &Config, web3: &Web3<FailoverHttp>) -> error::Result<()> {
+    let tokens = token_universe(config)?;
+    let decimals: HashMap<H160, u8> = tokens
+        .iter()
+        .copied()
+        .zip(
+            try_join_all(
+                tokens
+                    .iter()
+                    .map(|token| fetch_decimals_of_token(web3, *token)),
+            )
+            .await?,
+        )
+        .collect();
+    let errors = config.validate_decimals(&decimals);
+    if !errors.is_empty() {
+        return Err(Error::Config(field_errors("The config", &errors)));
+    }
+    Ok(())
+}
 
-            let hex = tx_hash.to_string();
-            println!("Transaction hash as hex: {}", hex);
-        }
-        Err(e) => {
-            println!("Error: {}", e);
+async fn check_rpc_health(web3: &Web3<FailoverHttp>) {
+    for endpoint in web3.transport().check_health().await {
+        match endpoint.latency {
+            Some(latency) => println!("RPC {}: {}ms", endpoint.url, latency.as_millis()),
+            None => println!("RPC {}: down", endpoint.url),
         }
     }
+}
+
+/// Runs one arbitrage pass if a block has been produced since `last_block`,
+/// returning the block that was evaluated and the outcome.
+async fn evaluate_new_block(
+    config: &Config,
+    web3: &Web3<FailoverHttp>,
+    universe: &mut Option<Universe>,
+    wallet_key: Option<&SecretKey>,
+    nonces: &NonceManager,
+    relay: Option<&Relay>,
+    last_block: Option<U64>,
+) -> error::Result<Option<(U64, Evaluation)>> {
+    let block = web3.eth().block_number().await?;
+    if last_block == Some(block) {
+        return Ok(None);
+    }
 
-    return result;
+    if let Some(wallet_key) = wallet_key {
+        if let Err(err) = replace_stuck_transactions(config, web3, wallet_key, nonces, block).await
+        {
+            eprintln!("Replacing stuck transactions failed: {}", err);
+        }
+    }
+    let universe = match universe {
+        Some(universe) => universe,
+        None => universe.insert(Universe::discover(config, web3).await?),
+    };
+    let evaluation = arbitrage(config, web3, universe, wallet_key, nonces, relay).await?;
+    Ok(Some((block, evaluation)))
 }
 
-async fn estimate_swap_fee(
-    web3: &Web3<Http>,
+/// Speeds up every transaction pending for `STUCK_TX_BLOCKS` blocks, and
+/// cancels it once it has been sped up `MAX_SPEED_UPS` times.
+async fn replace_stuck_transactions(
     config: &Config,
-    pool_address: Address,
-    current_sqrt_price: U256,
-) -> Result<f64, web3::Error> {
-    let prvk = SecretKey::from_str(&config.private_key).unwrap();
-    let pool_file = File::open("./pool_abi.json").unwrap();
-    let pool_abi = web3::ethabi::Contract::load(pool_file).unwrap();
-    let pool_contract = Contract::new(web3.eth(), pool_address, pool_abi);
-
-    let min_sqrt_ratio: U256 = U256::from_dec_str("4295128739").unwrap();
-    if current_sqrt_price <= min_sqrt_ratio {
-        return Err(web3::Error::InvalidResponse("Invalid sqrtPriceX96".into()));
-    }
-    let delta: U256 = U256::from(10);
-    let sqrt_price_limit_x96: U256 = current_sqrt_price.saturating_sub(delta);
-
-    let mut options = web3::contract::Options::default();
-    options.gas = Some(config.gas_limit.into());
-
-    let approval = approve_erc20(&web3, &config).await;
-
-    match approval {
-        Ok(_) => {
-            let result: Result<TransactionReceipt, web3::Error> = pool_contract
-                .signed_call_with_confirmations(
-                    "swap",
-                    (
-                        Token::Address(config.public_key),
-                        Token::Bool(true),
-                        Token::Int(f64_to_u256(config.amount_to_trade)),
-                        Token::Uint(sqrt_price_limit_x96),
-                        Token::Bytes(Vec::new()),
-                    ),
-                    options,
-                    1,
-                    &prvk,
-                )
+    web3: &Web3<FailoverHttp>,
+    wallet_key: &SecretKey,
+    nonces: &NonceManager,
+    block: U64,
+) -> error::Result<()> {
+    let stuck: Vec<_> = nonces
+        .prune(web3)
+        .await?
+        .into_iter()
+        .filter(|pending| pending.sent_at + STUCK_TX_BLOCKS <= block)
+        .collect();
+    if stuck.is_empty() {
+        return Ok(());
+    }
+
+    let settings = config.fee_settings()?;
+    let fee = fetch_fee_quote(web3, &settings).await?;
+    for pending in stuck {
+        let replaced = if pending.cancelled || pending.replacements < MAX_SPEED_UPS {
+            nonces
+                .speed_up(web3, pending.nonce, &fee, settings.cap(), wallet_key)
                 .await
-                .map_err(|e| web3::Error::InvalidResponse(format!("Failed to send: {}", e)));
+        } else {
+            nonces
+                .cancel(web3, pending.nonce, &fee, settings.cap(), wallet_key)
+                .await
+        };
+        match replaced {
+            Ok(tx_hash) => println!(
+                "Replaced {:?} at nonce {} with {} ({})",
+                pending.hash,
+                pending.nonce,
+                tx_link(&config.explorer_url, tx_hash),
+                fee
+            ),
+            Err(err) => eprintln!(
+                "Could not replace {:?} at nonce {}: {}",
+                pending.hash, pending.nonce, err
+            ),
+        }
+    }
+    Ok(())
+}
 
-            match &result {
-                Ok(tx_hash) => {
-                    println!("Transaction hash as hex: {:#?}", tx_hash);
-                }
-                Err(e) => {
-                    println!("Error: {}", e);
-                }
-            }
+async fn run_headless(config_path: &str) -> error::Result<()> {
+    let config: Config = load_config(config_path)?;
+    println!(
+        "Loaded config from {} (chain: {})",
+        config_path, config.chain
+    );
+
+    let wallet_key = if config.keystore_path.is_empty() {
+        println!("No keystore configured, opportunities are only simulated");
+        None
+    } else {
+        Some(prompt_wallet_key(&config)?)
+    };
+
+    let web3: Web3<FailoverHttp> = connect(&config).await?;
+    let (stop_tx, stop_rx) = watch::channel(false);
+    let status = Arc::new(Mutex::new(ArbitrageStatus::default()));
+
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            println!("Received Ctrl-C, stopping");
         }
-        Err(why) => {
-            web3::Error::InvalidResponse(format!("Failed to approve: {}", why));
+        let _ = stop_tx.send(true);
+    });
+
+    run_arbitrage_loop(config, web3, wallet_key, stop_rx, status).await;
+
+    Ok(())
+}
+
+/// Asks for the keystore password on the terminal and unlocks the wallet.
+fn prompt_wallet_key(config: &Config) -> error::Result<SecretKey> {
+    if config.keystore_path.is_empty() {
+        return Err(Error::Config("No keystore configured".into()));
+    }
+    let password =
+        rpassword::prompt_password(format!("Keystore password for {:?}: ", config.public_key))
+            .map_err(|e| Error::Config(format!("Failed to read password: {}", e)))?;
+    keystore::unlock(&config.keystore_path, &password)
+}
+
+/// Sets the allowance of the router, and of the executor that earlier
+/// versions approved, on every token of the config back to zero.
+async fn run_revoke_approvals(config_path: &str) -> error::Result<()> {
+    let config: Config = load_config(config_path)?;
+    let spenders = [&config.router_address, &config.executor_address]
+        .into_iter()
+        .filter(|address| !address.is_empty())
+        .map(|address| token_string_to_h160(address))
+        .collect::<error::Result<Vec<H160>>>()?;
+    if spenders.is_empty() {
+        return Err(Error::Config(
+            "No router_address or executor_address to revoke approvals of".into(),
+        ));
+    }
+    let wallet_key = prompt_wallet_key(&config)?;
+    let web3: Web3<FailoverHttp> = connect(&config).await?;
+    let nonces = NonceManager::new(config.public_key);
+    let approver = Approver {
+        web3: &web3,
+        nonces: &nonces,
+        key: &wallet_key,
+        fee: fetch_fee_quote(&web3, &config.fee_settings()?).await?,
+    };
+
+    for spender in spenders {
+        for token in token_universe(&config)? {
+            match approver.revoke(token, spender).await? {
+                Some(tx_hash) => println!(
+                    "Revoked {:?} on {:?}: {}",
+                    spender,
+                    token,
+                    tx_link(&config.explorer_url, tx_hash)
+                ),
+                None => println!("{:?} has no allowance on {:?}", spender, token),
+            }
         }
     }
+    Ok(())
+}
+
+/// Moves a wallet key into an encrypted keystore and points the config at
+/// it, dropping any plaintext `private_key` the config still held.
+fn run_import_key(config_path: &str) -> error::Result<()> {
+    let read_failed = |e: std::io::Error| Error::Config(format!("Failed to read input: {}", e));
+
+    let config: Config = get_config_from(config_path)?;
+    let plaintext_key = plaintext_private_key(config_path);
+
+    let prompt = match plaintext_key {
+        Some(_) => format!("Private key (blank to use the one in {}): ", config_path),
+        None => String::from("Private key: "),
+    };
+    let mut private_key = rpassword::prompt_password(prompt).map_err(read_failed)?;
+    if private_key.trim().is_empty() {
+        private_key = plaintext_key.ok_or_else(|| Error::Config("No private key given".into()))?;
+    }
+
+    let password = rpassword::prompt_password("Keystore password: ").map_err(read_failed)?;
+    let repeated = rpassword::prompt_password("Repeat password: ").map_err(read_failed)?;
+    if password != repeated {
+        return Err(Error::Config("Passwords do not match".into()));
+    }
 
-    return Ok(0.0);
+    let (keystore_path, address) = keystore::import_key(&private_key, &password, KEYSTORE_DIR)?;
+    println!("Key for {:?} encrypted into {}", address, keystore_path);
+    write_config_to(
+        config_path,
+        Config {
+            keystore_path,
+            public_key: address,
+            ..config
+        },
+    )
 }
 
-async fn approve_erc20(web3: &Web3<Http>, config: &Config) -> web3::Result<()> {
-    let prvk = SecretKey::from_str(&config.private_key).unwrap();
-    let erc20_file = File::open("./erc20_abi.json").unwrap();
-    let erc20_abi = web3::ethabi::Contract::load(erc20_file).unwrap();
+/// Recordings only hold V3 pools, so with V2 factories a backtest would
+/// search fewer routes than live trading does.
+fn refuse_v2_factories(config: &Config) -> error::Result<()> {
+    if config.v2_factories.is_empty() {
+        return Ok(());
+    }
+    Err(Error::Config(
+        "Backtests replay Uniswap V3 pools only, remove v2_factories from the config to record or backtest".into(),
+    ))
+}
 
-    let contract1 = Contract::new(
-        web3.eth(),
-        H160::from_str(config.token_address_1.as_str()).unwrap(),
-        erc20_abi.clone(),
+/// Replays a recording made by `record` with the strategy settings of the
+/// config and prints the outcome.
+fn run_backtest(
+    config_path: &str,
+    data_path: &str,
+    events_path: Option<&str>,
+) -> error::Result<()> {
+    let config: Config = load_config(config_path)?;
+    refuse_v2_factories(&config)?;
+    let mut data = backtest::load_data(data_path)?;
+    let mut events = backtest::decode_logs(&data.logs)?;
+    events.append(&mut data.events);
+    if let Some(events_path) = events_path {
+        events.extend(backtest::load_events(events_path)?);
+    }
+    println!(
+        "Replaying {} events over {} pools from block {}",
+        events.len(),
+        data.pools.len(),
+        data.block
     );
-    // let contract2 = Contract::new(
-    //     web3.eth(),
-    //     H160::from_str(config.token_address_2.as_str()).unwrap(),
-    //     erc20_abi.clone(),
-    // );
-
-    let approve_params = (
-        Token::Address(config.public_key),
-        Token::Uint(f64_to_u256(config.amount_to_trade)),
+
+    let flash_pool = if config.flash_pool_address.is_empty() {
+        None
+    } else {
+        Some(token_string_to_h160(&config.flash_pool_address)?)
+    };
+    let params = backtest::BacktestParams {
+        master: token_string_to_h160(&config.token_address_master)?,
+        amount_in: amount_to_trade_units(&config, data.master_decimals)?,
+        max_hops: config.max_hops,
+        minimum_profit: config.minimum_profit,
+        slippage_percent: config.slippage_threshhold,
+        flash_pool,
+        gas_cost_in_master: data.gas_cost_in_master,
+        labels: token_labels(&token_universe(&config)?),
+    };
+    let report = backtest::run_backtest(data, events, &params).map_err(Error::Simulation)?;
+    println!("{}", report);
+
+    Ok(())
+}
+
+/// Snapshots every V3 pool of the token universe and records their `Swap`,
+/// `Mint` and `Burn` logs for the next `blocks` blocks into `out_path`.
+async fn run_record(config_path: &str, blocks: u64, out_path: &str) -> error::Result<()> {
+    let config: Config = load_config(config_path)?;
+    refuse_v2_factories(&config)?;
+    let web3 = connect(&config).await?;
+
+    let fee_tiers = fee_tiers_for(&config, &web3).await?;
+    let tokens = token_universe(&config)?;
+    let pools = discover_token_pools(&web3, &config, &tokens, &fee_tiers).await?;
+    let addresses: Vec<Address> = pools
+        .iter()
+        .filter(|pool| pool.protocol == Protocol::UniswapV3)
+        .map(|pool| pool.address)
+        .collect();
+
+    let block = web3.eth().block_number().await?.as_u64();
+    let states =
+        try_join_all(addresses.iter().map(|address| {
+            PoolState::fetch(&web3, *address, RECORDED_TICK_BITMAP_WORDS_EACH_SIDE)
+        }))
+        .await?;
+
+    let master = tokens[0];
+    let master_decimals = fetch_decimals_of_token(&web3, master).await?;
+    let fee = fetch_fee_quote(&web3, &config.fee_settings()?).await?;
+    let native_pool = native_pool(&web3, &config, master).await?;
+    let native_price = native_price_in_master(
+        &web3,
+        &config,
+        native_pool.as_ref(),
+        master,
+        master_decimals,
+    )
+    .await?;
+    let gas_cost_in_master = fee.cost_in_native(config.gas_limit) * native_price;
+
+    let pool_abi = load_abi("./pool_abi.json")?;
+    let topics = ["Swap", "Mint", "Burn"]
+        .iter()
+        .map(|name| Ok(pool_abi.event(name)?.signature()))
+        .collect::<error::Result<Vec<H256>>>()?;
+
+    println!(
+        "Recording {} pools from block {} to {}",
+        states.len(),
+        block + 1,
+        block + blocks
     );
+    let mut logs = Vec::new();
+    let mut from = block + 1;
+    while from <= block + blocks {
+        let latest = web3.eth().block_number().await?.as_u64();
+        if latest < from {
+            tokio::time::sleep(config.poll_interval()).await;
+            continue;
+        }
 
-    let tx_hash1 = contract1
-        .signed_call_with_confirmations(
-            "approve",
-            approve_params.clone(),
-            Default::default(),
-            1,
-            &prvk,
-        )
-        .await
-        .map_err(|e| web3::Error::InvalidResponse(format!("Approval 1 failed: {}", e)));
-
-    // let tx_hash2 = contract2
-    //     .call(
-    //         "approve",
-    //         approve_params.clone(),
-    //         config.public_key,
-    //         Default::default(),
-    //     )
-    //     .await
-    //     .map_err(|e| web3::Error::InvalidResponse(format!("Approval 2 failed: {}", e)));
-
-    println!("Transaction 1 Hash: {:?}", tx_hash1);
-    //println!("Transaction 2 Hash: {:?}", tx_hash2);
+        let to = latest.min(block + blocks);
+        let filter = FilterBuilder::default()
+            .from_block(BlockNumber::Number(from.into()))
+            .to_block(BlockNumber::Number(to.into()))
+            .address(addresses.clone())
+            .topics(Some(topics.clone()), None, None, None)
+            .build();
+        let batch = web3.eth().logs(filter).await?;
+        println!("Blocks {} to {}: {} events", from, to, batch.len());
+        logs.extend(batch);
+        from = to + 1;
+    }
+
+    let data = backtest::BacktestData {
+        block,
+        master_decimals,
+        gas_cost_in_master,
+        pools: states,
+        events: Vec::new(),
+        logs,
+    };
+    let json = serde_json::to_string_pretty(&data)
+        .map_err(|e| Error::Decode(format!("Failed to encode recording: {}", e)))?;
+    fs::write(out_path, json)
+        .map_err(|e| Error::Config(format!("Failed to write {}: {}", out_path, e)))?;
+    println!("Recording written to {}", out_path);
 
     Ok(())
 }
 
-async fn get_price_and_pool_address(
-    web3: &Web3<Http>,
+/// Connects to the RPC endpoints of the chain, refusing any that serves
+/// another chain id than the one configured. Endpoints that do not answer
+/// are only reported, as failover skips them anyway.
+async fn connect(config: &Config) -> error::Result<Web3<FailoverHttp>> {
+    let chain = config.chain_config()?;
+    let mut answered = false;
+    for url in &chain.rpc_urls {
+        let endpoint = Web3::new(FailoverHttp::new(std::slice::from_ref(url))?);
+        match endpoint.eth().chain_id().await {
+            Ok(chain_id) if chain_id == U256::from(chain.chain_id) => answered = true,
+            Ok(chain_id) => {
+                return Err(Error::Config(format!(
+                    "{} serves chain id {}, but {} is configured with {}",
+                    url, chain_id, config.chain, chain.chain_id
+                )))
+            }
+            Err(err) => eprintln!("RPC {} did not answer eth_chainId: {}", url, err),
+        }
+    }
+    if !answered {
+        return Err(Error::Config(format!(
+            "No {} RPC endpoint answered eth_chainId",
+            config.chain
+        )));
+    }
+    Ok(Web3::new(FailoverHttp::new(&chain.rpc_urls)?))
+}
+
+async fn arbitrage(
     config: &Config,
-    pair_id: u8,
-) -> web3::Result<(f64, U256, Address)> {
-    let (token_a, token_b) = match pair_id {
-        1 => (&config.token_address_master, &config.token_address_1),
-        2 => (&config.token_address_master, &config.token_address_2),
-        _ => return Err(web3::Error::InvalidResponse("Invalid pair_id".into())),
+    web3: &Web3<FailoverHttp>,
+    universe: &Universe,
+    wallet_key: Option<&SecretKey>,
+    nonces: &NonceManager,
+    relay: Option<&Relay>,
+) -> error::Result<Evaluation> {
+    let Universe {
+        labels,
+        decimals,
+        pools,
+        ..
+    } = universe;
+    let pool_states: HashMap<Address, Pool> = try_join_all(
+        pools
+            .iter()
+            .map(|pool| Pool::fetch(web3, pool, TICK_BITMAP_WORDS_EACH_SIDE)),
+    )
+    .await?
+    .into_iter()
+    .map(|pool| (pool.address(), pool))
+    .collect();
+
+    let mut prices = Vec::new();
+    for pool in pools {
+        let state = &pool_states[&pool.address];
+        let price = PoolPrice {
+            pool: pool.address,
+            pair: format!("{} / {}", labels[&pool.token0], labels[&pool.token1]),
+            protocol: pool.protocol,
+            fee: pool.fee,
+            price: pool_price(
+                state.sqrt_price_x96().map_err(Error::Simulation)?,
+                pool.token0,
+                pool.token1,
+                decimals[&pool.token0],
+                decimals[&pool.token1],
+            ),
+        };
+        println!(
+            "{} pool {:?} ({}): price {} liquidity {}",
+            price.pair,
+            pool.address,
+            pool_label(pool.protocol, pool.fee),
+            price.price,
+            state.liquidity()
+        );
+        prices.push(price);
+    }
+
+    let opportunity = evaluate_arbitrage(web3, config, universe, &pool_states).await?;
+    let mut evaluation = Evaluation {
+        profit: opportunity.profit.clone(),
+        pools: pools.iter().map(|pool| pool.address).collect(),
+        prices,
+        paper_trade: None,
+        tx_hash: None,
     };
 
-    let factory_file = File::open("./factory_abi.json").unwrap();
-    let factory_abi = web3::ethabi::Contract::load(factory_file).unwrap();
+    if !evaluation.profit.is_profitable(config.minimum_profit) {
+        println!("Exiting: Profit below threshold");
+        return Ok(evaluation);
+    }
+
+    println!("Profitable: {}", evaluation.profit);
+    if config.dry_run {
+        let trade = paper_trade(web3, config, &opportunity).await?;
+        println!("Dry run: {}", trade);
+        evaluation.paper_trade = Some(trade);
+        return Ok(evaluation);
+    }
+    if config.executor_address.is_empty() || config.flash_pool_address.is_empty() {
+        println!("No executor contract or flash pool configured, not executing");
+        return Ok(evaluation);
+    }
+    let Some(wallet_key) = wallet_key else {
+        println!("No wallet unlocked, not executing");
+        return Ok(evaluation);
+    };
 
-    let factory_contract = Contract::new(
-        web3.eth(),
-        H160::from_str(config.contract_address.as_str()).unwrap(),
-        factory_abi,
+    let tx_hash =
+        execute_opportunity(web3, config, wallet_key, nonces, relay, &opportunity).await?;
+    println!(
+        "Arbitrage transaction sent: {}",
+        tx_link(&config.explorer_url, tx_hash)
     );
+    evaluation.tx_hash = Some(tx_hash);
+
+    Ok(evaluation)
+}
+
+/// What a run discovers once: the tokens, their decimals and the pools
+/// between them. Only pool state is read again on each block, as discovery
+/// scans factory logs and queries every pair and fee tier.
+struct Universe {
+    labels: HashMap<H160, String>,
+    decimals: HashMap<H160, u8>,
+    pools: Vec<DiscoveredPool>,
+    /// Wrapped native / master pool gas is priced from, `None` when gas is
+    /// paid in the master token.
+    native_pool: Option<DiscoveredPool>,
+}
+
+impl Universe {
+    async fn discover(config: &Config, web3: &Web3<FailoverHttp>) -> error::Result<Universe> {
+        let fee_tiers = match fee_tiers_for(config, web3).await {
+            Ok(fee_tiers) => fee_tiers,
+            Err(err) => {
+                eprintln!("Fee tier discovery failed, using defaults: {}", err);
+                DEFAULT_FEE_TIERS.to_vec()
+            }
+        };
+        println!("Fee tiers: {:?}", fee_tiers);
+        let tokens = token_universe(config)?;
+        let decimals = tokens
+            .iter()
+            .copied()
+            .zip(
+                try_join_all(
+                    tokens
+                        .iter()
+                        .map(|token| fetch_decimals_of_token(web3, *token)),
+                )
+                .await?,
+            )
+            .collect();
+        let pools = discover_token_pools(web3, config, &tokens, &fee_tiers).await?;
+        let native_pool = native_pool(web3, config, tokens[0]).await?;
+        println!(
+            "Discovered {} pools between {} tokens",
+            pools.len(),
+            tokens.len()
+        );
+        Ok(Universe {
+            labels: token_labels(&tokens),
+            decimals,
+            pools,
+            native_pool,
+        })
+    }
+}
+
+/// The master token followed by every configured routing token, without
+/// duplicates.
+fn token_universe(config: &Config) -> error::Result<Vec<H160>> {
+    let mut tokens = vec![token_string_to_h160(&config.token_address_master)?];
+    for token in &config.tokens {
+        let token = token_string_to_h160(token)?;
+        if !tokens.contains(&token) {
+            tokens.push(token);
+        }
+    }
+    Ok(tokens)
+}
+
+/// "master" for the first token, a shortened address for the rest.
+fn token_labels(tokens: &[H160]) -> HashMap<H160, String> {
+    tokens
+        .iter()
+        .enumerate()
+        .map(|(i, token)| {
+            let label = if i == 0 {
+                "master".to_string()
+            } else {
+                let hex = format!("{:?}", token);
+                format!("{}…{}", &hex[..6], &hex[hex.len() - 4..])
+            };
+            (*token, label)
+        })
+        .collect()
+}
 
-    let token_a_h160 = token_string_to_h160(token_a)?;
-    let token_b_h160 = token_string_to_h160(token_b)?;
+/// Every pool between any two tokens of the universe, on the V3 factory
+/// across `fee_tiers` and on every V2 factory.
+async fn discover_token_pools(
+    web3: &Web3<FailoverHttp>,
+    config: &Config,
+    tokens: &[H160],
+    fee_tiers: &[u32],
+) -> error::Result<Vec<DiscoveredPool>> {
+    let dexes = config.dexes(fee_tiers)?;
+    let pairs: Vec<(H160, H160)> = tokens
+        .iter()
+        .enumerate()
+        .flat_map(|(i, token_a)| {
+            tokens[i + 1..]
+                .iter()
+                .map(move |token_b| (*token_a, *token_b))
+        })
+        .collect();
+
+    let pools = try_join_all(dexes.iter().flat_map(|dex| {
+        pairs
+            .iter()
+            .map(move |(token_a, token_b)| dex.discover(web3, *token_a, *token_b))
+    }))
+    .await?;
+
+    Ok(pools.into_iter().flatten().collect())
+}
+
+async fn fee_tiers_for(config: &Config, web3: &Web3<FailoverHttp>) -> error::Result<Vec<u32>> {
+    enabled_fee_tiers(web3, token_string_to_h160(&config.contract_address)?).await
+}
+
+/// Outcome of one arbitrage pass.
+struct Evaluation {
+    profit: ProfitBreakdown,
+    /// Every pool discovered for the token universe, the ones whose swaps
+    /// can change the outcome.
+    pools: Vec<Address>,
+    prices: Vec<PoolPrice>,
+    /// What was booked instead of a transaction in dry-run mode.
+    paper_trade: Option<PaperTrade>,
+    tx_hash: Option<H256>,
+}
 
-    let pool_address: Address = factory_contract
-        .query(
-            "getPool",
-            (token_a_h160, token_b_h160, U256::from(3000)),
-            None,
-            Options::default(),
-            None,
+/// Best route found by one evaluation together with what is needed to
+/// execute it.
+struct Opportunity {
+    profit: ProfitBreakdown,
+    amount_in: U256,
+    legs: Vec<ExecutorLeg>,
+    /// Fees the opportunity was priced at and is sent with.
+    fee: FeeQuote,
+    master_decimals: u8,
+}
+
+/// The `FlashArbitrageExecutor.execute` call covering every leg of the
+/// opportunity, which reverts unless it clears `minimum_profit` plus gas.
+fn arbitrage_call(config: &Config, opportunity: &Opportunity) -> error::Result<ArbitrageCall> {
+    Ok(ArbitrageCall {
+        flash_pool: token_string_to_h160(&config.flash_pool_address)?,
+        token: token_string_to_h160(&config.token_address_master)?,
+        amount: opportunity.amount_in,
+        legs: opportunity.legs.clone(),
+        min_profit: to_token_units(
+            config.minimum_profit.max(0.0) + opportunity.profit.gas_cost,
+            opportunity.master_decimals,
         )
-        .await
-        .map_err(|e| {
-            web3::Error::InvalidResponse(format!("Factory contract query failed: {:?}", e))
-        })?;
-    if pool_address.is_zero() {
-        return Err(web3::Error::InvalidResponse(format!(
-            "No pair address for token pair: {}",
-            pair_id
-        )));
+        .map_err(Error::Simulation)?,
+    })
+}
+
+/// Sends every leg of the opportunity as one executor call, as a private
+/// bundle through `relay` when one is configured.
+async fn execute_opportunity(
+    web3: &Web3<FailoverHttp>,
+    config: &Config,
+    wallet_key: &SecretKey,
+    nonces: &NonceManager,
+    relay: Option<&Relay>,
+    opportunity: &Opportunity,
+) -> error::Result<H256> {
+    let tx = arbitrage_transaction(
+        token_string_to_h160(&config.executor_address)?,
+        &arbitrage_call(config, opportunity)?,
+        config.gas_limit,
+    )?;
+
+    let Some(relay) = relay else {
+        return nonces.send(web3, tx, &opportunity.fee, wallet_key).await;
+    };
+
+    let signed = nonces.sign(web3, tx, &opportunity.fee, wallet_key).await?;
+    let submitted = relay
+        .submit(
+            web3,
+            &[signed.raw_transaction],
+            signed.transaction_hash,
+            config.relay_target_blocks,
+        )
+        .await;
+    // A bundle that never landed leaves its nonce unused.
+    let inclusion = match submitted {
+        Ok(inclusion) => inclusion,
+        Err(err) => {
+            nonces.resync(web3).await?;
+            return Err(err);
+        }
+    };
+    println!(
+        "Bundle included in block {} after {} attempt(s)",
+        inclusion.block, inclusion.attempts
+    );
+    Ok(inclusion.tx_hash)
+}
+
+/// What executing the opportunity would have done. With an executor
+/// configured the call is checked against the chain from `public_key`,
+/// otherwise the off-chain simulation is taken as is.
+async fn paper_trade(
+    web3: &Web3<FailoverHttp>,
+    config: &Config,
+    opportunity: &Opportunity,
+) -> error::Result<PaperTrade> {
+    let profit = &opportunity.profit;
+    // The slippage buffer is a safety margin, not a cost.
+    let mut trade = PaperTrade {
+        route: profit.route.clone(),
+        amount_in: profit.amount_in,
+        amount_out: profit.amount_out,
+        gas_used: None,
+        gas_cost: profit.gas_cost,
+        realized_profit: profit.net_profit + profit.slippage_buffer,
+        reverted: None,
+    };
+    if config.executor_address.is_empty() || config.flash_pool_address.is_empty() {
+        return Ok(trade);
+    }
+
+    match dry_run_arbitrage(
+        web3,
+        token_string_to_h160(&config.executor_address)?,
+        &arbitrage_call(config, opportunity)?,
+        config.gas_limit,
+        config.public_key,
+    )
+    .await
+    {
+        Ok(gas_used) => {
+            let gas_used = gas_used.low_u64();
+            trade.gas_used = Some(gas_used);
+            if config.gas_limit > 0 {
+                trade.gas_cost = profit.gas_cost * gas_used as f64 / config.gas_limit as f64;
+            }
+            trade.realized_profit += profit.gas_cost - trade.gas_cost;
+        }
+        // A reverted transaction still pays for its gas.
+        Err(Error::Revert(reason)) => {
+            trade.realized_profit = -trade.gas_cost;
+            trade.reverted = Some(reason);
+        }
+        Err(Error::Rpc(web3::Error::Rpc(err))) => {
+            trade.realized_profit = -trade.gas_cost;
+            trade.reverted = Some(err.message);
+        }
+        Err(err) => return Err(err),
     }
 
-    let pool_file = File::open("./pool_abi.json").unwrap();
-    let pool_abi = web3::ethabi::Contract::load(pool_file).unwrap();
+    Ok(trade)
+}
+
+/// Searches the pool graph for cycles through the master token of up to
+/// `max_hops` swaps, simulates each one and ranks them by net profit,
+/// returning the best. Gas for a route is priced at `gas_limit` units, and
+/// the flash loan fee is included when a flash pool is configured.
+async fn evaluate_arbitrage(
+    web3: &Web3<FailoverHttp>,
+    config: &Config,
+    universe: &Universe,
+    pool_states: &HashMap<Address, Pool>,
+) -> error::Result<Opportunity> {
+    let master = token_string_to_h160(&config.token_address_master)?;
+
+    let flash_pool_address = if config.flash_pool_address.is_empty() {
+        None
+    } else {
+        Some(token_string_to_h160(&config.flash_pool_address)?)
+    };
+    let routes: Vec<Route> = PoolGraph::new(&universe.pools)
+        .cycles_from(master, config.max_hops)
+        .into_iter()
+        .filter(|route| {
+            flash_pool_address.is_none_or(|flash| route.pools.iter().all(|p| p.address != flash))
+        })
+        .collect();
+    if routes.is_empty() {
+        return Err(Error::Simulation(
+            "no round trip can be built from the discovered pools",
+        ));
+    }
+    println!(
+        "{} candidate routes of up to {} hops",
+        routes.len(),
+        config.max_hops
+    );
 
-    let pool_contract = Contract::new(web3.eth(), pool_address, pool_abi);
+    let flash_fee_pips = match flash_pool_address {
+        Some(address) => PoolState::fetch(web3, address, 0).await?.fee,
+        None => 0,
+    };
 
-    let slot0: (U256, i64, U256, u32, u32, u32, bool) = pool_contract
-        .query("slot0", (), None, Options::default(), None)
-        .await
-        .map_err(|e| {
-            web3::Error::InvalidResponse(format!("Pool contract query failed: {:?}", e))
-        })?;
+    let master_decimals = universe.decimals[&master];
+    let fee = fetch_fee_quote(web3, &config.fee_settings()?).await?;
+    let native_price = native_price_in_master(
+        web3,
+        config,
+        universe.native_pool.as_ref(),
+        master,
+        master_decimals,
+    )
+    .await?;
+    let gas_cost_in_master = fee.cost_in_native(config.gas_limit) * native_price;
+    println!(
+        "Fees ({}): {}, {} gas costs {:.6} master tokens",
+        config.fee_strategy, fee, config.gas_limit, gas_cost_in_master
+    );
 
-    let decimals_token1 = fetch_decimals_of_token(web3, token_a_h160)
-        .await
-        .map_err(|e| {
-            web3::Error::InvalidResponse(format!(
-                "Failed to get decimals for token 1 in pair {}: {:?}",
-                pair_id, e
-            ))
-        })?;
-
-    let decimals_token2 = fetch_decimals_of_token(web3, token_b_h160)
-        .await
-        .map_err(|e| {
-            web3::Error::InvalidResponse(format!(
-                "Failed to get decimals for token 2 in pair {}: {:?}",
-                pair_id, e
-            ))
-        })?;
+    let amount_in = amount_to_trade_units(config, master_decimals)?;
+    if amount_in.is_zero() {
+        return Err(Error::Simulation(
+            "amount_to_trade rounds to zero units of the master token",
+        ));
+    }
+    let mut opportunities: Vec<Opportunity> = Vec::new();
+    for route in routes {
+        let description = route.describe(&universe.labels);
+        let pools: Vec<&Pool> = route
+            .pools
+            .iter()
+            .map(|pool| &pool_states[&pool.address])
+            .collect();
+        let simulated = simulate_route(&pools, master, amount_in).and_then(|legs| {
+            let executor_legs =
+                ExecutorLeg::for_route(&pools, master, &legs, config.slippage_threshhold)?;
+            Ok((legs, executor_legs))
+        });
+        match simulated {
+            Ok((legs, executor_legs)) => opportunities.push(Opportunity {
+                profit: ProfitBreakdown::new(
+                    description,
+                    &legs,
+                    master_decimals,
+                    flash_fee_pips,
+                    gas_cost_in_master,
+                    config.slippage_threshhold,
+                ),
+                amount_in,
+                legs: executor_legs,
+                fee,
+                master_decimals,
+            }),
+            Err(e) => println!("{}: simulation failed: {}", description, e),
+        }
+    }
 
-    let sqrt_price_x96 = slot0.0;
-    let sqrt_price_x96_f64 = u256_to_f64(sqrt_price_x96);
-    let sqrt_price = sqrt_price_x96_f64 / (2_f64.powi(96));
-    let price = sqrt_price * sqrt_price;
-    let adj_price = price / 10_f64.powi(decimals_token1 as i32 - decimals_token2 as i32);
+    opportunities.sort_by(|a, b| b.profit.net_profit.total_cmp(&a.profit.net_profit));
+    for (rank, opportunity) in opportunities.iter().take(RANKED_ROUTES_LOGGED).enumerate() {
+        println!("#{} {}", rank + 1, opportunity.profit);
+    }
 
-    Ok((adj_price, sqrt_price_x96, pool_address))
+    opportunities
+        .into_iter()
+        .next()
+        .ok_or(Error::Simulation("no route could be simulated"))
 }
 
-fn check_valid_addresses(address_strs: Vec<&String>) -> HashMap<&String, bool> {
-    let mut results = HashMap::new();
+/// The wrapped native / master pool gas is priced from, `None` when gas is
+/// paid in the master token or no wrapped native token is configured.
+async fn native_pool(
+    web3: &Web3<FailoverHttp>,
+    config: &Config,
+    master: H160,
+) -> error::Result<Option<DiscoveredPool>> {
+    if config.wrapped_native_address.is_empty() {
+        return Ok(None);
+    }
+    let wrapped_native = token_string_to_h160(&config.wrapped_native_address)?;
+    if wrapped_native == master {
+        return Ok(None);
+    }
+
+    let factory_address = token_string_to_h160(&config.contract_address)?;
+    discover_pools(
+        web3,
+        factory_address,
+        wrapped_native,
+        master,
+        &DEFAULT_FEE_TIERS,
+    )
+    .await?
+    .into_iter()
+    .next()
+    .map(Some)
+    .ok_or_else(|| Error::Config("No wrapped native / master pool to price gas".into()))
+}
 
-    for addr in address_strs {
-        let is_valid = addr.parse::<Address>().is_ok();
-        results.insert(addr, is_valid);
+/// How many master tokens one native token is worth, read from the current
+/// price of `native_pool`.
+async fn native_price_in_master(
+    web3: &Web3<FailoverHttp>,
+    config: &Config,
+    native_pool: Option<&DiscoveredPool>,
+    master: H160,
+    master_decimals: u8,
+) -> error::Result<f64> {
+    let Some(pool) = native_pool else {
+        return Ok(1.0);
+    };
+    let wrapped_native = token_string_to_h160(&config.wrapped_native_address)?;
+    let native_decimals = fetch_decimals_of_token(web3, wrapped_native).await?;
+    let sqrt_price_x96 = PoolState::fetch(web3, pool.address, 0)
+        .await?
+        .sqrt_price_x96;
+
+    let master_per_native = quote(
+        sqrt_price_x96,
+        U256::exp10(native_decimals as usize),
+        wrapped_native < master,
+    )
+    .map_err(Error::Simulation)?;
+    Ok(from_token_units(master_per_native, master_decimals))
+}
+
+/// Brings the router's allowance on the master token up to the trade
+/// amount, following `approval_policy`. Runs once when the loop starts,
+/// never per trade, as the approvals would go out ahead of a private
+/// bundle. Nothing is approved without a router.
+async fn approve_router(
+    config: &Config,
+    web3: &Web3<FailoverHttp>,
+    wallet_key: &SecretKey,
+    nonces: &NonceManager,
+) -> error::Result<()> {
+    if config.router_address.is_empty() {
+        return Ok(());
+    }
+    let spender = token_string_to_h160(&config.router_address)?;
+    let master = token_string_to_h160(&config.token_address_master)?;
+    let amount = amount_to_trade_units(config, fetch_decimals_of_token(web3, master).await?)?;
+    let approver = Approver {
+        web3,
+        nonces,
+        key: wallet_key,
+        fee: fetch_fee_quote(web3, &config.fee_settings()?).await?,
+    };
+    for tx_hash in approver
+        .ensure(master, spender, amount, config.approval_policy)
+        .await?
+    {
+        println!(
+            "Approval of {:?} on {:?} sent: {}",
+            spender,
+            master,
+            tx_link(&config.explorer_url, tx_hash)
+        );
     }
+    Ok(())
+}
 
-    return results;
+/// Price of `base` in `quote` from a V3 pool's `sqrtPriceX96`. Pools order
+/// their tokens by address, so the raw price is token1 per token0.
+fn pool_price(
+    sqrt_price_x96: U256,
+    base: H160,
+    quote: H160,
+    decimals_base: u8,
+    decimals_quote: u8,
+) -> f64 {
+    if base < quote {
+        sqrt_price_x96_to_price(sqrt_price_x96, decimals_base, decimals_quote)
+    } else {
+        sqrt_price_x96_to_price(sqrt_price_x96, decimals_quote, decimals_base).recip()
+    }
 }
-fn u256_to_f64(u: U256) -> f64 {
-    let (upper, lower) = u.div_mod(U256::from(u64::MAX));
-    (upper.as_u64() as f64) * ((u64::MAX as f64) + 1.0) + (lower.as_u64() as f64)
+
+/// `amount_to_trade` in the smallest units of a token with `decimals`.
+fn amount_to_trade_units(config: &Config, decimals: u8) -> error::Result<U256> {
+    to_token_units(config.amount_to_trade, decimals)
+        .map_err(|e| Error::Config(format!("amount_to_trade {}: {}", config.amount_to_trade, e)))
 }
 
-fn token_string_to_h160(token: &String) -> web3::Result<H160> {
-    return H160::from_str(token.as_str())
-        .map_err(|e| web3::Error::InvalidResponse(format!("Failed to convert token_a: {:?}", e)));
+fn token_string_to_h160(token: &str) -> error::Result<H160> {
+    H160::from_str(token).map_err(|e| Error::Config(format!("Invalid address {}: {}", token, e)))
 }
 
-async fn fetch_decimals_of_token(web3: &Web3<Http>, token_address: H160) -> web3::Result<u8> {
-    let token_abi_file = File::open("./erc20_abi.json").unwrap();
-    let token_abi = web3::ethabi::Contract::load(token_abi_file).unwrap();
-    let token_contract = Contract::new(web3.eth(), token_address, token_abi);
+async fn fetch_decimals_of_token(
+    web3: &Web3<FailoverHttp>,
+    token_address: H160,
+) -> error::Result<u8> {
+    let token_contract = Contract::new(web3.eth(), token_address, load_abi("./erc20_abi.json")?);
     let decimals: u8 = token_contract
         .query("decimals", (), None, Options::default(), None)
-        .await
-        .map_err(|e| {
-            web3::Error::InvalidResponse(format!("Token contract query failed: {:?}", e))
-        })?;
+        .await?;
 
     Ok(decimals)
 }
 
-pub fn calculate_sqrt_price_limit(price: f64) -> u128 {
-    let sqrt_price = price.sqrt();
-    let sqrt_price_fixed_point: u128 = (sqrt_price * (1u64 << 48) as f64) as u128;
-    return sqrt_price_fixed_point << 48;
-}
-
 pub fn priv_key_to_pub_key(private_key: &String) -> Result<Address, &'static str> {
     let secp: Secp256k1<secp256k1::All> = Secp256k1::new();
 
@@ -891,20 +3033,260 @@ pub fn priv_key_to_pub_key(private_key: &String) -> Result<Address, &'static str
     Ok(address)
 }
 
-fn f64_to_u256(val: f64) -> U256 {
-    let val_str = val.to_string();
-    let parts: Vec<&str> = val_str.split('.').collect();
-    let int_part: U256 = parts[0].parse::<U256>().unwrap();
-    let decimal_part_str: String;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    if parts.len() > 1 {
-        decimal_part_str = format!("0.{}", parts[1]);
-    } else {
-        decimal_part_str = "0.0".to_string();
+    #[test]
+    fn only_real_legacy_keys_are_offered() {
+        let key = |json: &str| legacy_private_key(&serde_json::from_str(json).unwrap());
+        assert_eq!(
+            key(
+                r#"{"private_key": "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318"}"#
+            ),
+            Some("0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318".into())
+        );
+        assert_eq!(
+            key(&format!(r#"{{"private_key": "{}"}}"#, "0".repeat(64))),
+            None
+        );
+        assert_eq!(
+            key(&format!(r#"{{"private_key": "0x{}"}}"#, "0".repeat(64))),
+            None
+        );
+        assert_eq!(key(r#"{"private_key": ""}"#), None);
+        assert_eq!(key(r#"{"chain": "Ethereum"}"#), None);
+    }
+
+    fn migrated(json: &str, from_version: u32) -> Config {
+        let mut value: serde_json::Value = serde_json::from_str(json).unwrap();
+        assert_eq!(migrate_config(&mut value).unwrap(), from_version);
+        serde_json::from_value(value).unwrap()
+    }
+
+    /// The two tokens of version 0 become the `tokens` list, as the
+    /// original `config.json` shipped them.
+    #[test]
+    fn upgrade_token_pairs_lists_both_tokens() {
+        let version_0 = |token_2: &str| {
+            format!(
+                r#"{{
+                    "chain": "Ethereum",
+                    "contract_address": "0x0227628f3F023bb0B980b67D528571c95c6DaC1c",
+                    "private_key": "0000000000000000000000000000000000000000000000000000000000000000",
+                    "public_key": "0xa07876136c3a3141dc2c25071330d3b08225e043",
+                    "token_address_master": "0xfFf9976782d46CC05630D1f6eBAb18b2324d6B14",
+                    "token_address_1": "0xb2608eEa90C44C1568dE718500e192E69C781363",
+                    "token_address_2": "{}",
+                    "gas_limit": 2500000,
+                    "slippage_threshhold": 1000.0,
+                    "minimum_profit": 100.0,
+                    "amount_to_trade": 0.0001
+                }}"#,
+                token_2
+            )
+        };
+
+        let config = migrated(&version_0("0x1f9840a85d5aF5bf1D1762F925BDADdC4201F984"), 0);
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert_eq!(
+            config.tokens,
+            [
+                "0xb2608eEa90C44C1568dE718500e192E69C781363",
+                "0x1f9840a85d5aF5bf1D1762F925BDADdC4201F984"
+            ]
+        );
+        assert_eq!(config.chains, default_chains());
+
+        // An unset second token is dropped rather than kept as an empty one.
+        let config = migrated(&version_0(""), 0);
+        assert_eq!(
+            config.tokens,
+            ["0xb2608eEa90C44C1568dE718500e192E69C781363"]
+        );
+
+        // A `tokens` list already there wins over the legacy pair.
+        let mut value: serde_json::Value = serde_json::from_str(&version_0("")).unwrap();
+        value["tokens"] = serde_json::json!(["0x1f9840a85d5aF5bf1D1762F925BDADdC4201F984"]);
+        migrate_config(&mut value).unwrap();
+        assert_eq!(
+            value["tokens"],
+            serde_json::json!(["0x1f9840a85d5aF5bf1D1762F925BDADdC4201F984"])
+        );
+        assert!(value.get("token_address_1").is_none());
+    }
+
+    /// Version 1 URLs land on the chain they were keyed by, which keeps the
+    /// id it always had.
+    #[test]
+    fn upgrade_chains_keeps_chain_ids() {
+        let config = migrated(
+            r#"{
+                "version": 1,
+                "chain": "Polygon",
+                "rpc_urls": {
+                    "Ethereum": ["http://127.0.0.1:8545", "https://eth.example"],
+                    "Binance": [],
+                    "Polygon": ["https://polygon.example"]
+                },
+                "ws_urls": { "Polygon": "wss://polygon.example" },
+                "contract_address": "0x1F98431c8aD98523631AE4a59f267346ea31F984",
+                "public_key": "0xa07876136c3a3141dc2c25071330d3b08225e043",
+                "token_address_master": "0x0d500B1d8E8eF31E21C99d1Db9A6444d3ADf1270",
+                "gas_limit": 2500000,
+                "slippage_threshhold": 0.5,
+                "minimum_profit": 100.0,
+                "amount_to_trade": 0.0001
+            }"#,
+            1,
+        );
+        let defaults = default_chains();
+        assert_eq!(
+            config.chains.keys().collect::<Vec<_>>(),
+            defaults.keys().collect::<Vec<_>>()
+        );
+        for (chain, chain_config) in &config.chains {
+            assert_eq!(chain_config.chain_id, defaults[chain].chain_id, "{}", chain);
+        }
+
+        let polygon = config.chain_config().unwrap();
+        assert_eq!(polygon.chain_id, 137);
+        assert_eq!(polygon.rpc_urls, ["https://polygon.example"]);
+        assert_eq!(polygon.ws_url, "wss://polygon.example");
+        let ethereum = &config.chains[&Chain::new("Ethereum")];
+        assert_eq!(ethereum.chain_id, 1);
+        assert_eq!(
+            ethereum.rpc_urls,
+            ["http://127.0.0.1:8545", "https://eth.example"]
+        );
+        let binance = &config.chains[&Chain::new("Binance")];
+        assert_eq!(binance.chain_id, 56);
+        assert_eq!(binance.rpc_urls, defaults[&Chain::new("Binance")].rpc_urls);
     }
 
-    let decimal_part_f64: f64 = decimal_part_str.parse().unwrap();
-    let decimal_part_u256: U256 = ((decimal_part_f64 * 1e18).round() as u64).into();
+    /// The chains a version 1 config is migrated to are the ones the
+    /// registry knows under the same ids.
+    #[test]
+    fn default_chains_match_the_registry() {
+        let registry = registry::load("").unwrap();
+        for (chain, chain_config) in default_chains() {
+            assert_eq!(
+                chain_config.chain_id, registry[&chain].chain_id,
+                "{}",
+                chain
+            );
+        }
+    }
 
-    int_part * U256::exp10(18) + decimal_part_u256
+    /// A config on Sepolia, with the Sepolia contracts of the registry,
+    /// validates and runs on the chain id the registry has for them.
+    #[test]
+    fn sepolia_config_is_consistent() {
+        let config: Config = serde_json::from_str(
+            r#"{
+                "version": 2,
+                "chain": "Sepolia",
+                "chains": {
+                    "Sepolia": {
+                        "chain_id": 11155111,
+                        "rpc_urls": ["http://127.0.0.1:8545"],
+                        "native_symbol": "ETH",
+                        "eip1559": true,
+                        "block_time_secs": 12.0
+                    }
+                },
+                "contract_address": "0x0227628f3F023bb0B980b67D528571c95c6DaC1c",
+                "public_key": "0xa07876136c3a3141dc2c25071330d3b08225e043",
+                "token_address_master": "0xfFf9976782d46CC05630D1f6eBAb18b2324d6B14",
+                "tokens": ["0x1f9840a85d5aF5bf1D1762F925BDADdC4201F984"],
+                "gas_limit": 2500000,
+                "slippage_threshhold": 0.5,
+                "minimum_profit": 100.0,
+                "amount_to_trade": 0.0001
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(config.validate(), []);
+
+        let info = &registry::load("").unwrap()[&config.chain];
+        assert_eq!(config.chain_config().unwrap().chain_id, info.chain_id);
+        let factory: Address = config.contract_address.parse().unwrap();
+        assert!(info
+            .dexes
+            .iter()
+            .any(|dex| dex.factory.parse::<Address>().ok() == Some(factory)));
+        assert_eq!(
+            config.token_address_master.parse::<Address>().ok(),
+            info.wrapped_native.parse::<Address>().ok()
+        );
+    }
+
+    /// Pools are discovered on the first evaluation and only read again on
+    /// the following ones.
+    #[tokio::test]
+    async fn discovery_runs_once() {
+        let selector =
+            |signature: &str| hex::encode(&web3::signing::keccak256(signature.as_bytes())[..4]);
+        let (decimals, get_pool) = (
+            selector("decimals()"),
+            selector("getPool(address,address,uint24)"),
+        );
+        let mock = mock_rpc::MockRpc::start(move |method, params| {
+            let word = |value: u64| format!("0x{:064x}", value);
+            match method {
+                "eth_blockNumber" => Ok(serde_json::json!("0x64")),
+                "eth_getLogs" => Ok(serde_json::json!([])),
+                "eth_call" => {
+                    let data = params[0]["data"].as_str().unwrap_or_default();
+                    Ok(serde_json::json!(if data[2..].starts_with(&decimals) {
+                        word(18)
+                    } else if data[2..].starts_with(&get_pool) {
+                        // No pool for any pair.
+                        word(0)
+                    } else {
+                        // feeAmountTickSpacing
+                        word(10)
+                    }))
+                }
+                _ => Err(format!("unexpected {}", method)),
+            }
+        });
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "version": 2,
+            "chain": "Local",
+            "chains": {
+                "Local": {
+                    "chain_id": 1,
+                    "rpc_urls": [mock.url],
+                    "native_symbol": "ETH",
+                    "eip1559": true,
+                    "block_time_secs": 12.0
+                }
+            },
+            "contract_address": "0x1F98431c8aD98523631AE4a59f267346ea31F984",
+            "public_key": "0xa07876136c3a3141dc2c25071330d3b08225e043",
+            "token_address_master": "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2",
+            "tokens": ["0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48"],
+            "gas_limit": 2500000,
+            "slippage_threshhold": 0.5,
+            "minimum_profit": 100.0,
+            "amount_to_trade": 1.0
+        }))
+        .unwrap();
+        let web3 = Web3::new(FailoverHttp::new(std::slice::from_ref(&mock.url)).unwrap());
+        let nonces = NonceManager::new(Address::zero());
+
+        let mut universe = None;
+        for _ in 0..2 {
+            let result =
+                evaluate_new_block(&config, &web3, &mut universe, None, &nonces, None, None).await;
+            assert!(matches!(result, Err(Error::Simulation(_))));
+        }
+        let universe = universe.unwrap();
+        assert_eq!(universe.pools, []);
+        assert_eq!(universe.decimals.len(), 2);
+        assert_eq!(mock.calls("eth_getLogs").len(), 1);
+        // Two decimals, then the tick spacing and pool of each default tier.
+        assert_eq!(mock.calls("eth_call").len(), 2 + 4 + 4);
+    }
 }
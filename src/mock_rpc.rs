@@ -0,0 +1,138 @@
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+/// Answers a call with its result, or the message of a JSON-RPC error.
+type Handler = dyn Fn(&str, &[Value]) -> Result<Value, String> + Send + Sync;
+
+/// A request the server received.
+#[derive(Debug, Clone)]
+pub struct Request {
+    /// Header names are lowercase.
+    pub headers: HashMap<String, String>,
+    pub body: String,
+}
+
+impl Request {
+    pub fn json(&self) -> Value {
+        serde_json::from_str(&self.body).unwrap()
+    }
+
+    pub fn method(&self) -> String {
+        self.json()["method"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string()
+    }
+}
+
+/// Local JSON-RPC server standing in for a node or a relay in tests. Every
+/// connection is served on its own thread until the test exits.
+pub struct MockRpc {
+    pub url: String,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl MockRpc {
+    pub fn start(
+        handler: impl Fn(&str, &[Value]) -> Result<Value, String> + Send + Sync + 'static,
+    ) -> MockRpc {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let handler: Arc<Handler> = Arc::new(handler);
+
+        let received = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let received = received.clone();
+                let handler = handler.clone();
+                thread::spawn(move || serve(stream, &received, handler.as_ref()));
+            }
+        });
+        MockRpc { url, requests }
+    }
+
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Params of every call of `method`, in order.
+    pub fn calls(&self, method: &str) -> Vec<Value> {
+        self.requests()
+            .iter()
+            .filter(|request| request.method() == method)
+            .map(|request| request.json()["params"].clone())
+            .collect()
+    }
+}
+
+/// URL of a local port nothing listens on.
+pub fn dead_url() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    format!("http://{}", listener.local_addr().unwrap())
+}
+
+fn serve(stream: TcpStream, received: &Mutex<Vec<Request>>, handler: &Handler) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut stream = stream;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap_or(0) == 0 {
+            return;
+        }
+        let mut headers = HashMap::new();
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header).unwrap_or(0) == 0 {
+                return;
+            }
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+            }
+        }
+        let length = headers
+            .get("content-length")
+            .and_then(|length| length.parse().ok())
+            .unwrap_or(0);
+        let mut body = vec![0; length];
+        if reader.read_exact(&mut body).is_err() {
+            return;
+        }
+        let request = Request {
+            headers,
+            body: String::from_utf8(body).unwrap(),
+        };
+        let call = request.json();
+        received.lock().unwrap().push(request);
+
+        let params = call["params"].as_array().cloned().unwrap_or_default();
+        let response = match handler(call["method"].as_str().unwrap_or_default(), &params) {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": call["id"], "result": result }),
+            Err(message) => json!({
+                "jsonrpc": "2.0",
+                "id": call["id"],
+                "error": { "code": -32000, "message": message },
+            }),
+        }
+        .to_string();
+        let written = write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            response.len(),
+            response
+        );
+        if written.is_err() {
+            return;
+        }
+    }
+}
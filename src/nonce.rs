@@ -0,0 +1,475 @@
+use crate::{
+    error::{self, Error},
+    gas::FeeQuote,
+    rpc::FailoverHttp,
+};
+use std::{collections::BTreeMap, sync::Mutex};
+use web3::{
+    signing::SecretKey,
+    types::{
+        Address, BlockNumber, Bytes, SignedTransaction, TransactionParameters, H256, U256, U64,
+    },
+    Web3,
+};
+
+/// Gas of a plain transfer, all a cancellation needs.
+const CANCEL_GAS: u64 = 21_000;
+
+/// A transaction sent from the managed address and not yet mined.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingTx {
+    pub nonce: U256,
+    pub hash: H256,
+    /// Unsigned, so it can be signed again at higher fees.
+    pub tx: TransactionParameters,
+    pub fee: FeeQuote,
+    /// Block that was current when it was last (re)sent.
+    pub sent_at: U64,
+    /// Speed-ups sent so far.
+    pub replacements: u32,
+    pub cancelled: bool,
+}
+
+#[derive(Debug, Default)]
+struct NonceState {
+    /// Next nonce to hand out, `None` until synced from the node.
+    next: Option<U256>,
+    pending: BTreeMap<U256, PendingTx>,
+}
+
+/// Hands out the nonces of one account locally so transactions can be sent
+/// back to back without waiting on each other, and keeps what was sent so a
+/// stuck one can be sped up or cancelled with the same nonce.
+#[derive(Debug)]
+pub struct NonceManager {
+    address: Address,
+    state: Mutex<NonceState>,
+    /// Held while syncing from the node, so concurrent callers wait for one
+    /// sync instead of each starting from the same count.
+    sync: tokio::sync::Mutex<()>,
+}
+
+impl NonceManager {
+    pub fn new(address: Address) -> NonceManager {
+        NonceManager {
+            address,
+            state: Mutex::new(NonceState::default()),
+            sync: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    pub fn address(&self) -> Address {
+        self.address
+    }
+
+    /// Takes the next nonce, syncing from the node on first use.
+    pub async fn reserve(&self, web3: &Web3<FailoverHttp>) -> error::Result<U256> {
+        if self.state.lock().unwrap().next.is_none() {
+            let _sync = self.sync.lock().await;
+            if self.state.lock().unwrap().next.is_none() {
+                self.sync_from_node(web3).await?;
+            }
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let nonce = state.next.unwrap_or_default();
+        state.next = Some(nonce + 1);
+        Ok(nonce)
+    }
+
+    /// Starts over from `eth_getTransactionCount`. Mined transactions are
+    /// forgotten, as are tracked ones the node no longer has pending, and a
+    /// nonce reserved for a transaction that never went out is handed out
+    /// again.
+    pub async fn resync(&self, web3: &Web3<FailoverHttp>) -> error::Result<U256> {
+        let _sync = self.sync.lock().await;
+        self.sync_from_node(web3).await
+    }
+
+    async fn sync_from_node(&self, web3: &Web3<FailoverHttp>) -> error::Result<U256> {
+        let mined = web3
+            .eth()
+            .transaction_count(self.address, Some(BlockNumber::Latest))
+            .await?;
+        let pending = web3
+            .eth()
+            .transaction_count(self.address, Some(BlockNumber::Pending))
+            .await?;
+
+        let mut state = self.state.lock().unwrap();
+        state
+            .pending
+            .retain(|nonce, _| *nonce >= mined && *nonce < pending);
+        state.next = Some(pending);
+        Ok(pending)
+    }
+
+    /// Forgets every tracked transaction that has been mined, returning the
+    /// ones still pending.
+    pub async fn prune(&self, web3: &Web3<FailoverHttp>) -> error::Result<Vec<PendingTx>> {
+        let mined = web3
+            .eth()
+            .transaction_count(self.address, Some(BlockNumber::Latest))
+            .await?;
+
+        let mut state = self.state.lock().unwrap();
+        state.pending.retain(|nonce, _| *nonce >= mined);
+        Ok(state.pending.values().cloned().collect())
+    }
+
+    /// Signs `tx` at `fee` with a fresh nonce without sending it, for
+    /// submission elsewhere. `resync` frees the nonce if it never lands.
+    pub async fn sign(
+        &self,
+        web3: &Web3<FailoverHttp>,
+        mut tx: TransactionParameters,
+        fee: &FeeQuote,
+        key: &SecretKey,
+    ) -> error::Result<SignedTransaction> {
+        tx.nonce = Some(self.reserve(web3).await?);
+        fee.apply(&mut tx);
+        match sign(web3, tx, key).await {
+            Ok(signed) => Ok(signed),
+            Err(err) => {
+                self.resync_after(web3, &err).await;
+                Err(err)
+            }
+        }
+    }
+
+    /// Signs and broadcasts `tx` at `fee` with the next nonce, and tracks
+    /// it until it is mined.
+    pub async fn send(
+        &self,
+        web3: &Web3<FailoverHttp>,
+        tx: TransactionParameters,
+        fee: &FeeQuote,
+        key: &SecretKey,
+    ) -> error::Result<H256> {
+        let nonce = self.reserve(web3).await?;
+        let pending = PendingTx {
+            nonce,
+            hash: H256::zero(),
+            tx,
+            fee: *fee,
+            sent_at: U64::zero(),
+            replacements: 0,
+            cancelled: false,
+        };
+        self.broadcast(web3, pending, key).await
+    }
+
+    /// Sends the transaction at `nonce` again at `fresh` fees, or enough
+    /// above its last ones to replace it. Fails rather than go over `cap`.
+    pub async fn speed_up(
+        &self,
+        web3: &Web3<FailoverHttp>,
+        nonce: U256,
+        fresh: &FeeQuote,
+        cap: Option<U256>,
+        key: &SecretKey,
+    ) -> error::Result<H256> {
+        let mut pending = self.tracked(nonce)?;
+        pending.fee = replacement_fee(&pending.fee, fresh, cap)?;
+        pending.replacements += 1;
+        self.broadcast(web3, pending, key).await
+    }
+
+    /// Replaces the transaction at `nonce` with an empty transfer to the
+    /// managed address, so the nonce is used up without doing anything.
+    pub async fn cancel(
+        &self,
+        web3: &Web3<FailoverHttp>,
+        nonce: U256,
+        fresh: &FeeQuote,
+        cap: Option<U256>,
+        key: &SecretKey,
+    ) -> error::Result<H256> {
+        let mut pending = self.tracked(nonce)?;
+        pending.fee = replacement_fee(&pending.fee, fresh, cap)?;
+        pending.tx = TransactionParameters {
+            to: Some(self.address),
+            gas: U256::from(CANCEL_GAS),
+            value: U256::zero(),
+            data: Bytes::default(),
+            ..Default::default()
+        };
+        pending.cancelled = true;
+        self.broadcast(web3, pending, key).await
+    }
+
+    fn tracked(&self, nonce: U256) -> error::Result<PendingTx> {
+        self.state
+            .lock()
+            .unwrap()
+            .pending
+            .get(&nonce)
+            .cloned()
+            .ok_or_else(|| Error::Execution(format!("No pending transaction at nonce {}", nonce)))
+    }
+
+    async fn broadcast(
+        &self,
+        web3: &Web3<FailoverHttp>,
+        mut pending: PendingTx,
+        key: &SecretKey,
+    ) -> error::Result<H256> {
+        let mut tx = pending.tx.clone();
+        tx.nonce = Some(pending.nonce);
+        pending.fee.apply(&mut tx);
+
+        let sent = async {
+            let signed = sign(web3, tx, key).await?;
+            let block = web3.eth().block_number().await?;
+            web3.eth()
+                .send_raw_transaction(signed.raw_transaction)
+                .await
+                .map_err(|e| match Error::from(e) {
+                    Error::Rpc(e) => Error::Execution(format!("Failed to send transaction: {}", e)),
+                    e => e,
+                })?;
+            Ok((signed.transaction_hash, block))
+        }
+        .await;
+
+        match sent {
+            Ok((hash, block)) => {
+                pending.hash = hash;
+                pending.sent_at = block;
+                self.state
+                    .lock()
+                    .unwrap()
+                    .pending
+                    .insert(pending.nonce, pending);
+                Ok(hash)
+            }
+            Err(err) => {
+                self.resync_after(web3, &err).await;
+                Err(err)
+            }
+        }
+    }
+
+    async fn resync_after(&self, web3: &Web3<FailoverHttp>, err: &Error) {
+        match self.resync(web3).await {
+            Ok(next) => println!("Nonce resynced to {} after: {}", next, err),
+            Err(resync_err) => {
+                eprintln!("Nonce resync failed: {}", resync_err);
+                self.state.lock().unwrap().next = None;
+            }
+        }
+    }
+}
+
+async fn sign(
+    web3: &Web3<FailoverHttp>,
+    tx: TransactionParameters,
+    key: &SecretKey,
+) -> error::Result<SignedTransaction> {
+    web3.accounts()
+        .sign_transaction(tx, key)
+        .await
+        .map_err(|e| Error::Execution(format!("Failed to sign transaction: {}", e)))
+}
+
+fn replacement_fee(
+    previous: &FeeQuote,
+    fresh: &FeeQuote,
+    cap: Option<U256>,
+) -> error::Result<FeeQuote> {
+    let fee = fresh.replacing(previous);
+    if cap.is_some_and(|cap| fee.max_fee > cap) {
+        return Err(Error::Execution(
+            "replacing the transaction would exceed the max fee cap".into(),
+        ));
+    }
+    Ok(fee)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_rpc::MockRpc;
+    use serde_json::json;
+    use std::sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    };
+    use web3::signing::keccak256;
+
+    /// A node whose account has `mined` transactions mined and `pending`
+    /// sent, and which rejects raw transactions while `reject` is set.
+    struct Node {
+        mock: MockRpc,
+        mined: Arc<AtomicU64>,
+        pending: Arc<AtomicU64>,
+        reject: Arc<AtomicBool>,
+    }
+
+    impl Node {
+        fn start(mined: u64, pending: u64) -> Node {
+            let (mined, pending) = (
+                Arc::new(AtomicU64::new(mined)),
+                Arc::new(AtomicU64::new(pending)),
+            );
+            let reject = Arc::new(AtomicBool::new(false));
+            let (counts, rejecting) = ((mined.clone(), pending.clone()), reject.clone());
+            let mock = MockRpc::start(move |method, params| match method {
+                "eth_getTransactionCount" => {
+                    let count = match params[1].as_str() {
+                        Some("pending") => &counts.1,
+                        _ => &counts.0,
+                    };
+                    Ok(json!(U256::from(count.load(Ordering::SeqCst))))
+                }
+                "eth_chainId" => Ok(json!("0x1")),
+                "eth_blockNumber" => Ok(json!("0x64")),
+                "eth_sendRawTransaction" if rejecting.load(Ordering::SeqCst) => {
+                    Err("nonce too low".into())
+                }
+                "eth_sendRawTransaction" => {
+                    let raw: Bytes = serde_json::from_value(params[0].clone()).unwrap();
+                    Ok(json!(H256(keccak256(&raw.0))))
+                }
+                _ => Err(format!("unexpected {}", method)),
+            });
+            Node {
+                mock,
+                mined,
+                pending,
+                reject,
+            }
+        }
+
+        fn web3(&self) -> Web3<FailoverHttp> {
+            Web3::new(FailoverHttp::new(std::slice::from_ref(&self.mock.url)).unwrap())
+        }
+    }
+
+    fn key() -> SecretKey {
+        SecretKey::from_slice(&[1; 32]).unwrap()
+    }
+
+    fn fee(gwei: u64) -> FeeQuote {
+        FeeQuote {
+            base_fee: Some(U256::exp10(9)),
+            max_priority_fee: U256::exp10(9) * gwei,
+            max_fee: U256::exp10(9) * gwei * 3,
+        }
+    }
+
+    fn transfer() -> TransactionParameters {
+        TransactionParameters {
+            to: Some(Address::repeat_byte(0x22)),
+            gas: 50_000.into(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_first_use_syncs_once() {
+        let node = Node::start(3, 5);
+        let web3 = node.web3();
+        let nonces = NonceManager::new(Address::repeat_byte(0x11));
+
+        let (a, b) = tokio::join!(nonces.reserve(&web3), nonces.reserve(&web3));
+        let mut reserved = [a.unwrap(), b.unwrap()];
+        reserved.sort();
+        assert_eq!(reserved, [U256::from(5), U256::from(6)]);
+        assert_eq!(node.mock.calls("eth_getTransactionCount").len(), 2);
+        assert_eq!(nonces.reserve(&web3).await.unwrap(), 7.into());
+    }
+
+    #[tokio::test]
+    async fn speed_up_and_cancel_reuse_the_nonce() {
+        let node = Node::start(0, 0);
+        let web3 = node.web3();
+        let address = Address::repeat_byte(0x11);
+        let nonces = NonceManager::new(address);
+
+        let first = nonces
+            .send(&web3, transfer(), &fee(2), &key())
+            .await
+            .unwrap();
+        let second = nonces
+            .send(&web3, transfer(), &fee(2), &key())
+            .await
+            .unwrap();
+        node.pending.store(2, Ordering::SeqCst);
+
+        let sped_up = nonces
+            .speed_up(&web3, 0.into(), &fee(2), None, &key())
+            .await
+            .unwrap();
+        assert_ne!(sped_up, first);
+        let cancelled = nonces
+            .cancel(&web3, 1.into(), &fee(1), None, &key())
+            .await
+            .unwrap();
+        assert_ne!(cancelled, second);
+        assert_eq!(node.mock.calls("eth_sendRawTransaction").len(), 4);
+
+        let pending = nonces.prune(&web3).await.unwrap();
+        assert_eq!(pending.len(), 2);
+        assert_eq!(
+            (pending[0].nonce, pending[0].hash, pending[0].replacements),
+            (0.into(), sped_up, 1)
+        );
+        // 12% over the fees it replaces.
+        assert_eq!(pending[0].fee.max_fee, U256::from(6_720_000_000u64));
+        assert_eq!(
+            pending[0].fee.max_priority_fee,
+            U256::from(2_240_000_000u64)
+        );
+        assert_eq!(pending[0].tx, transfer());
+        assert!(pending[1].cancelled);
+        assert_eq!(pending[1].hash, cancelled);
+        assert_eq!(pending[1].tx.to, Some(address));
+        assert_eq!(pending[1].tx.gas, CANCEL_GAS.into());
+        assert!(pending[1].tx.data.0.is_empty());
+
+        // Over the cap nothing is sent.
+        assert!(nonces
+            .speed_up(&web3, 0.into(), &fee(2), Some(U256::exp10(9) * 7), &key())
+            .await
+            .is_err());
+        assert!(nonces
+            .speed_up(&web3, 9.into(), &fee(2), None, &key())
+            .await
+            .is_err());
+        assert_eq!(node.mock.calls("eth_sendRawTransaction").len(), 4);
+
+        // Mined transactions are forgotten.
+        node.mined.store(1, Ordering::SeqCst);
+        let pending = nonces.prune(&web3).await.unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].nonce, 1.into());
+    }
+
+    #[tokio::test]
+    async fn failed_send_resyncs() {
+        let node = Node::start(4, 4);
+        let web3 = node.web3();
+        let nonces = NonceManager::new(Address::repeat_byte(0x11));
+        assert_eq!(nonces.reserve(&web3).await.unwrap(), 4.into());
+
+        // Another client used nonces 4 and 5 meanwhile.
+        node.mined.store(6, Ordering::SeqCst);
+        node.pending.store(6, Ordering::SeqCst);
+        node.reject.store(true, Ordering::SeqCst);
+        assert!(matches!(
+            nonces.send(&web3, transfer(), &fee(2), &key()).await,
+            Err(Error::Execution(_))
+        ));
+        assert!(nonces.prune(&web3).await.unwrap().is_empty());
+
+        node.reject.store(false, Ordering::SeqCst);
+        nonces
+            .send(&web3, transfer(), &fee(2), &key())
+            .await
+            .unwrap();
+        let pending = nonces.prune(&web3).await.unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].nonce, 6.into());
+    }
+}
@@ -0,0 +1,87 @@
+use crate::{fixed_point::from_token_units, v3_pool::SwapSimulation};
+use std::fmt;
+
+/// Where the value of a round trip goes. Every amount is in whole master
+/// tokens so the parts add up: `net = gross - pool_fees - gas_cost - slippage_buffer`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ProfitBreakdown {
+    pub route: String,
+    pub amount_in: f64,
+    pub amount_out: f64,
+    /// Profit the price difference alone would yield, before pool fees.
+    pub gross_profit: f64,
+    pub pool_fees: f64,
+    pub gas_cost: f64,
+    pub slippage_buffer: f64,
+    pub net_profit: f64,
+}
+
+impl ProfitBreakdown {
+    /// Builds the breakdown for a simulated route whose first leg takes the
+    /// master token in and whose last leg pays it back out. `flash_fee_pips`
+    /// is the fee of the pool the input is borrowed from, 0 if it is not.
+    pub fn new(
+        route: String,
+        legs: &[SwapSimulation],
+        master_decimals: u8,
+        flash_fee_pips: u32,
+        gas_cost_in_master: f64,
+        slippage_percent: f64,
+    ) -> ProfitBreakdown {
+        let amount_in = legs
+            .first()
+            .map_or(0.0, |leg| from_token_units(leg.amount_in, master_decimals));
+        let amount_out = legs
+            .last()
+            .map_or(0.0, |leg| from_token_units(leg.amount_out, master_decimals));
+
+        let fee_free_fraction: f64 = legs
+            .iter()
+            .map(|leg| 1.0 - leg.effective_fee_percent() / 100.0)
+            .product();
+        let amount_out_before_fees = if fee_free_fraction > 0.0 {
+            amount_out / fee_free_fraction
+        } else {
+            amount_out
+        };
+
+        let flash_fee = amount_in * flash_fee_pips as f64 / 1e6;
+
+        let gross_profit = amount_out_before_fees - amount_in;
+        let pool_fees = amount_out_before_fees - amount_out + flash_fee;
+        let slippage_buffer = amount_out * slippage_percent / 100.0;
+        let net_profit = gross_profit - pool_fees - gas_cost_in_master - slippage_buffer;
+
+        ProfitBreakdown {
+            route,
+            amount_in,
+            amount_out,
+            gross_profit,
+            pool_fees,
+            gas_cost: gas_cost_in_master,
+            slippage_buffer,
+            net_profit,
+        }
+    }
+
+    pub fn is_profitable(&self, minimum_profit: f64) -> bool {
+        self.net_profit > 0.0 && self.net_profit >= minimum_profit
+    }
+}
+
+impl fmt::Display for ProfitBreakdown {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: in {:.6} out {:.6} | gross {:.6} fees {:.6} gas {:.6} slippage {:.6} | net {:.6}",
+            self.route,
+            self.amount_in,
+            self.amount_out,
+            self.gross_profit,
+            self.pool_fees,
+            self.gas_cost,
+            self.slippage_buffer,
+            self.net_profit
+        )
+    }
+}
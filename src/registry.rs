@@ -0,0 +1,122 @@
+use crate::{dex::Protocol, error, error::Error, v2_pair::DEFAULT_V2_FEE, Chain};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, path::Path};
+use web3::types::{Address, H256};
+
+/// Contracts of the major DEXes on every supported chain, compiled in so a
+/// fresh install can pick them from Settings.
+const BUILTIN_REGISTRY: &str = include_str!("../registry.json");
+/// Optional file in the same layout as `registry.json` whose chains are
+/// merged over the built-in ones.
+pub const USER_REGISTRY_PATH: &str = "user_registry.json";
+
+/// Known contracts, keyed by chain.
+pub type Registry = BTreeMap<Chain, ChainInfo>;
+
+/// What is known about one chain. In the user registry every field may be
+/// left out to keep the built-in value.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct ChainInfo {
+    /// EIP-155 id, as `eth_chainId` answers.
+    #[serde(default)]
+    pub chain_id: u64,
+    /// Token gas is paid in, wrapped (WETH, WBNB, WMATIC).
+    #[serde(default)]
+    pub wrapped_native: String,
+    /// Block explorer transactions are linked to, without a trailing `/tx`.
+    #[serde(default)]
+    pub explorer_url: String,
+    #[serde(default)]
+    pub dexes: Vec<KnownDex>,
+}
+
+/// A DEX deployment. Routers and quoters are listed for reference; the bot
+/// only needs the factory.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct KnownDex {
+    pub name: String,
+    pub protocol: Protocol,
+    pub factory: String,
+    #[serde(default)]
+    pub router: String,
+    #[serde(default)]
+    pub quoter: String,
+    /// Swap fee of a V2 fork in hundredths of a basis point. V3 pools carry
+    /// their own.
+    #[serde(default = "default_v2_fee")]
+    pub fee: u32,
+}
+
+fn default_v2_fee() -> u32 {
+    DEFAULT_V2_FEE
+}
+
+/// The built-in registry with `user_path` merged over it when it exists.
+pub fn load(user_path: &str) -> error::Result<Registry> {
+    let mut registry = parse("the built-in registry", BUILTIN_REGISTRY)?;
+    if Path::new(user_path).exists() {
+        let data = fs::read_to_string(user_path)
+            .map_err(|e| Error::Config(format!("Failed to load {}: {}", user_path, e)))?;
+        for (chain, info) in parse(user_path, &data)? {
+            registry.entry(chain).or_default().merge(info);
+        }
+    }
+    Ok(registry)
+}
+
+fn parse(source: &str, data: &str) -> error::Result<Registry> {
+    let registry: Registry = serde_json::from_str(data)
+        .map_err(|e| Error::Config(format!("Failed to load {}: {}", source, e)))?;
+    for (chain, info) in &registry {
+        let addresses = info.dexes.iter().flat_map(|dex| {
+            [
+                (&dex.name, "factory", &dex.factory),
+                (&dex.name, "router", &dex.router),
+                (&dex.name, "quoter", &dex.quoter),
+            ]
+        });
+        let wrapped_native = String::from("wrapped native");
+        for (owner, field, address) in
+            addresses.chain([(&wrapped_native, "address", &info.wrapped_native)])
+        {
+            if !address.is_empty() && address.parse::<Address>().is_err() {
+                return Err(Error::Config(format!(
+                    "{}: the {} {} {} of {} is not an address",
+                    source, chain, owner, field, address
+                )));
+            }
+        }
+    }
+    Ok(registry)
+}
+
+impl ChainInfo {
+    /// Takes every field `other` sets. A DEX replaces the one of the same
+    /// name, others are added.
+    fn merge(&mut self, other: ChainInfo) {
+        if other.chain_id != 0 {
+            self.chain_id = other.chain_id;
+        }
+        if !other.wrapped_native.is_empty() {
+            self.wrapped_native = other.wrapped_native;
+        }
+        if !other.explorer_url.is_empty() {
+            self.explorer_url = other.explorer_url;
+        }
+        for dex in other.dexes {
+            match self.dexes.iter_mut().find(|known| known.name == dex.name) {
+                Some(known) => *known = dex,
+                None => self.dexes.push(dex),
+            }
+        }
+    }
+}
+
+/// Explorer page of `tx_hash`, or the bare hash without an explorer.
+pub fn tx_link(explorer_url: &str, tx_hash: H256) -> String {
+    if explorer_url.is_empty() {
+        format!("{:?}", tx_hash)
+    } else {
+        format!("{}/tx/{:?}", explorer_url.trim_end_matches('/'), tx_hash)
+    }
+}
@@ -0,0 +1,425 @@
+use crate::{
+    error::{self, Error},
+    rpc::FailoverHttp,
+};
+use serde_json::{json, Value};
+use std::{fs, path::Path, time::Duration};
+use web3::{
+    signing::{hash_message, keccak256, Key, SecretKey},
+    types::{Address, Bytes, H256, U64},
+    Web3,
+};
+
+/// Header the relay authenticates a request by, `address:signature` of the
+/// request body.
+const SIGNATURE_HEADER: &str = "X-Flashbots-Signature";
+/// Where the key requests are signed with is kept, next to the wallet
+/// keystores. The relay builds a searcher's reputation on its address.
+pub const AUTH_KEY_PATH: &str = "keystore/relay_auth_key";
+/// How often the chain is polled while waiting for a target block.
+const BLOCK_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Outcome of `eth_callBundle`, simulated on top of the latest block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BundleSimulation {
+    pub gas_used: u64,
+    /// What the bundle pays the block builder, in wei.
+    pub coinbase_diff: String,
+}
+
+/// The auth key stored at `path`, or a new one written there on first use
+/// so bundles keep being signed by the same searcher.
+pub fn load_auth_key(path: &str) -> error::Result<SecretKey> {
+    if Path::new(path).exists() {
+        let data = fs::read_to_string(path)
+            .map_err(|e| Error::Config(format!("Failed to read {}: {}", path, e)))?;
+        let bytes = hex::decode(data.trim())
+            .map_err(|e| Error::Config(format!("{} is not a hex key: {}", path, e)))?;
+        return SecretKey::from_slice(&bytes)
+            .map_err(|e| Error::Config(format!("{} holds an invalid key: {}", path, e)));
+    }
+
+    let key = loop {
+        if let Ok(key) = SecretKey::from_slice(&rand::random::<[u8; 32]>()) {
+            break key;
+        }
+    };
+    if let Some(dir) = Path::new(path).parent() {
+        fs::create_dir_all(dir)
+            .map_err(|e| Error::Config(format!("Failed to create {}: {}", dir.display(), e)))?;
+    }
+    fs::write(path, hex::encode(key.secret_bytes()))
+        .map_err(|e| Error::Config(format!("Failed to write {}: {}", path, e)))?;
+    Ok(key)
+}
+
+/// A bundle that made it into a block.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BundleInclusion {
+    pub tx_hash: H256,
+    pub block: U64,
+    /// Target blocks the bundle was sent for until it landed.
+    pub attempts: u64,
+}
+
+/// Client of a Flashbots-style relay, which keeps bundles out of the public
+/// mempool and only lands them whole. Requests are signed with an auth key
+/// that holds no funds, which the relay only uses to tell searchers apart.
+pub struct Relay {
+    url: String,
+    client: reqwest::Client,
+    auth_key: SecretKey,
+}
+
+impl Relay {
+    pub fn new(url: &str, auth_key: SecretKey) -> error::Result<Relay> {
+        Ok(Relay {
+            url: url.to_string(),
+            client: reqwest::Client::builder()
+                .build()
+                .map_err(|e| Error::Config(format!("Relay client: {}", e)))?,
+            auth_key,
+        })
+    }
+
+    /// Simulates `txs` as a bundle for `block`, failing with
+    /// `Error::Revert` when any of them reverts.
+    pub async fn call_bundle(&self, txs: &[Bytes], block: U64) -> error::Result<BundleSimulation> {
+        let result = self
+            .request(
+                "eth_callBundle",
+                json!([{
+                    "txs": txs,
+                    "blockNumber": block,
+                    "stateBlockNumber": "latest",
+                }]),
+            )
+            .await?;
+
+        for tx in result["results"].as_array().into_iter().flatten() {
+            let reason = tx["revert"].as_str().or_else(|| tx["error"].as_str());
+            if let Some(reason) = reason {
+                return Err(Error::Revert(reason.to_string()));
+            }
+        }
+        Ok(BundleSimulation {
+            gas_used: result["totalGasUsed"].as_u64().unwrap_or_default(),
+            coinbase_diff: result["coinbaseDiff"].as_str().unwrap_or("0").to_string(),
+        })
+    }
+
+    /// Hands `txs` to the relay for inclusion in `block` only, returning the
+    /// bundle hash.
+    pub async fn send_bundle(&self, txs: &[Bytes], block: U64) -> error::Result<H256> {
+        let result = self
+            .request(
+                "eth_sendBundle",
+                json!([{
+                    "txs": txs,
+                    "blockNumber": block,
+                }]),
+            )
+            .await?;
+        serde_json::from_value(result["bundleHash"].clone())
+            .map_err(|e| Error::Decode(format!("eth_sendBundle bundleHash: {}", e)))
+    }
+
+    /// Simulates the bundle, then sends it for each of the next
+    /// `target_blocks` blocks in turn until `tx_hash`, its last
+    /// transaction, has a receipt.
+    pub async fn submit(
+        &self,
+        web3: &Web3<FailoverHttp>,
+        txs: &[Bytes],
+        tx_hash: H256,
+        target_blocks: u64,
+    ) -> error::Result<BundleInclusion> {
+        let latest = web3.eth().block_number().await?;
+        let simulation = self.call_bundle(txs, latest + 1).await?;
+        println!(
+            "Bundle simulated: {} gas, {} wei to the builder",
+            simulation.gas_used, simulation.coinbase_diff
+        );
+
+        for attempt in 1..=target_blocks {
+            let target = web3.eth().block_number().await? + 1;
+            let bundle_hash = self.send_bundle(txs, target).await?;
+            println!(
+                "Bundle {:?} sent to {} for block {}",
+                bundle_hash, self.url, target
+            );
+
+            while web3.eth().block_number().await? < target {
+                tokio::time::sleep(BLOCK_POLL_INTERVAL).await;
+            }
+            if let Some(receipt) = web3.eth().transaction_receipt(tx_hash).await? {
+                return Ok(BundleInclusion {
+                    tx_hash,
+                    block: receipt.block_number.unwrap_or(target),
+                    attempts: attempt,
+                });
+            }
+            println!("Bundle {:?} not included in block {}", bundle_hash, target);
+        }
+
+        Err(Error::Execution(format!(
+            "bundle not included in {} target blocks",
+            target_blocks
+        )))
+    }
+
+    async fn request(&self, method: &str, params: Value) -> error::Result<Value> {
+        let body = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": method,
+            "params": params,
+        })
+        .to_string();
+
+        let response = self
+            .client
+            .post(&self.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(SIGNATURE_HEADER, self.signature(&body)?)
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| Error::Execution(format!("{} to {}: {}", method, self.url, e)))?;
+        let status = response.status();
+        let text = response
+            .text()
+            .await
+            .map_err(|e| Error::Execution(format!("{} to {}: {}", method, self.url, e)))?;
+        let response: Value = serde_json::from_str(&text).map_err(|_| {
+            Error::Execution(format!("{} to {}: {} {}", method, self.url, status, text))
+        })?;
+
+        if let Some(err) = response.get("error") {
+            let message = err["message"].as_str().unwrap_or("unknown error");
+            return Err(if message.contains("revert") {
+                Error::Revert(message.to_string())
+            } else {
+                Error::Execution(format!("{} rejected: {}", method, message))
+            });
+        }
+        Ok(response["result"].clone())
+    }
+
+    /// `address:signature`, the EIP-191 signature of the hex keccak hash of
+    /// `body`.
+    fn signature(&self, body: &str) -> error::Result<String> {
+        let digest = format!("0x{}", hex::encode(keccak256(body.as_bytes())));
+        let signature = (&self.auth_key)
+            .sign(hash_message(digest).as_bytes(), None)
+            .map_err(|e| Error::Execution(format!("Failed to sign relay request: {}", e)))?;
+
+        let mut bytes = [0u8; 65];
+        bytes[..32].copy_from_slice(signature.r.as_bytes());
+        bytes[32..64].copy_from_slice(signature.s.as_bytes());
+        bytes[64] = signature.v as u8;
+        Ok(format!(
+            "{:?}:0x{}",
+            self.auth_address(),
+            hex::encode(bytes)
+        ))
+    }
+
+    pub fn auth_address(&self) -> Address {
+        (&self.auth_key).address()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_rpc::MockRpc;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use web3::{signing::recover, types::TransactionReceipt};
+
+    fn relay(url: &str) -> Relay {
+        Relay::new(url, SecretKey::from_slice(&[7; 32]).unwrap()).unwrap()
+    }
+
+    fn txs() -> Vec<Bytes> {
+        vec![Bytes(vec![0x02, 0xf8, 0x71]), Bytes(vec![0xab, 0xcd])]
+    }
+
+    fn receipt(tx_hash: H256, block: u64) -> Value {
+        serde_json::to_value(TransactionReceipt {
+            transaction_hash: tx_hash,
+            block_number: Some(block.into()),
+            status: Some(1.into()),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    /// A node and relay in one whose chain advances a block on every
+    /// `eth_blockNumber`, and which includes the bundle sent for
+    /// `included_in`, if any.
+    fn chain(included_in: Option<u64>) -> MockRpc {
+        let block = AtomicU64::new(100);
+        let sent_for = AtomicU64::new(0);
+        MockRpc::start(move |method, params| match method {
+            "eth_blockNumber" => Ok(json!(U64::from(block.fetch_add(1, Ordering::SeqCst)))),
+            "eth_callBundle" => {
+                Ok(json!({ "totalGasUsed": 21000, "coinbaseDiff": "5", "results": [] }))
+            }
+            "eth_sendBundle" => {
+                let target: U64 = serde_json::from_value(params[0]["blockNumber"].clone()).unwrap();
+                sent_for.store(target.as_u64(), Ordering::SeqCst);
+                Ok(json!({ "bundleHash": H256::repeat_byte(0xbb) }))
+            }
+            "eth_getTransactionReceipt" => {
+                let target = sent_for.load(Ordering::SeqCst);
+                Ok(match included_in {
+                    Some(block) if block == target => {
+                        receipt(serde_json::from_value(params[0].clone()).unwrap(), block)
+                    }
+                    _ => Value::Null,
+                })
+            }
+            _ => Err(format!("unexpected {}", method)),
+        })
+    }
+
+    #[tokio::test]
+    async fn bundle_requests() {
+        let mock = MockRpc::start(|method, _| match method {
+            "eth_callBundle" => Ok(json!({
+                "totalGasUsed": 150000,
+                "coinbaseDiff": "42000",
+                "results": [{ "gasUsed": 150000 }],
+            })),
+            "eth_sendBundle" => Ok(json!({ "bundleHash": H256::repeat_byte(0xbb) })),
+            _ => Err(format!("unexpected {}", method)),
+        });
+        let relay = relay(&mock.url);
+
+        let simulation = relay.call_bundle(&txs(), 17.into()).await.unwrap();
+        assert_eq!(
+            simulation,
+            BundleSimulation {
+                gas_used: 150000,
+                coinbase_diff: "42000".into(),
+            }
+        );
+        assert_eq!(
+            relay.send_bundle(&txs(), 18.into()).await.unwrap(),
+            H256::repeat_byte(0xbb)
+        );
+
+        let requests = mock.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(
+            requests[0].json(),
+            json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "eth_callBundle",
+                "params": [{
+                    "txs": ["0x02f871", "0xabcd"],
+                    "blockNumber": "0x11",
+                    "stateBlockNumber": "latest",
+                }],
+            })
+        );
+        assert_eq!(
+            requests[1].json(),
+            json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "eth_sendBundle",
+                "params": [{ "txs": ["0x02f871", "0xabcd"], "blockNumber": "0x12" }],
+            })
+        );
+
+        // `address:signature` of the keccak hash of the exact body sent.
+        for request in requests {
+            let header = &request.headers[&SIGNATURE_HEADER.to_lowercase()];
+            let (address, signature) = header.split_once(':').unwrap();
+            assert_eq!(address, format!("{:?}", relay.auth_address()));
+            let signature = hex::decode(signature.trim_start_matches("0x")).unwrap();
+            assert_eq!(signature.len(), 65);
+            let digest = format!("0x{}", hex::encode(keccak256(request.body.as_bytes())));
+            let signer = recover(
+                hash_message(digest).as_bytes(),
+                &signature[..64],
+                signature[64] as i32 - 27,
+            )
+            .unwrap();
+            assert_eq!(signer, relay.auth_address());
+        }
+    }
+
+    #[tokio::test]
+    async fn reverting_bundles_are_errors() {
+        let mock = MockRpc::start(|method, _| match method {
+            "eth_callBundle" => Ok(json!({
+                "totalGasUsed": 90000,
+                "results": [{ "gasUsed": 21000 }, { "revert": "profit below minimum" }],
+            })),
+            _ => Err("execution reverted: nonce too low".into()),
+        });
+        let relay = relay(&mock.url);
+
+        match relay.call_bundle(&txs(), 17.into()).await {
+            Err(Error::Revert(reason)) => assert_eq!(reason, "profit below minimum"),
+            other => panic!("expected a revert, got {:?}", other.map(|_| ())),
+        }
+        match relay.send_bundle(&txs(), 17.into()).await {
+            Err(Error::Revert(reason)) => assert_eq!(reason, "execution reverted: nonce too low"),
+            other => panic!("expected a revert, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[tokio::test]
+    async fn submit_stops_once_included() {
+        let mock = chain(Some(104));
+        let web3 = Web3::new(FailoverHttp::new(std::slice::from_ref(&mock.url)).unwrap());
+        let tx_hash = H256::repeat_byte(0x11);
+
+        let inclusion = relay(&mock.url)
+            .submit(&web3, &txs(), tx_hash, 5)
+            .await
+            .unwrap();
+        assert_eq!(
+            inclusion,
+            BundleInclusion {
+                tx_hash,
+                block: 104.into(),
+                attempts: 2,
+            }
+        );
+        assert_eq!(mock.calls("eth_callBundle").len(), 1);
+        let targets: Vec<Value> = mock
+            .calls("eth_sendBundle")
+            .iter()
+            .map(|params| params[0]["blockNumber"].clone())
+            .collect();
+        assert_eq!(targets, [json!("0x66"), json!("0x68")]);
+    }
+
+    #[tokio::test]
+    async fn submit_gives_up_after_the_target_blocks() {
+        let mock = chain(None);
+        let web3 = Web3::new(FailoverHttp::new(std::slice::from_ref(&mock.url)).unwrap());
+
+        let result = relay(&mock.url)
+            .submit(&web3, &txs(), H256::repeat_byte(0x11), 3)
+            .await;
+        assert!(matches!(result, Err(Error::Execution(_))));
+        assert_eq!(mock.calls("eth_sendBundle").len(), 3);
+        assert_eq!(mock.calls("eth_getTransactionReceipt").len(), 3);
+    }
+
+    #[test]
+    fn auth_key_is_kept() {
+        let dir = std::env::temp_dir().join(format!("relay-auth-{}", std::process::id()));
+        let path = dir.join("auth_key").to_string_lossy().into_owned();
+        let key = load_auth_key(&path).unwrap();
+        assert_eq!(load_auth_key(&path).unwrap(), key);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
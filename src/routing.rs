@@ -0,0 +1,255 @@
+use crate::discovery::{pool_label, DiscoveredPool};
+use std::collections::HashMap;
+use web3::types::Address;
+
+/// A cycle through the pool graph: the pools to swap through in order and
+/// the token held before each swap, ending back in the starting token.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Route {
+    pub pools: Vec<DiscoveredPool>,
+    pub tokens: Vec<Address>,
+}
+
+impl Route {
+    /// Readable form such as `master -0.3%-> 0xb260…1363 -0.05%-> master`,
+    /// naming tokens from `labels` where possible.
+    pub fn describe(&self, labels: &HashMap<Address, String>) -> String {
+        let label = |token: &Address| {
+            labels
+                .get(token)
+                .cloned()
+                .unwrap_or_else(|| format!("{:?}", token))
+        };
+
+        let mut description = label(&self.tokens[0]);
+        for (pool, token) in self.pools.iter().zip(&self.tokens[1..]) {
+            description.push_str(&format!(
+                " -{}-> {}",
+                pool_label(pool.protocol, pool.fee),
+                label(token)
+            ));
+        }
+        description
+    }
+}
+
+/// Tokens connected by the pools that trade them. Several pools may join
+/// the same two tokens, one per fee tier and exchange.
+pub struct PoolGraph {
+    edges: HashMap<Address, Vec<DiscoveredPool>>,
+}
+
+impl PoolGraph {
+    pub fn new(pools: &[DiscoveredPool]) -> PoolGraph {
+        let mut edges: HashMap<Address, Vec<DiscoveredPool>> = HashMap::new();
+        for pool in pools {
+            edges.entry(pool.token0).or_default().push(*pool);
+            edges.entry(pool.token1).or_default().push(*pool);
+        }
+        PoolGraph { edges }
+    }
+
+    /// Every cycle of two to `max_hops` swaps that starts and ends in
+    /// `start`. A route never uses a pool twice or passes through an
+    /// intermediate token twice.
+    pub fn cycles_from(&self, start: Address, max_hops: usize) -> Vec<Route> {
+        let mut routes = Vec::new();
+        let mut route = Route {
+            pools: Vec::new(),
+            tokens: vec![start],
+        };
+        self.extend_cycles(start, max_hops, &mut route, &mut routes);
+        routes
+    }
+
+    fn extend_cycles(
+        &self,
+        start: Address,
+        max_hops: usize,
+        route: &mut Route,
+        routes: &mut Vec<Route>,
+    ) {
+        let token = route.tokens[route.tokens.len() - 1];
+        for pool in self.edges.get(&token).into_iter().flatten() {
+            if route.pools.iter().any(|used| used.address == pool.address) {
+                continue;
+            }
+
+            let next = pool.other_token(token);
+            if next == start {
+                let mut cycle = route.clone();
+                cycle.pools.push(*pool);
+                cycle.tokens.push(next);
+                routes.push(cycle);
+                continue;
+            }
+            // Going on to `next` only pays off if there is a hop left to
+            // come back.
+            if route.tokens.contains(&next) || route.pools.len() + 2 > max_hops {
+                continue;
+            }
+
+            route.pools.push(*pool);
+            route.tokens.push(next);
+            self.extend_cycles(start, max_hops, route, routes);
+            route.pools.pop();
+            route.tokens.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        dex::{simulate_route, Pool, Protocol},
+        v2_pair::PairState,
+    };
+    use web3::types::U256;
+
+    fn token(byte: u8) -> Address {
+        Address::repeat_byte(byte)
+    }
+
+    const MASTER: u8 = 1;
+
+    /// A V2 pair `number` between two tokens, holding `reserve0` and
+    /// `reserve1` whole tokens.
+    fn pair(number: u8, tokens: (u8, u8), fee: u32, reserves: (u64, u64)) -> PairState {
+        let ether = |amount: u64| U256::from(amount) * U256::exp10(18);
+        PairState {
+            address: Address::from_low_u64_be(number as u64),
+            token0: token(tokens.0),
+            token1: token(tokens.1),
+            fee,
+            reserve0: ether(reserves.0),
+            reserve1: ether(reserves.1),
+        }
+    }
+
+    /// Master (1) trades against A (2) in two pairs, the 0.05% one with A
+    /// 10% cheaper, and in a triangle through B (3). B also trades against
+    /// C (4), which leads nowhere.
+    fn pairs() -> Vec<PairState> {
+        vec![
+            pair(1, (1, 2), 3000, (1000, 1000)),
+            pair(2, (1, 2), 500, (1000, 1100)),
+            pair(3, (2, 3), 3000, (1000, 1000)),
+            pair(4, (1, 3), 3000, (1000, 1000)),
+            pair(5, (3, 4), 3000, (1000, 1000)),
+        ]
+    }
+
+    fn graph(pairs: &[PairState]) -> PoolGraph {
+        let pools: Vec<DiscoveredPool> = pairs
+            .iter()
+            .map(|pair| DiscoveredPool {
+                protocol: Protocol::UniswapV2,
+                address: pair.address,
+                token0: pair.token0,
+                token1: pair.token1,
+                fee: pair.fee,
+                liquidity: 0,
+                sqrt_price_x96: U256::zero(),
+            })
+            .collect();
+        PoolGraph::new(&pools)
+    }
+
+    fn pool_numbers(route: &Route) -> Vec<u64> {
+        route
+            .pools
+            .iter()
+            .map(|pool| pool.address.to_low_u64_be())
+            .collect()
+    }
+
+    #[test]
+    fn cycles_start_and_end_on_master_without_reusing_pools() {
+        let routes = graph(&pairs()).cycles_from(token(MASTER), 3);
+        let mut found: Vec<Vec<u64>> = routes.iter().map(pool_numbers).collect();
+        found.sort();
+        assert_eq!(
+            found,
+            [
+                vec![1, 2],
+                vec![1, 3, 4],
+                vec![2, 1],
+                vec![2, 3, 4],
+                vec![4, 3, 1],
+                vec![4, 3, 2],
+            ]
+        );
+
+        for route in &routes {
+            assert_eq!(route.tokens.len(), route.pools.len() + 1);
+            assert_eq!(route.tokens.first(), Some(&token(MASTER)));
+            assert_eq!(route.tokens.last(), Some(&token(MASTER)));
+            // Every hop goes through the pool between its two tokens.
+            for (pool, hop) in route.pools.iter().zip(route.tokens.windows(2)) {
+                assert_eq!(pool.other_token(hop[0]), hop[1]);
+            }
+            let mut intermediate = route.tokens[1..route.tokens.len() - 1].to_vec();
+            intermediate.sort();
+            intermediate.dedup();
+            assert_eq!(intermediate.len(), route.tokens.len() - 2);
+        }
+    }
+
+    #[test]
+    fn max_hops_bounds_the_routes() {
+        let graph = graph(&pairs());
+        let two_hops: Vec<Vec<u64>> = graph
+            .cycles_from(token(MASTER), 2)
+            .iter()
+            .map(pool_numbers)
+            .collect();
+        assert_eq!(two_hops.len(), 2);
+        assert!(two_hops.iter().all(|pools| pools.len() == 2));
+
+        assert_eq!(graph.cycles_from(token(MASTER), 4).len(), 6);
+        assert!(graph.cycles_from(token(MASTER), 1).is_empty());
+        // No cycle comes back to the dead end.
+        assert!(graph.cycles_from(token(4), 3).is_empty());
+        assert!(graph.cycles_from(token(9), 3).is_empty());
+    }
+
+    #[test]
+    fn routes_rank_by_simulated_profit() {
+        let pairs = pairs();
+        let states: HashMap<Address, Pool> = pairs
+            .iter()
+            .map(|pair| (pair.address, Pool::V2(pair.clone())))
+            .collect();
+        let amount_in = U256::exp10(18);
+
+        let mut ranked: Vec<(Vec<u64>, f64)> = graph(&pairs)
+            .cycles_from(token(MASTER), 3)
+            .iter()
+            .map(|route| {
+                let pools: Vec<&Pool> = route
+                    .pools
+                    .iter()
+                    .map(|pool| &states[&pool.address])
+                    .collect();
+                let legs = simulate_route(&pools, token(MASTER), amount_in).unwrap();
+                let out = legs.last().unwrap().amount_out;
+                (pool_numbers(route), (out.as_u128() as f64 - 1e18) / 1e18)
+            })
+            .collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        let order: Vec<&[u64]> = ranked.iter().map(|(pools, _)| pools.as_slice()).collect();
+        // Buying A where it is cheap pays, and fewer hops pay fewer fees.
+        assert_eq!(order[..2], [&[2, 1][..], &[2, 3, 4][..]]);
+        assert!(ranked[1].1 > 0.08);
+        // The fair triangle loses its fees, buying A where it is dear loses
+        // the whole spread.
+        assert!(ranked[2..4]
+            .iter()
+            .all(|(_, profit)| (-0.02..0.0).contains(profit)));
+        assert!(ranked[4..]
+            .iter()
+            .all(|(pools, profit)| pools.contains(&2) && *profit < -0.09));
+    }
+}
@@ -0,0 +1,244 @@
+use futures::future::{join_all, BoxFuture};
+use jsonrpc_core::{Call, Value};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use web3::{error::TransportError, helpers, transports::Http, Error, RequestId, Transport};
+
+/// How long a health check waits for `eth_blockNumber` before counting the
+/// endpoint as down.
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+struct Endpoint {
+    url: String,
+    transport: Http,
+    healthy: bool,
+    /// Round trip of the last successful health check.
+    latency: Option<Duration>,
+}
+
+impl Endpoint {
+    /// Healthy endpoints first, fastest first. Endpoints that have not been
+    /// checked yet keep their configured order behind measured ones.
+    fn rank(&self) -> (bool, Duration) {
+        (!self.healthy, self.latency.unwrap_or(Duration::MAX))
+    }
+}
+
+/// Health of one endpoint as of the last check.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EndpointStatus {
+    pub url: String,
+    pub healthy: bool,
+    pub latency: Option<Duration>,
+}
+
+/// HTTP transport over several RPC endpoints of one chain. Each call goes to
+/// the best ranked endpoint and moves on to the next one when it fails with a
+/// transport error. JSON-RPC errors are returned as they are, since another
+/// node would give the same answer.
+#[derive(Debug, Clone)]
+pub struct FailoverHttp {
+    endpoints: Arc<Mutex<Vec<Endpoint>>>,
+    id: Arc<AtomicUsize>,
+}
+
+impl FailoverHttp {
+    pub fn new(urls: &[String]) -> web3::Result<FailoverHttp> {
+        if urls.is_empty() {
+            return Err(Error::Transport(TransportError::Message(
+                "No RPC endpoints configured".into(),
+            )));
+        }
+
+        let endpoints = urls
+            .iter()
+            .map(|url| {
+                Ok(Endpoint {
+                    url: url.clone(),
+                    transport: Http::new(url)?,
+                    healthy: true,
+                    latency: None,
+                })
+            })
+            .collect::<web3::Result<Vec<Endpoint>>>()?;
+
+        Ok(FailoverHttp {
+            endpoints: Arc::new(Mutex::new(endpoints)),
+            id: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    /// Times `eth_blockNumber` on every endpoint and ranks them by latency.
+    /// Endpoints that fail or time out are only tried once all others have
+    /// failed.
+    pub async fn check_health(&self) -> Vec<EndpointStatus> {
+        let candidates = self.ranked();
+        let latencies = join_all(candidates.iter().map(|(_, transport)| async move {
+            let started = Instant::now();
+            match tokio::time::timeout(
+                HEALTH_CHECK_TIMEOUT,
+                transport.execute("eth_blockNumber", vec![]),
+            )
+            .await
+            {
+                Ok(Ok(_)) => Some(started.elapsed()),
+                _ => None,
+            }
+        }))
+        .await;
+
+        let mut endpoints = self.endpoints.lock().unwrap();
+        for ((url, _), latency) in candidates.iter().zip(latencies) {
+            if let Some(endpoint) = endpoints.iter_mut().find(|endpoint| &endpoint.url == url) {
+                endpoint.healthy = latency.is_some();
+                endpoint.latency = latency;
+            }
+        }
+        endpoints.sort_by_key(Endpoint::rank);
+
+        endpoints
+            .iter()
+            .map(|endpoint| EndpointStatus {
+                url: endpoint.url.clone(),
+                healthy: endpoint.healthy,
+                latency: endpoint.latency,
+            })
+            .collect()
+    }
+
+    fn ranked(&self) -> Vec<(String, Http)> {
+        self.endpoints
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|endpoint| (endpoint.url.clone(), endpoint.transport.clone()))
+            .collect()
+    }
+
+    fn mark_down(&self, url: &str) {
+        let mut endpoints = self.endpoints.lock().unwrap();
+        if let Some(endpoint) = endpoints.iter_mut().find(|endpoint| endpoint.url == url) {
+            endpoint.healthy = false;
+            endpoint.latency = None;
+        }
+        endpoints.sort_by_key(Endpoint::rank);
+    }
+}
+
+impl Transport for FailoverHttp {
+    type Out = BoxFuture<'static, web3::Result<Value>>;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+        let id = self.id.fetch_add(1, Ordering::AcqRel);
+        (id, helpers::build_request(id, method, params))
+    }
+
+    fn send(&self, id: RequestId, request: Call) -> Self::Out {
+        let failover = self.clone();
+        Box::pin(async move {
+            let mut last_error = Error::Unreachable;
+            for (url, transport) in failover.ranked() {
+                match transport.send(id, request.clone()).await {
+                    Err(err @ (Error::Transport(_) | Error::Unreachable)) => {
+                        eprintln!("RPC endpoint {} failed: {}", url, err);
+                        failover.mark_down(&url);
+                        last_error = err;
+                    }
+                    result => return result,
+                }
+            }
+            Err(last_error)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_rpc::{dead_url, MockRpc};
+    use serde_json::json;
+
+    /// A node answering every call with block 100, after `delay`.
+    fn node(delay: Duration) -> MockRpc {
+        MockRpc::start(move |method, _| {
+            std::thread::sleep(delay);
+            match method {
+                "eth_blockNumber" => Ok(json!("0x64")),
+                _ => Err("execution reverted".into()),
+            }
+        })
+    }
+
+    fn healthy(statuses: &[EndpointStatus]) -> Vec<(String, bool)> {
+        statuses
+            .iter()
+            .map(|status| (status.url.clone(), status.healthy))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn fails_over_on_transport_errors() {
+        let (dead, live) = (dead_url(), node(Duration::ZERO));
+        let transport = FailoverHttp::new(&[dead.clone(), live.url.clone()]).unwrap();
+
+        let web3 = web3::Web3::new(transport.clone());
+        assert_eq!(web3.eth().block_number().await.unwrap(), 100.into());
+        assert_eq!(live.calls("eth_blockNumber").len(), 1);
+        // The dead endpoint is now tried last.
+        assert_eq!(transport.ranked()[0].0, live.url);
+
+        // A JSON-RPC error is the node's answer, not a reason to move on.
+        let backup = node(Duration::ZERO);
+        let transport = FailoverHttp::new(&[live.url.clone(), backup.url.clone()]).unwrap();
+        let web3 = web3::Web3::new(transport.clone());
+        assert!(matches!(web3.eth().gas_price().await, Err(Error::Rpc(_))));
+        assert!(backup.requests().is_empty());
+        assert_eq!(transport.ranked()[0].0, live.url);
+
+        // With every endpoint down the last error comes back.
+        let transport = FailoverHttp::new(&[dead_url(), dead_url()]).unwrap();
+        assert!(matches!(
+            web3::Web3::new(transport).eth().block_number().await,
+            Err(Error::Transport(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn ranks_by_latency() {
+        let (dead, slow, fast) = (
+            dead_url(),
+            node(Duration::from_millis(200)),
+            node(Duration::ZERO),
+        );
+        let transport =
+            FailoverHttp::new(&[dead.clone(), slow.url.clone(), fast.url.clone()]).unwrap();
+
+        let statuses = transport.check_health().await;
+        assert_eq!(
+            healthy(&statuses),
+            [
+                (fast.url.clone(), true),
+                (slow.url.clone(), true),
+                (dead, false)
+            ]
+        );
+        assert!(statuses[0].latency < statuses[1].latency);
+        assert_eq!(statuses[2].latency, None);
+
+        let web3 = web3::Web3::new(transport);
+        web3.eth().block_number().await.unwrap();
+        assert_eq!(fast.calls("eth_blockNumber").len(), 2);
+        assert_eq!(slow.calls("eth_blockNumber").len(), 1);
+    }
+
+    #[test]
+    fn needs_an_endpoint() {
+        assert!(FailoverHttp::new(&[]).is_err());
+    }
+}
@@ -0,0 +1,125 @@
+use crate::{
+    discovery::load_abi,
+    error::{self, Error},
+};
+use futures::StreamExt;
+use web3::{
+    api::SubscriptionStream,
+    error::TransportError,
+    transports::WebSocket,
+    types::{Address, BlockHeader, FilterBuilder, Log, H256, U256, U64},
+    Web3,
+};
+
+/// Push notifications over a WebSocket connection: `newHeads` plus the
+/// `Swap` logs of the pools the last evaluation routed through, so the bot
+/// only re-evaluates when one of them trades.
+pub struct PoolWatcher {
+    web3: Web3<WebSocket>,
+    /// `Swap` of V3 pools and of V2 pairs, which differ.
+    swap_topics: Vec<H256>,
+    heads: SubscriptionStream<WebSocket, BlockHeader>,
+    swaps: Option<SubscriptionStream<WebSocket, Log>>,
+    pools: Vec<Address>,
+    last_change: Option<U64>,
+}
+
+impl PoolWatcher {
+    /// Connects to `url` and subscribes to new heads. Fails with a config
+    /// error when the node serves another chain than `chain_id`, whose blocks
+    /// would say nothing about the pools being traded.
+    pub async fn connect(url: &str, chain_id: u64) -> error::Result<PoolWatcher> {
+        let swap_topics = vec![
+            load_abi("./pool_abi.json")?.event("Swap")?.signature(),
+            load_abi("./v2_pair_abi.json")?.event("Swap")?.signature(),
+        ];
+
+        let web3 = Web3::new(WebSocket::new(url).await?);
+        let served = web3.eth().chain_id().await?;
+        if served != U256::from(chain_id) {
+            return Err(Error::Config(format!(
+                "{} serves chain id {}, but the chain is configured with {}",
+                url, served, chain_id
+            )));
+        }
+        let heads = web3.eth_subscribe().subscribe_new_heads().await?;
+
+        Ok(PoolWatcher {
+            web3,
+            swap_topics,
+            heads,
+            swaps: None,
+            pools: Vec::new(),
+            last_change: None,
+        })
+    }
+
+    /// Subscribes to `Swap` logs of `pools`, replacing the previous
+    /// subscription if the set changed.
+    pub async fn watch(&mut self, pools: &[Address]) -> error::Result<()> {
+        let mut pools = pools.to_vec();
+        pools.sort_unstable();
+        pools.dedup();
+        if pools == self.pools {
+            return Ok(());
+        }
+
+        if let Some(swaps) = self.swaps.take() {
+            // The node drops the subscription with the connection anyway.
+            let _ = swaps.unsubscribe().await;
+        }
+        if !pools.is_empty() {
+            let filter = FilterBuilder::default()
+                .address(pools.clone())
+                .topics(Some(self.swap_topics.clone()), None, None, None)
+                .build();
+            self.swaps = Some(self.web3.eth_subscribe().subscribe_logs(filter).await?);
+        }
+        println!("Watching {} pools for swaps", pools.len());
+        self.pools = pools;
+        Ok(())
+    }
+
+    /// Waits for the next block in which a watched pool traded and returns
+    /// its number. While no pools are watched every new block counts.
+    pub async fn next_change(&mut self) -> error::Result<U64> {
+        loop {
+            let block = tokio::select! {
+                head = self.heads.next() => {
+                    let head = head.ok_or_else(subscription_closed)??;
+                    if !self.pools.is_empty() {
+                        continue;
+                    }
+                    head.number
+                }
+                swap = next_log(&mut self.swaps) => {
+                    swap.ok_or_else(subscription_closed)??.block_number
+                }
+            };
+
+            // A block with several swaps produces one change.
+            if let Some(block) = block {
+                if self.last_change.is_none_or(|last| block > last) {
+                    self.last_change = Some(block);
+                    return Ok(block);
+                }
+            }
+        }
+    }
+}
+
+/// Next log of `swaps`, never resolving while there is no subscription.
+async fn next_log(
+    swaps: &mut Option<SubscriptionStream<WebSocket, Log>>,
+) -> Option<web3::Result<Log>> {
+    match swaps {
+        Some(swaps) => swaps.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+fn subscription_closed() -> Error {
+    Error::Rpc(web3::Error::Transport(TransportError::Message(
+        "WebSocket subscription closed".into(),
+    )))
+}
@@ -0,0 +1,258 @@
+use crate::{
+    discovery::load_abi,
+    error,
+    fixed_point::sqrt_price_x96_from_reserves,
+    rpc::FailoverHttp,
+    v3_math::{get_tick_at_sqrt_ratio, mul_div, FEE_DENOMINATOR, MAX_SQRT_RATIO, MIN_SQRT_RATIO},
+    v3_pool::SwapSimulation,
+};
+use serde::{Deserialize, Serialize};
+use web3::{
+    contract::{Contract, Options},
+    types::{Address, U256},
+    Web3,
+};
+
+/// Fee of Uniswap V2 and most of its forks, 0.3% in hundredths of a basis
+/// point.
+pub const DEFAULT_V2_FEE: u32 = 3000;
+
+/// Snapshot of a Uniswap V2-style constant-product pair. Its whole state is
+/// the two reserves, so a swap is priced exactly from them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PairState {
+    pub address: Address,
+    pub token0: Address,
+    pub token1: Address,
+    /// Swap fee in hundredths of a basis point, set by the factory's fork.
+    pub fee: u32,
+    pub reserve0: U256,
+    pub reserve1: U256,
+}
+
+impl PairState {
+    /// Reads `token0`, `token1` and `getReserves` from the pair. Pairs do not
+    /// know their fee, so it comes from the factory it was found on.
+    pub async fn fetch(
+        web3: &Web3<FailoverHttp>,
+        pair_address: Address,
+        fee: u32,
+    ) -> error::Result<PairState> {
+        let pair_contract =
+            Contract::new(web3.eth(), pair_address, load_abi("./v2_pair_abi.json")?);
+
+        let token0: Address = pair_contract
+            .query("token0", (), None, Options::default(), None)
+            .await?;
+        let token1: Address = pair_contract
+            .query("token1", (), None, Options::default(), None)
+            .await?;
+        let (reserve0, reserve1, _): (U256, U256, u32) = pair_contract
+            .query("getReserves", (), None, Options::default(), None)
+            .await?;
+
+        Ok(PairState {
+            address: pair_address,
+            token0,
+            token1,
+            fee,
+            reserve0,
+            reserve1,
+        })
+    }
+
+    /// Direction of a swap that pays `token_in` into the pair.
+    pub fn zero_for_one(&self, token_in: Address) -> Result<bool, &'static str> {
+        if token_in == self.token0 {
+            Ok(true)
+        } else if token_in == self.token1 {
+            Ok(false)
+        } else {
+            Err("token is not part of the pair")
+        }
+    }
+
+    /// Simulates selling `amount_in` of `token_in` with the pair's
+    /// `getAmountOut` formula.
+    pub fn simulate_exact_input(
+        &self,
+        token_in: Address,
+        amount_in: U256,
+    ) -> Result<SwapSimulation, &'static str> {
+        let zero_for_one = self.zero_for_one(token_in)?;
+        let (reserve_in, reserve_out) = if zero_for_one {
+            (self.reserve0, self.reserve1)
+        } else {
+            (self.reserve1, self.reserve0)
+        };
+        let amount_out = get_amount_out(amount_in, reserve_in, reserve_out, self.fee)?;
+
+        let (reserve0, reserve1) = if zero_for_one {
+            (self.reserve0 + amount_in, self.reserve1 - amount_out)
+        } else {
+            (self.reserve0 - amount_out, self.reserve1 + amount_in)
+        };
+        let sqrt_price_x96_after = sqrt_price_x96_from_reserves(reserve0, reserve1)?
+            .clamp(MIN_SQRT_RATIO, MAX_SQRT_RATIO - 1);
+
+        Ok(SwapSimulation {
+            zero_for_one,
+            amount_in,
+            amount_out,
+            fee_amount: mul_div(amount_in, U256::from(self.fee), U256::from(FEE_DENOMINATOR))?,
+            sqrt_price_x96_after,
+            tick_after: get_tick_at_sqrt_ratio(sqrt_price_x96_after)?,
+            liquidity_after: (reserve0 * reserve1).integer_sqrt().low_u128(),
+            ticks_crossed: 0,
+            amount_remaining: U256::zero(),
+        })
+    }
+
+    /// Applies the result of a simulated swap, as if it had been sent.
+    pub fn apply_swap(&mut self, simulation: &SwapSimulation) {
+        if simulation.zero_for_one {
+            self.reserve0 += simulation.amount_in;
+            self.reserve1 -= simulation.amount_out;
+        } else {
+            self.reserve1 += simulation.amount_in;
+            self.reserve0 -= simulation.amount_out;
+        }
+    }
+}
+
+/// `UniswapV2Library.getAmountOut` with the fee in hundredths of a basis
+/// point instead of the hardcoded 997/1000.
+pub fn get_amount_out(
+    amount_in: U256,
+    reserve_in: U256,
+    reserve_out: U256,
+    fee: u32,
+) -> Result<U256, &'static str> {
+    if amount_in.is_zero() {
+        return Err("swap amount is zero");
+    }
+    if reserve_in.is_zero() || reserve_out.is_zero() {
+        return Err("pair has no liquidity");
+    }
+    if fee >= FEE_DENOMINATOR {
+        return Err("fee is 100% or more");
+    }
+    let amount_in_with_fee = amount_in
+        .checked_mul(U256::from(FEE_DENOMINATOR - fee))
+        .ok_or("swap amount overflows")?;
+    let denominator = reserve_in
+        .checked_mul(U256::from(FEE_DENOMINATOR))
+        .and_then(|reserve| reserve.checked_add(amount_in_with_fee))
+        .ok_or("swap amount overflows")?;
+    mul_div(amount_in_with_fee, reserve_out, denominator)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const E18: u64 = 1_000_000_000_000_000_000;
+
+    fn u256(value: &str) -> U256 {
+        U256::from_dec_str(value).unwrap()
+    }
+
+    fn ether(amount: u64) -> U256 {
+        U256::from(amount) * E18
+    }
+
+    /// Swap cases of Uniswap's `UniswapV2Pair` tests, where the output is
+    /// `UniswapV2Library.getAmountOut` exactly.
+    #[test]
+    fn uniswap_v2_outputs() {
+        for (amount_in, reserve_in, reserve_out, expected) in [
+            (1, 5, 10, "1662497915624478906"),
+            (1, 10, 5, "453305446940074565"),
+            (2, 5, 10, "2851015155847869602"),
+            (2, 10, 5, "831248957812239453"),
+            (1, 10, 10, "906610893880149131"),
+            (1, 100, 100, "987158034397061298"),
+            (1, 1000, 1000, "996006981039903216"),
+        ] {
+            assert_eq!(
+                get_amount_out(
+                    ether(amount_in),
+                    ether(reserve_in),
+                    ether(reserve_out),
+                    DEFAULT_V2_FEE
+                )
+                .unwrap(),
+                u256(expected)
+            );
+        }
+        // `UniswapV2Router02` tests.
+        assert_eq!(
+            get_amount_out(2.into(), 100.into(), 100.into(), DEFAULT_V2_FEE).unwrap(),
+            1.into()
+        );
+    }
+
+    /// PancakeSwap's `getAmountOut`, 9975/10000, is a fee of 2500.
+    #[test]
+    fn other_fees() {
+        assert_eq!(
+            get_amount_out(ether(1), ether(5), ether(10), 2500).unwrap(),
+            u256("1663192997082117548")
+        );
+        assert_eq!(
+            get_amount_out(ether(1), ether(100), ether(100), 2500).unwrap(),
+            u256("987648209114086982")
+        );
+        assert_eq!(
+            get_amount_out(ether(1), ether(100), ether(100), 0).unwrap(),
+            u256("990099009900990099")
+        );
+        assert!(get_amount_out(ether(1), ether(100), ether(100), FEE_DENOMINATOR).is_err());
+        assert!(get_amount_out(U256::zero(), ether(100), ether(100), DEFAULT_V2_FEE).is_err());
+        assert!(get_amount_out(ether(1), U256::zero(), ether(100), DEFAULT_V2_FEE).is_err());
+    }
+
+    /// `FlashArbitrageExecutor._swapV2` outputs for 3.14 token0 or token1
+    /// into a 1000 / 2500000 pair, worked out in exact integer arithmetic.
+    #[test]
+    fn simulation_matches_the_contract() {
+        for (fee, out0, out1) in [
+            (DEFAULT_V2_FEE, "7805970080348769918363", "1252865580577479"),
+            (2500, "7809872580162045714475", "1253493897533406"),
+            (10_000, "7751333799647448528328", "1244069143111599"),
+        ] {
+            let mut pair = PairState {
+                address: Address::repeat_byte(0xaa),
+                token0: Address::repeat_byte(0x01),
+                token1: Address::repeat_byte(0x02),
+                fee,
+                reserve0: ether(1000),
+                reserve1: ether(2_500_000),
+            };
+            let amount_in = u256("3141592653589793238");
+
+            let sell0 = pair.simulate_exact_input(pair.token0, amount_in).unwrap();
+            assert!(sell0.zero_for_one);
+            assert!(sell0.is_complete());
+            assert_eq!(sell0.amount_out, u256(out0));
+
+            let sell1 = pair.simulate_exact_input(pair.token1, amount_in).unwrap();
+            assert!(!sell1.zero_for_one);
+            assert_eq!(sell1.amount_out, u256(out1));
+
+            let (reserve0, reserve1) = (pair.reserve0, pair.reserve1);
+            pair.apply_swap(&sell0);
+            assert_eq!(pair.reserve0, reserve0 + amount_in);
+            assert_eq!(pair.reserve1, reserve1 - sell0.amount_out);
+            // Selling pushes the price of token0 down.
+            assert!(
+                sell0.sqrt_price_x96_after
+                    < sqrt_price_x96_from_reserves(reserve0, reserve1).unwrap()
+            );
+
+            assert!(pair
+                .simulate_exact_input(Address::repeat_byte(0x03), amount_in)
+                .is_err());
+        }
+    }
+}
@@ -0,0 +1,679 @@
+use std::collections::HashMap;
+use web3::types::U256;
+
+pub const MIN_TICK: i32 = -887272;
+pub const MAX_TICK: i32 = 887272;
+
+/// `getSqrtRatioAtTick(MIN_TICK)`
+pub const MIN_SQRT_RATIO: U256 = U256([4295128739, 0, 0, 0]);
+/// `getSqrtRatioAtTick(MAX_TICK)`
+pub const MAX_SQRT_RATIO: U256 = U256([0x5d951d5263988d26, 0xefd1fc6a50648849, 0xfffd8963, 0]);
+
+/// 2^96
+pub const Q96: U256 = U256([0, 1 << 32, 0, 0]);
+
+/// Pool fees are counted in millionths, hundredths of a basis point.
+pub const FEE_DENOMINATOR: u32 = 1_000_000;
+
+/// Magic numbers from Uniswap's `TickMath`, one per bit of the absolute tick
+/// starting at 0x2. Each is `1 / sqrt(1.0001)^(2^i)` as a Q128.128.
+const TICK_RATIOS: [u128; 19] = [
+    0xfff97272373d413259a46990580e213a,
+    0xfff2e50f5f656932ef12357cf3c7fdcc,
+    0xffe5caca7e10e4e61c3624eaa0941cd0,
+    0xffcb9843d60f6159c9db58835c926644,
+    0xff973b41fa98c081472e6896dfb254c0,
+    0xff2ea16466c96a3843ec78b326b52861,
+    0xfe5dee046a99a2a811c461f1969c3053,
+    0xfcbe86c7900a88aedcffc83b479aa3a4,
+    0xf987a7253ac413176f2b074cf7815e54,
+    0xf3392b0822b70005940c7a398e4b70f3,
+    0xe7159475a2c29b7443b29c7fa6e889d9,
+    0xd097f3bdfd2022b8845ad8f792aa5825,
+    0xa9f746462d870fdf8a65dc1f90e061e5,
+    0x70d869a156d2a1b890bb3df62baf32f7,
+    0x31be135f97d08fd981231505542fcfa6,
+    0x9aa508b5b7a84e1c677de54f3e99bc9,
+    0x5d6af8dedb81196699c329225ee604,
+    0x2216e584f5fa1ea926041bedfe98,
+    0x48a170391f7dc42444e8fa2,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SwapStep {
+    pub sqrt_price_next_x96: U256,
+    pub amount_in: U256,
+    pub amount_out: U256,
+    pub fee_amount: U256,
+}
+
+/// `floor(a * b / denominator)` with a 512-bit intermediate product.
+pub fn mul_div(a: U256, b: U256, denominator: U256) -> Result<U256, &'static str> {
+    if denominator.is_zero() {
+        return Err("mulDiv by zero");
+    }
+    U256::try_from(a.full_mul(b) / denominator).map_err(|_| "mulDiv overflow")
+}
+
+/// `ceil(a * b / denominator)` with a 512-bit intermediate product.
+pub fn mul_div_rounding_up(a: U256, b: U256, denominator: U256) -> Result<U256, &'static str> {
+    let result = mul_div(a, b, denominator)?;
+    if (a.full_mul(b) % denominator).is_zero() {
+        Ok(result)
+    } else {
+        result.checked_add(U256::one()).ok_or("mulDiv overflow")
+    }
+}
+
+pub fn div_rounding_up(x: U256, y: U256) -> U256 {
+    let (quotient, remainder) = x.div_mod(y);
+    if remainder.is_zero() {
+        quotient
+    } else {
+        quotient + 1
+    }
+}
+
+fn to_uint160(value: U256) -> Result<U256, &'static str> {
+    if value.bits() > 160 {
+        return Err("sqrt price overflows uint160");
+    }
+    Ok(value)
+}
+
+pub fn get_sqrt_ratio_at_tick(tick: i32) -> Result<U256, &'static str> {
+    let abs_tick = tick.unsigned_abs();
+    if abs_tick > MAX_TICK as u32 {
+        return Err("tick out of range");
+    }
+
+    let mut ratio: U256 = if abs_tick & 0x1 != 0 {
+        U256::from(0xfffcb933bd6fad37aa2d162d1a594001u128)
+    } else {
+        U256::one() << 128
+    };
+    for (i, magic) in TICK_RATIOS.iter().enumerate() {
+        if abs_tick & (0x2 << i) != 0 {
+            ratio = (ratio * U256::from(*magic)) >> 128;
+        }
+    }
+
+    if tick > 0 {
+        ratio = U256::MAX / ratio;
+    }
+
+    let round_up = if ratio.low_u32() == 0 { 0 } else { 1 };
+    Ok((ratio >> 32) + round_up)
+}
+
+/// Greatest tick whose sqrt ratio is less than or equal to `sqrt_price_x96`.
+pub fn get_tick_at_sqrt_ratio(sqrt_price_x96: U256) -> Result<i32, &'static str> {
+    if sqrt_price_x96 < MIN_SQRT_RATIO || sqrt_price_x96 >= MAX_SQRT_RATIO {
+        return Err("sqrt price out of range");
+    }
+
+    // Start from a floating point estimate and walk to the exact tick, which is
+    // at most a couple of steps away.
+    let ratio = u256_as_f64(sqrt_price_x96) / u256_as_f64(Q96);
+    let estimate = (2.0 * ratio.ln() / 1.0001_f64.ln()).floor() as i32;
+    let mut tick = estimate.clamp(MIN_TICK, MAX_TICK - 1);
+
+    while tick > MIN_TICK && get_sqrt_ratio_at_tick(tick)? > sqrt_price_x96 {
+        tick -= 1;
+    }
+    while tick < MAX_TICK - 1 && get_sqrt_ratio_at_tick(tick + 1)? <= sqrt_price_x96 {
+        tick += 1;
+    }
+
+    Ok(tick)
+}
+
+/// Nearest `f64` to `value`, keeping all 256 bits of magnitude.
+pub fn u256_as_f64(value: U256) -> f64 {
+    value
+        .0
+        .iter()
+        .rev()
+        .fold(0.0, |acc, limb| acc * 2_f64.powi(64) + *limb as f64)
+}
+
+pub fn get_next_sqrt_price_from_amount0_rounding_up(
+    sqrt_price_x96: U256,
+    liquidity: u128,
+    amount: U256,
+    add: bool,
+) -> Result<U256, &'static str> {
+    if amount.is_zero() {
+        return Ok(sqrt_price_x96);
+    }
+    let numerator1 = U256::from(liquidity) << 96;
+    let (product, product_overflow) = amount.overflowing_mul(sqrt_price_x96);
+
+    if add {
+        if !product_overflow {
+            let (denominator, denominator_overflow) = numerator1.overflowing_add(product);
+            if !denominator_overflow {
+                return mul_div_rounding_up(numerator1, sqrt_price_x96, denominator);
+            }
+        }
+        let denominator = (numerator1 / sqrt_price_x96)
+            .checked_add(amount)
+            .ok_or("amount0 overflow")?;
+        Ok(div_rounding_up(numerator1, denominator))
+    } else {
+        if product_overflow || numerator1 <= product {
+            return Err("insufficient liquidity for amount0 out");
+        }
+        to_uint160(mul_div_rounding_up(
+            numerator1,
+            sqrt_price_x96,
+            numerator1 - product,
+        )?)
+    }
+}
+
+pub fn get_next_sqrt_price_from_amount1_rounding_down(
+    sqrt_price_x96: U256,
+    liquidity: u128,
+    amount: U256,
+    add: bool,
+) -> Result<U256, &'static str> {
+    let liquidity = U256::from(liquidity);
+    let fits_in_uint160 = amount.bits() <= 160;
+
+    if add {
+        let quotient = if fits_in_uint160 {
+            (amount << 96) / liquidity
+        } else {
+            mul_div(amount, Q96, liquidity)?
+        };
+        to_uint160(
+            sqrt_price_x96
+                .checked_add(quotient)
+                .ok_or("sqrt price overflow")?,
+        )
+    } else {
+        let quotient = if fits_in_uint160 {
+            div_rounding_up(amount << 96, liquidity)
+        } else {
+            mul_div_rounding_up(amount, Q96, liquidity)?
+        };
+        if sqrt_price_x96 <= quotient {
+            return Err("insufficient liquidity for amount1 out");
+        }
+        Ok(sqrt_price_x96 - quotient)
+    }
+}
+
+pub fn get_next_sqrt_price_from_input(
+    sqrt_price_x96: U256,
+    liquidity: u128,
+    amount_in: U256,
+    zero_for_one: bool,
+) -> Result<U256, &'static str> {
+    if sqrt_price_x96.is_zero() || liquidity == 0 {
+        return Err("pool has no liquidity");
+    }
+    if zero_for_one {
+        get_next_sqrt_price_from_amount0_rounding_up(sqrt_price_x96, liquidity, amount_in, true)
+    } else {
+        get_next_sqrt_price_from_amount1_rounding_down(sqrt_price_x96, liquidity, amount_in, true)
+    }
+}
+
+pub fn get_next_sqrt_price_from_output(
+    sqrt_price_x96: U256,
+    liquidity: u128,
+    amount_out: U256,
+    zero_for_one: bool,
+) -> Result<U256, &'static str> {
+    if sqrt_price_x96.is_zero() || liquidity == 0 {
+        return Err("pool has no liquidity");
+    }
+    if zero_for_one {
+        get_next_sqrt_price_from_amount1_rounding_down(sqrt_price_x96, liquidity, amount_out, false)
+    } else {
+        get_next_sqrt_price_from_amount0_rounding_up(sqrt_price_x96, liquidity, amount_out, false)
+    }
+}
+
+pub fn get_amount0_delta(
+    sqrt_ratio_a_x96: U256,
+    sqrt_ratio_b_x96: U256,
+    liquidity: u128,
+    round_up: bool,
+) -> Result<U256, &'static str> {
+    let (lower, upper) = if sqrt_ratio_a_x96 > sqrt_ratio_b_x96 {
+        (sqrt_ratio_b_x96, sqrt_ratio_a_x96)
+    } else {
+        (sqrt_ratio_a_x96, sqrt_ratio_b_x96)
+    };
+    if lower.is_zero() {
+        return Err("sqrt price is zero");
+    }
+
+    let numerator1 = U256::from(liquidity) << 96;
+    let numerator2 = upper - lower;
+
+    if round_up {
+        Ok(div_rounding_up(
+            mul_div_rounding_up(numerator1, numerator2, upper)?,
+            lower,
+        ))
+    } else {
+        Ok(mul_div(numerator1, numerator2, upper)? / lower)
+    }
+}
+
+pub fn get_amount1_delta(
+    sqrt_ratio_a_x96: U256,
+    sqrt_ratio_b_x96: U256,
+    liquidity: u128,
+    round_up: bool,
+) -> Result<U256, &'static str> {
+    let (lower, upper) = if sqrt_ratio_a_x96 > sqrt_ratio_b_x96 {
+        (sqrt_ratio_b_x96, sqrt_ratio_a_x96)
+    } else {
+        (sqrt_ratio_a_x96, sqrt_ratio_b_x96)
+    };
+
+    if round_up {
+        mul_div_rounding_up(U256::from(liquidity), upper - lower, Q96)
+    } else {
+        mul_div(U256::from(liquidity), upper - lower, Q96)
+    }
+}
+
+/// Port of `SwapMath.computeSwapStep`. `amount_remaining` is the unsigned
+/// amount still to be swapped, interpreted as input when `exact_input` is set
+/// and as output otherwise.
+pub fn compute_swap_step(
+    sqrt_ratio_current_x96: U256,
+    sqrt_ratio_target_x96: U256,
+    liquidity: u128,
+    amount_remaining: U256,
+    exact_input: bool,
+    fee_pips: u32,
+) -> Result<SwapStep, &'static str> {
+    let zero_for_one = sqrt_ratio_current_x96 >= sqrt_ratio_target_x96;
+    let fee = U256::from(fee_pips);
+    let fee_complement = U256::from(FEE_DENOMINATOR - fee_pips);
+
+    let mut amount_in = U256::zero();
+    let mut amount_out = U256::zero();
+    let sqrt_ratio_next_x96: U256;
+
+    if exact_input {
+        let amount_remaining_less_fee = mul_div(
+            amount_remaining,
+            fee_complement,
+            U256::from(FEE_DENOMINATOR),
+        )?;
+        amount_in = if zero_for_one {
+            get_amount0_delta(
+                sqrt_ratio_target_x96,
+                sqrt_ratio_current_x96,
+                liquidity,
+                true,
+            )?
+        } else {
+            get_amount1_delta(
+                sqrt_ratio_current_x96,
+                sqrt_ratio_target_x96,
+                liquidity,
+                true,
+            )?
+        };
+        sqrt_ratio_next_x96 = if amount_remaining_less_fee >= amount_in {
+            sqrt_ratio_target_x96
+        } else {
+            get_next_sqrt_price_from_input(
+                sqrt_ratio_current_x96,
+                liquidity,
+                amount_remaining_less_fee,
+                zero_for_one,
+            )?
+        };
+    } else {
+        amount_out = if zero_for_one {
+            get_amount1_delta(
+                sqrt_ratio_target_x96,
+                sqrt_ratio_current_x96,
+                liquidity,
+                false,
+            )?
+        } else {
+            get_amount0_delta(
+                sqrt_ratio_current_x96,
+                sqrt_ratio_target_x96,
+                liquidity,
+                false,
+            )?
+        };
+        sqrt_ratio_next_x96 = if amount_remaining >= amount_out {
+            sqrt_ratio_target_x96
+        } else {
+            get_next_sqrt_price_from_output(
+                sqrt_ratio_current_x96,
+                liquidity,
+                amount_remaining,
+                zero_for_one,
+            )?
+        };
+    }
+
+    let max = sqrt_ratio_target_x96 == sqrt_ratio_next_x96;
+
+    if zero_for_one {
+        if !max || !exact_input {
+            amount_in =
+                get_amount0_delta(sqrt_ratio_next_x96, sqrt_ratio_current_x96, liquidity, true)?;
+        }
+        if !max || exact_input {
+            amount_out = get_amount1_delta(
+                sqrt_ratio_next_x96,
+                sqrt_ratio_current_x96,
+                liquidity,
+                false,
+            )?;
+        }
+    } else {
+        if !max || !exact_input {
+            amount_in =
+                get_amount1_delta(sqrt_ratio_current_x96, sqrt_ratio_next_x96, liquidity, true)?;
+        }
+        if !max || exact_input {
+            amount_out = get_amount0_delta(
+                sqrt_ratio_current_x96,
+                sqrt_ratio_next_x96,
+                liquidity,
+                false,
+            )?;
+        }
+    }
+
+    if !exact_input && amount_out > amount_remaining {
+        amount_out = amount_remaining;
+    }
+
+    let fee_amount = if exact_input && sqrt_ratio_next_x96 != sqrt_ratio_target_x96 {
+        amount_remaining - amount_in
+    } else {
+        mul_div_rounding_up(amount_in, fee, fee_complement)?
+    };
+
+    Ok(SwapStep {
+        sqrt_price_next_x96: sqrt_ratio_next_x96,
+        amount_in,
+        amount_out,
+        fee_amount,
+    })
+}
+
+/// Word index and bit index of a compressed tick in the pool's `tickBitmap`.
+pub fn tick_bitmap_position(compressed_tick: i32) -> (i16, u32) {
+    (
+        (compressed_tick >> 8) as i16,
+        compressed_tick.rem_euclid(256) as u32,
+    )
+}
+
+pub fn compress_tick(tick: i32, tick_spacing: i32) -> i32 {
+    tick.div_euclid(tick_spacing)
+}
+
+/// Port of `TickBitmap.nextInitializedTickWithinOneWord` over a partial copy
+/// of the bitmap. Returns `None` if the word that would be searched was not
+/// loaded.
+pub fn next_initialized_tick_within_one_word(
+    tick_bitmap: &HashMap<i16, U256>,
+    tick: i32,
+    tick_spacing: i32,
+    lte: bool,
+) -> Option<(i32, bool)> {
+    let compressed = compress_tick(tick, tick_spacing);
+
+    if lte {
+        let (word_pos, bit_pos) = tick_bitmap_position(compressed);
+        let mask = (U256::one() << bit_pos) - 1 + (U256::one() << bit_pos);
+        let masked = *tick_bitmap.get(&word_pos)? & mask;
+        let initialized = !masked.is_zero();
+        let next = if initialized {
+            let most_significant_bit = masked.bits() as i32 - 1;
+            (compressed - (bit_pos as i32 - most_significant_bit)) * tick_spacing
+        } else {
+            (compressed - bit_pos as i32) * tick_spacing
+        };
+        Some((next, initialized))
+    } else {
+        let (word_pos, bit_pos) = tick_bitmap_position(compressed + 1);
+        let mask = !((U256::one() << bit_pos) - 1);
+        let masked = *tick_bitmap.get(&word_pos)? & mask;
+        let initialized = !masked.is_zero();
+        let next = if initialized {
+            let least_significant_bit = masked.trailing_zeros() as i32;
+            (compressed + 1 + (least_significant_bit - bit_pos as i32)) * tick_spacing
+        } else {
+            (compressed + 1 + (255 - bit_pos as i32)) * tick_spacing
+        };
+        Some((next, initialized))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u256(value: &str) -> U256 {
+        U256::from_dec_str(value).unwrap()
+    }
+
+    /// Uniswap's `encodePriceSqrt(reserve1, reserve0)` test helper.
+    fn encode_price_sqrt(reserve1: u64, reserve0: u64) -> U256 {
+        ((U256::from(reserve1) << 192) / reserve0).integer_sqrt()
+    }
+
+    /// `TickMath` values from Uniswap's tests.
+    #[test]
+    fn known_ticks() {
+        assert_eq!(get_sqrt_ratio_at_tick(0).unwrap(), Q96);
+        assert_eq!(get_sqrt_ratio_at_tick(MIN_TICK).unwrap(), MIN_SQRT_RATIO);
+        assert_eq!(get_sqrt_ratio_at_tick(MAX_TICK).unwrap(), MAX_SQRT_RATIO);
+        assert_eq!(
+            get_sqrt_ratio_at_tick(MIN_TICK + 1).unwrap(),
+            u256("4295343490")
+        );
+        assert_eq!(
+            get_sqrt_ratio_at_tick(MAX_TICK - 1).unwrap(),
+            u256("1461373636630004318706518188784493106690254656249")
+        );
+        assert!(get_sqrt_ratio_at_tick(MIN_TICK - 1).is_err());
+        assert!(get_sqrt_ratio_at_tick(MAX_TICK + 1).is_err());
+
+        assert_eq!(get_tick_at_sqrt_ratio(MIN_SQRT_RATIO).unwrap(), MIN_TICK);
+        assert_eq!(
+            get_tick_at_sqrt_ratio(u256("4295343490")).unwrap(),
+            MIN_TICK + 1
+        );
+        assert_eq!(
+            get_tick_at_sqrt_ratio(u256("1461373636630004318706518188784493106690254656249"))
+                .unwrap(),
+            MAX_TICK - 1
+        );
+        assert_eq!(
+            get_tick_at_sqrt_ratio(MAX_SQRT_RATIO - 1).unwrap(),
+            MAX_TICK - 1
+        );
+        assert!(get_tick_at_sqrt_ratio(MIN_SQRT_RATIO - 1).is_err());
+        assert!(get_tick_at_sqrt_ratio(MAX_SQRT_RATIO).is_err());
+        assert_eq!(
+            get_tick_at_sqrt_ratio(encode_price_sqrt(121, 100)).unwrap(),
+            1906
+        );
+    }
+
+    /// `SwapMath.computeSwapStep` cases from Uniswap's tests, as
+    /// (amount in, amount out, fee) with the next price checked separately.
+    #[test]
+    fn swap_steps() {
+        let price = encode_price_sqrt(1, 1);
+        let liquidity = 2 * 10u128.pow(18);
+        let amount = U256::exp10(18);
+
+        // Exact input stopped by the price target, one for zero.
+        let target = encode_price_sqrt(101, 100);
+        let step = compute_swap_step(price, target, liquidity, amount, true, 600).unwrap();
+        assert_eq!(step.amount_in, u256("9975124224178055"));
+        assert_eq!(step.amount_out, u256("9925619580021728"));
+        assert_eq!(step.fee_amount, u256("5988667735148"));
+        assert_eq!(step.sqrt_price_next_x96, target);
+        assert!(step.amount_in + step.fee_amount < amount);
+
+        // Exact output stopped by the same target gives the same amounts.
+        let step = compute_swap_step(price, target, liquidity, amount, false, 600).unwrap();
+        assert_eq!(step.amount_in, u256("9975124224178055"));
+        assert_eq!(step.amount_out, u256("9925619580021728"));
+        assert_eq!(step.fee_amount, u256("5988667735148"));
+        assert_eq!(step.sqrt_price_next_x96, target);
+
+        // Exact input spent before the target.
+        let target = encode_price_sqrt(1000, 100);
+        let step = compute_swap_step(price, target, liquidity, amount, true, 600).unwrap();
+        assert_eq!(step.amount_in, u256("999400000000000000"));
+        assert_eq!(step.amount_out, u256("666399946655997866"));
+        assert_eq!(step.fee_amount, u256("600000000000000"));
+        assert_eq!(step.amount_in + step.fee_amount, amount);
+        assert!(step.sqrt_price_next_x96 < target);
+
+        // Exact output received before the target.
+        let step = compute_swap_step(price, target, liquidity, amount, false, 600).unwrap();
+        assert_eq!(step.amount_in, u256("2000000000000000000"));
+        assert_eq!(step.amount_out, amount);
+        assert_eq!(step.fee_amount, u256("1200720432259356"));
+        assert!(step.sqrt_price_next_x96 < target);
+
+        // Amount out is capped at what was asked for.
+        let step = compute_swap_step(
+            u256("417332158212080721273783715441582"),
+            u256("1452870262520218020823638996"),
+            159344665391607089467575320103,
+            U256::one(),
+            false,
+            1,
+        )
+        .unwrap();
+        assert_eq!(step.amount_in, U256::one());
+        assert_eq!(step.amount_out, U256::one());
+        assert_eq!(step.fee_amount, U256::one());
+        assert_eq!(
+            step.sqrt_price_next_x96,
+            u256("417332158212080721273783715441581")
+        );
+
+        // A target price of 1 uses part of the input.
+        let step = compute_swap_step(
+            U256::from(2),
+            U256::one(),
+            1,
+            u256("3915081100057732413702495386755767"),
+            true,
+            1,
+        )
+        .unwrap();
+        assert_eq!(step.amount_in, u256("39614081257132168796771975168"));
+        assert_eq!(step.amount_out, U256::zero());
+        assert_eq!(step.fee_amount, u256("39614120871253040049813"));
+        assert_eq!(step.sqrt_price_next_x96, U256::one());
+
+        // The whole input is taken as fee.
+        let step = compute_swap_step(
+            U256::from(2413),
+            u256("79887613182836312"),
+            1985041575832132834610021537970,
+            U256::from(10),
+            true,
+            1872,
+        )
+        .unwrap();
+        assert_eq!(step.amount_in, U256::zero());
+        assert_eq!(step.amount_out, U256::zero());
+        assert_eq!(step.fee_amount, U256::from(10));
+        assert_eq!(step.sqrt_price_next_x96, U256::from(2413));
+
+        // Too little liquidity for the output before the target, either way.
+        let price = u256("20282409603651670423947251286016");
+        let target = price * 11 / 10;
+        let step = compute_swap_step(price, target, 1024, U256::from(4), false, 3000).unwrap();
+        assert_eq!(step.amount_in, U256::from(26215));
+        assert_eq!(step.amount_out, U256::zero());
+        assert_eq!(step.fee_amount, U256::from(79));
+        assert_eq!(step.sqrt_price_next_x96, target);
+
+        let target = price * 9 / 10;
+        let step = compute_swap_step(price, target, 1024, U256::from(263000), false, 3000).unwrap();
+        assert_eq!(step.amount_in, U256::one());
+        assert_eq!(step.amount_out, U256::from(26214));
+        assert_eq!(step.fee_amount, U256::one());
+        assert_eq!(step.sqrt_price_next_x96, target);
+    }
+
+    /// Bitmap of Uniswap's `TickBitmap` tests, at a tick spacing of 1,
+    /// with every word from -2 to 3 loaded.
+    fn tick_bitmap(ticks: &[i32]) -> HashMap<i16, U256> {
+        let mut bitmap: HashMap<i16, U256> = (-2..=3).map(|word| (word, U256::zero())).collect();
+        for tick in ticks {
+            let (word_pos, bit_pos) = tick_bitmap_position(*tick);
+            *bitmap.get_mut(&word_pos).unwrap() |= U256::one() << bit_pos;
+        }
+        bitmap
+    }
+
+    #[test]
+    fn bitmap_lookups() {
+        let bitmap = tick_bitmap(&[-200, -55, -4, 70, 78, 84, 139, 240, 535]);
+        let next = |tick, lte| next_initialized_tick_within_one_word(&bitmap, tick, 1, lte);
+
+        // Searching up, to the right of `tick`.
+        assert_eq!(next(78, false), Some((84, true)));
+        assert_eq!(next(-55, false), Some((-4, true)));
+        assert_eq!(next(77, false), Some((78, true)));
+        assert_eq!(next(-56, false), Some((-55, true)));
+        assert_eq!(next(255, false), Some((511, false)));
+        assert_eq!(next(-257, false), Some((-200, true)));
+        assert_eq!(next(340, false), Some((511, false)));
+        assert_eq!(next(508, false), Some((511, false)));
+        assert_eq!(next(383, false), Some((511, false)));
+
+        // Searching down, at or to the left of `tick`.
+        assert_eq!(next(78, true), Some((78, true)));
+        assert_eq!(next(79, true), Some((78, true)));
+        assert_eq!(next(258, true), Some((256, false)));
+        assert_eq!(next(256, true), Some((256, false)));
+        assert_eq!(next(72, true), Some((70, true)));
+        assert_eq!(next(-257, true), Some((-512, false)));
+        assert_eq!(next(1023, true), Some((768, false)));
+        assert_eq!(next(900, true), Some((768, false)));
+
+        let bitmap = tick_bitmap(&[-200, -55, -4, 70, 78, 84, 139, 240, 329, 535]);
+        assert_eq!(
+            next_initialized_tick_within_one_word(&bitmap, 456, 1, true),
+            Some((329, true))
+        );
+
+        // Words that were not fetched are not guessed at.
+        assert_eq!(next(1024, true), None);
+        assert_eq!(next(-513, true), None);
+    }
+
+    /// Compressed ticks round towards negative infinity, as in Solidity
+    /// when the pool adjusts for a remainder.
+    #[test]
+    fn compressed_ticks() {
+        assert_eq!(compress_tick(-1, 60), -1);
+        assert_eq!(compress_tick(-60, 60), -1);
+        assert_eq!(compress_tick(-61, 60), -2);
+        assert_eq!(compress_tick(59, 60), 0);
+        assert_eq!(tick_bitmap_position(-1), (-1, 255));
+        assert_eq!(tick_bitmap_position(256), (1, 0));
+    }
+}
@@ -0,0 +1,548 @@
+use crate::{
+    discovery::load_abi,
+    error,
+    rpc::FailoverHttp,
+    v3_math::{
+        compress_tick, compute_swap_step, get_sqrt_ratio_at_tick, get_tick_at_sqrt_ratio,
+        next_initialized_tick_within_one_word, tick_bitmap_position, u256_as_f64, MAX_SQRT_RATIO,
+        MAX_TICK, MIN_SQRT_RATIO, MIN_TICK,
+    },
+};
+use futures::future::try_join_all;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use web3::{
+    contract::{Contract, Options},
+    types::{Address, U256},
+    Web3,
+};
+
+/// Largest `amountSpecified` either way, which `swap` takes as an `int256`
+/// that is positive for an exact input and negative for an exact output.
+pub const MAX_AMOUNT_SPECIFIED: U256 = U256([u64::MAX, u64::MAX, u64::MAX, i64::MAX as u64]);
+
+/// Output of the pool's `ticks(int24)` getter: liquidityGross, liquidityNet,
+/// both fee growth accumulators, tickCumulativeOutside,
+/// secondsPerLiquidityOutsideX128, secondsOutside and initialized.
+type TickInfo = (u128, i128, U256, U256, i64, U256, u32, bool);
+
+/// Snapshot of the parts of a Uniswap V3 pool needed to replay `swap`
+/// off-chain. Only the bitmap words that were fetched are known, so a
+/// simulation that walks past them stops early.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PoolState {
+    pub address: Address,
+    pub token0: Address,
+    pub token1: Address,
+    pub fee: u32,
+    pub tick_spacing: i32,
+    pub sqrt_price_x96: U256,
+    pub tick: i32,
+    pub liquidity: u128,
+    pub tick_bitmap: HashMap<i16, U256>,
+    pub liquidity_net: HashMap<i32, i128>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SwapSimulation {
+    pub zero_for_one: bool,
+    /// Total paid into the pool, fees included.
+    pub amount_in: U256,
+    pub amount_out: U256,
+    pub fee_amount: U256,
+    pub sqrt_price_x96_after: U256,
+    pub tick_after: i32,
+    pub liquidity_after: u128,
+    pub ticks_crossed: u32,
+    /// Part of the requested amount that could not be filled, either because
+    /// the price limit was hit or because the walk left the fetched bitmap.
+    pub amount_remaining: U256,
+}
+
+/// State change announced by one of the pool's `Swap`, `Mint` or `Burn`
+/// events.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum PoolEvent {
+    Swap {
+        sqrt_price_x96: U256,
+        liquidity: u128,
+        tick: i32,
+    },
+    Mint {
+        tick_lower: i32,
+        tick_upper: i32,
+        amount: u128,
+    },
+    Burn {
+        tick_lower: i32,
+        tick_upper: i32,
+        amount: u128,
+    },
+}
+
+impl SwapSimulation {
+    /// Fee paid as a percentage of the input amount.
+    pub fn effective_fee_percent(&self) -> f64 {
+        if self.amount_in.is_zero() {
+            return 0.0;
+        }
+        u256_as_f64(self.fee_amount) / u256_as_f64(self.amount_in) * 100.0
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.amount_remaining.is_zero()
+    }
+}
+
+impl PoolState {
+    /// Reads `slot0`, liquidity and fee settings from the pool, plus
+    /// `words_each_side` bitmap words around the current tick and the
+    /// `liquidityNet` of every initialized tick in them.
+    pub async fn fetch(
+        web3: &Web3<FailoverHttp>,
+        pool_address: Address,
+        words_each_side: i16,
+    ) -> error::Result<PoolState> {
+        let pool_contract = Contract::new(web3.eth(), pool_address, load_abi("./pool_abi.json")?);
+
+        let slot0: (U256, i32, u16, u16, u16, u8, bool) = pool_contract
+            .query("slot0", (), None, Options::default(), None)
+            .await?;
+        let liquidity: u128 = pool_contract
+            .query("liquidity", (), None, Options::default(), None)
+            .await?;
+        let fee: u32 = pool_contract
+            .query("fee", (), None, Options::default(), None)
+            .await?;
+        let tick_spacing: i32 = pool_contract
+            .query("tickSpacing", (), None, Options::default(), None)
+            .await?;
+        let token0: Address = pool_contract
+            .query("token0", (), None, Options::default(), None)
+            .await?;
+        let token1: Address = pool_contract
+            .query("token1", (), None, Options::default(), None)
+            .await?;
+
+        let (current_word, _) = tick_bitmap_position(compress_tick(slot0.1, tick_spacing));
+        let word_positions: Vec<i16> = (-words_each_side..=words_each_side)
+            .filter_map(|offset| current_word.checked_add(offset))
+            .collect();
+
+        let words: Vec<U256> = try_join_all(word_positions.iter().map(|word_pos| {
+            pool_contract.query("tickBitmap", (*word_pos,), None, Options::default(), None)
+        }))
+        .await?;
+        let tick_bitmap: HashMap<i16, U256> = word_positions.into_iter().zip(words).collect();
+
+        let initialized_ticks: Vec<i32> = tick_bitmap
+            .iter()
+            .flat_map(|(word_pos, word)| {
+                (0..256)
+                    .filter(move |bit| word.bit(*bit))
+                    .map(move |bit| ((*word_pos as i32) * 256 + bit as i32) * tick_spacing)
+            })
+            .collect();
+
+        let tick_infos: Vec<TickInfo> =
+            try_join_all(initialized_ticks.iter().map(|tick| {
+                pool_contract.query("ticks", (*tick,), None, Options::default(), None)
+            }))
+            .await?;
+        let liquidity_net: HashMap<i32, i128> = initialized_ticks
+            .into_iter()
+            .zip(tick_infos)
+            .map(|(tick, info)| (tick, info.1))
+            .collect();
+
+        Ok(PoolState {
+            address: pool_address,
+            token0,
+            token1,
+            fee,
+            tick_spacing,
+            sqrt_price_x96: slot0.0,
+            tick: slot0.1,
+            liquidity,
+            tick_bitmap,
+            liquidity_net,
+        })
+    }
+
+    /// Brings the snapshot up to date with an event the pool emitted. Ticks
+    /// are marked initialized while their `liquidityNet` is non-zero, and
+    /// only in bitmap words that were fetched.
+    pub fn apply(&mut self, event: &PoolEvent) -> Result<(), &'static str> {
+        match *event {
+            PoolEvent::Swap {
+                sqrt_price_x96,
+                liquidity,
+                tick,
+            } => {
+                self.sqrt_price_x96 = sqrt_price_x96;
+                self.liquidity = liquidity;
+                self.tick = tick;
+            }
+            PoolEvent::Mint {
+                tick_lower,
+                tick_upper,
+                amount,
+            } => self.update_position(tick_lower, tick_upper, amount as i128)?,
+            PoolEvent::Burn {
+                tick_lower,
+                tick_upper,
+                amount,
+            } => self.update_position(tick_lower, tick_upper, -(amount as i128))?,
+        }
+        Ok(())
+    }
+
+    /// Applies the result of a simulated swap, as if it had been sent.
+    pub fn apply_swap(&mut self, simulation: &SwapSimulation) {
+        self.sqrt_price_x96 = simulation.sqrt_price_x96_after;
+        self.liquidity = simulation.liquidity_after;
+        self.tick = simulation.tick_after;
+    }
+
+    fn update_position(
+        &mut self,
+        tick_lower: i32,
+        tick_upper: i32,
+        liquidity_delta: i128,
+    ) -> Result<(), &'static str> {
+        self.update_tick(tick_lower, liquidity_delta)?;
+        self.update_tick(
+            tick_upper,
+            liquidity_delta
+                .checked_neg()
+                .ok_or("liquidity delta out of range")?,
+        )?;
+
+        if tick_lower <= self.tick && self.tick < tick_upper {
+            self.liquidity = if liquidity_delta < 0 {
+                self.liquidity.checked_sub(liquidity_delta.unsigned_abs())
+            } else {
+                self.liquidity.checked_add(liquidity_delta as u128)
+            }
+            .ok_or("liquidity delta out of range")?;
+        }
+        Ok(())
+    }
+
+    fn update_tick(&mut self, tick: i32, liquidity_delta: i128) -> Result<(), &'static str> {
+        let net = self
+            .liquidity_net
+            .get(&tick)
+            .copied()
+            .unwrap_or(0)
+            .checked_add(liquidity_delta)
+            .ok_or("liquidity delta out of range")?;
+        if net == 0 {
+            self.liquidity_net.remove(&tick);
+        } else {
+            self.liquidity_net.insert(tick, net);
+        }
+
+        let (word_pos, bit_pos) = tick_bitmap_position(compress_tick(tick, self.tick_spacing));
+        if let Some(word) = self.tick_bitmap.get_mut(&word_pos) {
+            let mask = U256::one() << bit_pos;
+            *word = if net == 0 {
+                *word & !mask
+            } else {
+                *word | mask
+            };
+        }
+        Ok(())
+    }
+
+    /// Direction of a swap that pays `token_in` into the pool.
+    pub fn zero_for_one(&self, token_in: Address) -> Result<bool, &'static str> {
+        if token_in == self.token0 {
+            Ok(true)
+        } else if token_in == self.token1 {
+            Ok(false)
+        } else {
+            Err("token is not part of the pool")
+        }
+    }
+
+    /// Simulates selling `amount_in` of `token_in` with no price limit.
+    pub fn simulate_exact_input(
+        &self,
+        token_in: Address,
+        amount_in: U256,
+    ) -> Result<SwapSimulation, &'static str> {
+        self.simulate_swap(self.zero_for_one(token_in)?, amount_in, true, None)
+    }
+
+    /// Replays `UniswapV3Pool.swap` against this snapshot without sending a
+    /// transaction. `amount_specified` is an input amount when `exact_input`
+    /// is set and an output amount otherwise. Without a limit the swap may
+    /// run to the edge of the loaded bitmap.
+    pub fn simulate_swap(
+        &self,
+        zero_for_one: bool,
+        amount_specified: U256,
+        exact_input: bool,
+        sqrt_price_limit_x96: Option<U256>,
+    ) -> Result<SwapSimulation, &'static str> {
+        if amount_specified.is_zero() {
+            return Err("swap amount is zero");
+        }
+        if amount_specified > MAX_AMOUNT_SPECIFIED {
+            return Err("swap amount overflows int256");
+        }
+        let sqrt_price_limit_x96 = match sqrt_price_limit_x96 {
+            Some(limit) => limit,
+            None if zero_for_one => MIN_SQRT_RATIO + 1,
+            None => MAX_SQRT_RATIO - 1,
+        };
+        let limit_is_valid = if zero_for_one {
+            sqrt_price_limit_x96 < self.sqrt_price_x96 && sqrt_price_limit_x96 > MIN_SQRT_RATIO
+        } else {
+            sqrt_price_limit_x96 > self.sqrt_price_x96 && sqrt_price_limit_x96 < MAX_SQRT_RATIO
+        };
+        if !limit_is_valid {
+            return Err("sqrt price limit is on the wrong side of the current price");
+        }
+
+        let mut amount_remaining = amount_specified;
+        let mut amount_calculated = U256::zero();
+        let mut fee_total = U256::zero();
+        let mut sqrt_price_x96 = self.sqrt_price_x96;
+        let mut tick = self.tick;
+        let mut liquidity = self.liquidity;
+        let mut ticks_crossed = 0;
+
+        while !amount_remaining.is_zero() && sqrt_price_x96 != sqrt_price_limit_x96 {
+            let sqrt_price_start_x96 = sqrt_price_x96;
+
+            let (tick_next, initialized) = match next_initialized_tick_within_one_word(
+                &self.tick_bitmap,
+                tick,
+                self.tick_spacing,
+                zero_for_one,
+            ) {
+                Some(next) => next,
+                None => break,
+            };
+            let tick_next = tick_next.clamp(MIN_TICK, MAX_TICK);
+            let sqrt_price_next_x96 = get_sqrt_ratio_at_tick(tick_next)?;
+
+            let sqrt_price_target_x96 = if (zero_for_one
+                && sqrt_price_next_x96 < sqrt_price_limit_x96)
+                || (!zero_for_one && sqrt_price_next_x96 > sqrt_price_limit_x96)
+            {
+                sqrt_price_limit_x96
+            } else {
+                sqrt_price_next_x96
+            };
+
+            let step = compute_swap_step(
+                sqrt_price_x96,
+                sqrt_price_target_x96,
+                liquidity,
+                amount_remaining,
+                exact_input,
+                self.fee,
+            )?;
+            sqrt_price_x96 = step.sqrt_price_next_x96;
+            fee_total += step.fee_amount;
+
+            if exact_input {
+                amount_remaining -= step.amount_in + step.fee_amount;
+                amount_calculated += step.amount_out;
+            } else {
+                amount_remaining -= step.amount_out;
+                amount_calculated += step.amount_in + step.fee_amount;
+            }
+
+            if sqrt_price_x96 == sqrt_price_next_x96 {
+                if initialized {
+                    let net = self.liquidity_net.get(&tick_next).copied().unwrap_or(0);
+                    let net = if zero_for_one { -net } else { net };
+                    liquidity = if net < 0 {
+                        liquidity.checked_sub(net.unsigned_abs())
+                    } else {
+                        liquidity.checked_add(net as u128)
+                    }
+                    .ok_or("liquidity delta out of range")?;
+                    ticks_crossed += 1;
+                }
+                tick = if zero_for_one {
+                    tick_next - 1
+                } else {
+                    tick_next
+                };
+            } else if sqrt_price_x96 != sqrt_price_start_x96 {
+                tick = get_tick_at_sqrt_ratio(sqrt_price_x96)?;
+            }
+        }
+
+        let (amount_in, amount_out) = if exact_input {
+            (amount_specified - amount_remaining, amount_calculated)
+        } else {
+            (amount_calculated, amount_specified - amount_remaining)
+        };
+
+        Ok(SwapSimulation {
+            zero_for_one,
+            amount_in,
+            amount_out,
+            fee_amount: fee_total,
+            sqrt_price_x96_after: sqrt_price_x96,
+            tick_after: tick,
+            liquidity_after: liquidity,
+            ticks_crossed,
+            amount_remaining,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v3_math::Q96;
+
+    const TICK_SPACING: i32 = 60;
+    const OUTER: u128 = 1_000_000_000_000_000_000;
+    const INNER: u128 = 500_000_000_000_000_000;
+
+    /// A 0.3% pool at tick 0 with one position over [-120, 120] and one
+    /// over [-60, 60], so both are in range.
+    fn pool() -> PoolState {
+        let mut pool = PoolState {
+            address: Address::zero(),
+            token0: Address::from_low_u64_be(1),
+            token1: Address::from_low_u64_be(2),
+            fee: 3000,
+            tick_spacing: TICK_SPACING,
+            sqrt_price_x96: Q96,
+            tick: 0,
+            liquidity: 0,
+            tick_bitmap: [(-1, U256::zero()), (0, U256::zero())].into(),
+            liquidity_net: HashMap::new(),
+        };
+        for (tick_lower, tick_upper, amount) in [(-120, 120, OUTER), (-60, 60, INNER)] {
+            pool.apply(&PoolEvent::Mint {
+                tick_lower,
+                tick_upper,
+                amount,
+            })
+            .unwrap();
+        }
+        pool
+    }
+
+    fn sqrt_price(tick: i32) -> U256 {
+        get_sqrt_ratio_at_tick(tick).unwrap()
+    }
+
+    #[test]
+    fn positions() {
+        let mut pool = pool();
+        assert_eq!(pool.liquidity, OUTER + INNER);
+        assert_eq!(pool.liquidity_net[&-120], OUTER as i128);
+        assert_eq!(pool.liquidity_net[&60], -(INNER as i128));
+        let initialized = |pool: &PoolState, tick: i32| {
+            let (word_pos, bit_pos) = tick_bitmap_position(compress_tick(tick, TICK_SPACING));
+            pool.tick_bitmap[&word_pos].bit(bit_pos as usize)
+        };
+        assert!(initialized(&pool, -60) && initialized(&pool, 120));
+
+        pool.apply(&PoolEvent::Burn {
+            tick_lower: -60,
+            tick_upper: 60,
+            amount: INNER,
+        })
+        .unwrap();
+        assert_eq!(pool.liquidity, OUTER);
+        assert!(!pool.liquidity_net.contains_key(&-60));
+        assert!(!initialized(&pool, -60) && !initialized(&pool, 60));
+        assert!(initialized(&pool, -120));
+    }
+
+    /// Selling token0 crosses -60, where the inner position's positive
+    /// `liquidityNet` is taken away, and ends inside [-120, -60].
+    #[test]
+    fn zero_for_one_crosses_ticks() {
+        let pool = pool();
+        let amount_in = U256::from(6) * U256::exp10(15);
+
+        let first =
+            compute_swap_step(Q96, sqrt_price(-60), OUTER + INNER, amount_in, true, 3000).unwrap();
+        assert_eq!(first.sqrt_price_next_x96, sqrt_price(-60));
+        let second = compute_swap_step(
+            sqrt_price(-60),
+            sqrt_price(-120),
+            OUTER,
+            amount_in - first.amount_in - first.fee_amount,
+            true,
+            3000,
+        )
+        .unwrap();
+        assert!(second.sqrt_price_next_x96 > sqrt_price(-120));
+
+        let swap = pool.simulate_swap(true, amount_in, true, None).unwrap();
+        assert_eq!(swap.amount_in, amount_in);
+        assert_eq!(swap.amount_out, first.amount_out + second.amount_out);
+        assert_eq!(swap.fee_amount, first.fee_amount + second.fee_amount);
+        assert_eq!(swap.sqrt_price_x96_after, second.sqrt_price_next_x96);
+        assert_eq!(swap.liquidity_after, OUTER);
+        assert_eq!(swap.ticks_crossed, 1);
+        assert!((-120..-60).contains(&swap.tick_after));
+        assert!(swap.is_complete());
+    }
+
+    /// Buying token0 crosses 60 and 120, where the negative `liquidityNet`
+    /// of both upper ticks empties the pool, and stops at the end of the
+    /// fetched bitmap with the rest unfilled.
+    #[test]
+    fn one_for_zero_crosses_ticks() {
+        let pool = pool();
+        let amount_in = U256::exp10(17);
+
+        let swap = pool.simulate_swap(false, amount_in, true, None).unwrap();
+        let first =
+            compute_swap_step(Q96, sqrt_price(60), OUTER + INNER, amount_in, true, 3000).unwrap();
+        let second = compute_swap_step(
+            sqrt_price(60),
+            sqrt_price(120),
+            OUTER,
+            amount_in - first.amount_in - first.fee_amount,
+            true,
+            3000,
+        )
+        .unwrap();
+        assert_eq!(second.sqrt_price_next_x96, sqrt_price(120));
+
+        assert_eq!(swap.amount_out, first.amount_out + second.amount_out);
+        assert_eq!(swap.fee_amount, first.fee_amount + second.fee_amount);
+        assert_eq!(
+            swap.amount_in,
+            first.amount_in + first.fee_amount + second.amount_in + second.fee_amount
+        );
+        assert_eq!(swap.amount_remaining, amount_in - swap.amount_in);
+        assert_eq!(swap.liquidity_after, 0);
+        assert_eq!(swap.ticks_crossed, 2);
+        assert!(!swap.is_complete());
+    }
+
+    #[test]
+    fn price_limit_stops_the_swap() {
+        let pool = pool();
+        let limit = sqrt_price(-30);
+        let swap = pool
+            .simulate_swap(true, U256::exp10(18), true, Some(limit))
+            .unwrap();
+        assert_eq!(swap.sqrt_price_x96_after, limit);
+        assert_eq!(swap.ticks_crossed, 0);
+        assert_eq!(swap.liquidity_after, OUTER + INNER);
+        assert!(!swap.is_complete());
+
+        assert!(pool
+            .simulate_swap(true, U256::one(), true, Some(Q96))
+            .is_err());
+        assert!(pool.simulate_swap(true, U256::zero(), true, None).is_err());
+    }
+}
@@ -0,0 +1,567 @@
+use crate::{
+    fixed_point::{to_token_units, MAX_U256_DECIMALS},
+    gas::FeeStrategy,
+    v3_math::FEE_DENOMINATOR,
+    Config, CONFIG_VERSION,
+};
+use std::{collections::HashMap, fmt};
+use web3::{signing::keccak256, transports::Http, types::Address};
+
+/// Largest slippage buffer that still leaves something to trade with.
+const MAX_SLIPPAGE_PERCENT: f64 = 100.0;
+
+/// A config field that would make the bot misbehave, named as in
+/// `config.json`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+impl Config {
+    /// Every problem with the config at once, empty when it can be run.
+    pub fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        let mut fail = |field: &str, message: &str| {
+            errors.push(FieldError {
+                field: field.to_string(),
+                message: message.to_string(),
+            })
+        };
+
+        if self.version > CONFIG_VERSION {
+            fail("version", "written by a newer version of the bot");
+        }
+
+        for (field, address, required) in [
+            ("contract_address", &self.contract_address, true),
+            ("token_address_master", &self.token_address_master, true),
+            (
+                "wrapped_native_address",
+                &self.wrapped_native_address,
+                false,
+            ),
+            ("executor_address", &self.executor_address, false),
+            ("flash_pool_address", &self.flash_pool_address, false),
+            ("router_address", &self.router_address, false),
+        ] {
+            if required || !address.is_empty() {
+                if let Err(message) = check_address(address) {
+                    fail(field, message);
+                }
+            }
+        }
+        for (i, token) in self.tokens.iter().enumerate() {
+            if let Err(message) = check_address(token) {
+                fail(&format!("tokens[{}]", i), message);
+            }
+        }
+        for (i, factory) in self.v2_factories.iter().enumerate() {
+            if let Err(message) = check_address(&factory.address) {
+                fail(&format!("v2_factories[{}].address", i), message);
+            }
+            if factory.fee >= FEE_DENOMINATOR {
+                fail(
+                    &format!("v2_factories[{}].fee", i),
+                    "must be below 1000000, which is 100%",
+                );
+            }
+        }
+        if self.tokens.is_empty() {
+            fail("tokens", "at least one token to route through is needed");
+        }
+
+        let master = self.token_address_master.parse::<Address>().ok();
+        if master.is_some() && self.tokens.iter().any(|t| t.parse().ok() == master) {
+            fail("tokens", "must not contain the master token");
+        }
+        if self.executor_address.is_empty() != self.flash_pool_address.is_empty() {
+            fail(
+                "flash_pool_address",
+                "the executor and the flash pool are needed together",
+            );
+        }
+        if !self.flash_pool_address.is_empty()
+            && self.flash_pool_address.to_lowercase() == self.contract_address.to_lowercase()
+        {
+            fail("flash_pool_address", "is the factory, not a pool");
+        }
+        if !self.router_address.is_empty()
+            && self.router_address.to_lowercase() == self.executor_address.to_lowercase()
+        {
+            fail(
+                "router_address",
+                "is the executor, which borrows what it swaps and needs no allowance",
+            );
+        }
+        if !self.keystore_path.is_empty() && self.public_key.is_zero() {
+            fail("public_key", "must be the address of the keystore");
+        }
+
+        if !self.chains.contains_key(&self.chain) {
+            fail("chain", &format!("{} is not defined in chains", self.chain));
+        }
+        for (chain, definition) in &self.chains {
+            let field = |name: &str| format!("chains.{}.{}", chain, name);
+            if chain.to_string().trim().is_empty() {
+                fail("chains", "a chain needs a name");
+            }
+            if definition.chain_id == 0 {
+                fail(&field("chain_id"), "must be positive");
+            }
+            if definition.rpc_urls.is_empty() {
+                fail(&field("rpc_urls"), "at least one RPC URL is needed");
+            }
+            if definition
+                .rpc_urls
+                .iter()
+                .any(|url| Http::new(url).is_err())
+            {
+                fail(&field("rpc_urls"), "a URL does not parse");
+            }
+            if !definition.ws_url.is_empty()
+                && !definition.ws_url.starts_with("ws://")
+                && !definition.ws_url.starts_with("wss://")
+            {
+                fail(&field("ws_url"), "must be ws:// or wss://");
+            }
+            if definition.native_symbol.trim().is_empty() {
+                fail(&field("native_symbol"), "must not be empty");
+            }
+            if !definition.block_time_secs.is_finite() || definition.block_time_secs <= 0.0 {
+                fail(&field("block_time_secs"), "must be positive");
+            }
+        }
+
+        if !self.explorer_url.is_empty() && reqwest::Url::parse(&self.explorer_url).is_err() {
+            fail("explorer_url", "does not parse");
+        }
+        if !self.relay_url.is_empty() && reqwest::Url::parse(&self.relay_url).is_err() {
+            fail("relay_url", "does not parse");
+        }
+        if self.relay_target_blocks == 0 {
+            fail("relay_target_blocks", "must be at least 1");
+        }
+
+        if self.max_hops < 2 {
+            fail("max_hops", "must be at least 2");
+        }
+        if self.gas_limit == 0 {
+            fail("gas_limit", "must be positive");
+        }
+        if !(0.0..MAX_SLIPPAGE_PERCENT).contains(&self.slippage_threshhold) {
+            fail(
+                "slippage_threshhold",
+                "must be a percentage from 0 up to 100",
+            );
+        }
+        if !self.minimum_profit.is_finite() || self.minimum_profit < 0.0 {
+            fail("minimum_profit", "must not be negative");
+        }
+        if !self.amount_to_trade.is_finite() || self.amount_to_trade <= 0.0 {
+            fail("amount_to_trade", "must be positive");
+        }
+        for (field, gwei) in [
+            ("priority_fee_gwei", self.priority_fee_gwei),
+            ("max_fee_gwei", self.max_fee_gwei),
+        ] {
+            if !gwei.is_finite() || gwei < 0.0 {
+                fail(field, "must be a non-negative number of gwei");
+            }
+        }
+        if self.fee_strategy == FeeStrategy::Fixed
+            && self.max_fee_gwei > 0.0
+            && self.priority_fee_gwei > self.max_fee_gwei
+        {
+            fail("priority_fee_gwei", "is above the max fee cap");
+        }
+
+        errors
+    }
+
+    /// Problems that only show with the `decimals` the configured tokens
+    /// report on chain: more than an amount can hold, or an
+    /// `amount_to_trade` below one unit of the master token.
+    pub fn validate_decimals(&self, decimals: &HashMap<Address, u8>) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        let mut fail = |field: &str, message: &str| {
+            errors.push(FieldError {
+                field: field.to_string(),
+                message: message.to_string(),
+            })
+        };
+
+        let tokens = self
+            .tokens
+            .iter()
+            .enumerate()
+            .map(|(i, token)| (format!("tokens[{}]", i), token));
+        for (field, token) in [(
+            "token_address_master".to_string(),
+            &self.token_address_master,
+        )]
+        .into_iter()
+        .chain(tokens)
+        {
+            let Some(&token_decimals) = token
+                .parse::<Address>()
+                .ok()
+                .and_then(|address| decimals.get(&address))
+            else {
+                continue;
+            };
+            if token_decimals > MAX_U256_DECIMALS {
+                fail(
+                    &field,
+                    &format!(
+                        "has {} decimals, more than a uint256 amount can hold",
+                        token_decimals
+                    ),
+                );
+            } else if field == "token_address_master"
+                && to_token_units(self.amount_to_trade, token_decimals)
+                    .map_or(true, |units| units.is_zero())
+            {
+                fail(
+                    "amount_to_trade",
+                    &format!(
+                        "is less than one unit of the master token, which has {} decimals",
+                        token_decimals
+                    ),
+                );
+            }
+        }
+
+        errors
+    }
+}
+
+/// Parses `address`, checking the EIP-55 checksum when it is mixed case.
+fn check_address(address: &str) -> Result<Address, &'static str> {
+    let parsed: Address = address.parse().map_err(|_| "not an address")?;
+    if parsed.is_zero() {
+        return Err("must not be the zero address");
+    }
+
+    let hex = address.trim_start_matches("0x");
+    let mixed_case =
+        hex.chars().any(|c| c.is_ascii_uppercase()) && hex.chars().any(|c| c.is_ascii_lowercase());
+    if mixed_case && hex != checksummed(&parsed) {
+        return Err("checksum does not match");
+    }
+    Ok(parsed)
+}
+
+/// EIP-55 mixed-case hex of `address`, without the `0x`.
+fn checksummed(address: &Address) -> String {
+    let lower = hex::encode(address.as_bytes());
+    let hash = keccak256(lower.as_bytes());
+    lower
+        .chars()
+        .enumerate()
+        .map(|(i, c)| {
+            let nibble = (hash[i / 2] >> (if i % 2 == 0 { 4 } else { 0 })) & 0x0f;
+            if nibble >= 8 {
+                c.to_ascii_uppercase()
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Chain, V2Factory};
+
+    const MASTER: &str = "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2";
+    const TOKEN: &str = "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48";
+    const FACTORY: &str = "0x1F98431c8aD98523631AE4a59f267346ea31F984";
+    const EXECUTOR: &str = "0x5C69bEe701ef814a2B6a3EDD4B1652CB9cc5aA6f";
+    const FLASH_POOL: &str = "0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640";
+
+    fn config() -> Config {
+        serde_json::from_value(serde_json::json!({
+            "version": CONFIG_VERSION,
+            "chain": "Ethereum",
+            "contract_address": FACTORY,
+            "public_key": "0xa07876136c3a3141dc2c25071330d3b08225e043",
+            "token_address_master": MASTER,
+            "tokens": [TOKEN],
+            "gas_limit": 2500000,
+            "slippage_threshhold": 0.5,
+            "minimum_profit": 100.0,
+            "amount_to_trade": 0.0001,
+        }))
+        .unwrap()
+    }
+
+    type Change = Box<dyn FnOnce(&mut Config)>;
+
+    /// Fields `change` makes the config fail, with their messages.
+    fn errors(change: impl FnOnce(&mut Config)) -> Vec<String> {
+        let mut config = config();
+        change(&mut config);
+        config.validate().iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn valid_config() {
+        assert_eq!(errors(|_| {}), Vec::<String>::new());
+        assert_eq!(
+            errors(|config| {
+                config.executor_address = EXECUTOR.into();
+                config.flash_pool_address = FLASH_POOL.into();
+                config.router_address = "0x68b3465833fb72A70ecDF485E0e4C7bD8665Fc45".into();
+                config.v2_factories = vec![V2Factory {
+                    address: "0x5c69bee701ef814a2b6a3edd4b1652cb9cc5aa6f".into(),
+                    fee: 3000,
+                }];
+            }),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn field_errors() {
+        let cases: Vec<(Change, &str)> = vec![
+            (
+                Box::new(|c| c.version = CONFIG_VERSION + 1),
+                "version: written by a newer version of the bot",
+            ),
+            (
+                Box::new(|c| c.contract_address.clear()),
+                "contract_address: not an address",
+            ),
+            (
+                Box::new(|c| c.token_address_master = "0x1234".into()),
+                "token_address_master: not an address",
+            ),
+            (
+                Box::new(|c| c.wrapped_native_address = "weth".into()),
+                "wrapped_native_address: not an address",
+            ),
+            (
+                Box::new(|c| c.router_address = format!("0x{}", "0".repeat(40))),
+                "router_address: must not be the zero address",
+            ),
+            (
+                Box::new(|c| c.tokens.push("nope".into())),
+                "tokens[1]: not an address",
+            ),
+            (
+                Box::new(|c| {
+                    c.v2_factories = vec![V2Factory {
+                        address: "0x5C69bEe701ef814a2B6a3EDD4B1652CB9cc5aA6F".into(),
+                        fee: 3000,
+                    }]
+                }),
+                "v2_factories[0].address: checksum does not match",
+            ),
+            (
+                Box::new(|c| {
+                    c.v2_factories = vec![V2Factory {
+                        address: EXECUTOR.into(),
+                        fee: FEE_DENOMINATOR,
+                    }]
+                }),
+                "v2_factories[0].fee: must be below 1000000, which is 100%",
+            ),
+            (
+                Box::new(|c| c.tokens.clear()),
+                "tokens: at least one token to route through is needed",
+            ),
+            (
+                Box::new(|c| c.tokens.push(MASTER.to_lowercase())),
+                "tokens: must not contain the master token",
+            ),
+            (
+                Box::new(|c| c.executor_address = EXECUTOR.into()),
+                "flash_pool_address: the executor and the flash pool are needed together",
+            ),
+            (
+                Box::new(|c| {
+                    c.executor_address = EXECUTOR.into();
+                    c.flash_pool_address = FACTORY.to_lowercase();
+                }),
+                "flash_pool_address: is the factory, not a pool",
+            ),
+            (
+                Box::new(|c| {
+                    c.executor_address = EXECUTOR.into();
+                    c.flash_pool_address = FLASH_POOL.into();
+                    c.router_address = EXECUTOR.to_lowercase();
+                }),
+                "router_address: is the executor, which borrows what it swaps and needs no allowance",
+            ),
+            (
+                Box::new(|c| {
+                    c.keystore_path = "keystore/wallet.json".into();
+                    c.public_key = Address::zero();
+                }),
+                "public_key: must be the address of the keystore",
+            ),
+            (
+                Box::new(|c| c.chain = Chain::new("Base")),
+                "chain: Base is not defined in chains",
+            ),
+            (
+                Box::new(|c| {
+                    let polygon = c.chains[&Chain::new("Polygon")].clone();
+                    c.chains.insert(Chain::new(" "), polygon);
+                }),
+                "chains: a chain needs a name",
+            ),
+            (
+                Box::new(|c| c.chains.get_mut(&Chain::new("Polygon")).unwrap().chain_id = 0),
+                "chains.Polygon.chain_id: must be positive",
+            ),
+            (
+                Box::new(|c| c.chains.get_mut(&Chain::new("Polygon")).unwrap().rpc_urls.clear()),
+                "chains.Polygon.rpc_urls: at least one RPC URL is needed",
+            ),
+            (
+                Box::new(|c| {
+                    c.chains.get_mut(&Chain::new("Polygon")).unwrap().rpc_urls = vec!["not a url".into()]
+                }),
+                "chains.Polygon.rpc_urls: a URL does not parse",
+            ),
+            (
+                Box::new(|c| {
+                    c.chains.get_mut(&Chain::new("Polygon")).unwrap().ws_url =
+                        "https://polygon.example".into()
+                }),
+                "chains.Polygon.ws_url: must be ws:// or wss://",
+            ),
+            (
+                Box::new(|c| c.chains.get_mut(&Chain::new("Polygon")).unwrap().native_symbol.clear()),
+                "chains.Polygon.native_symbol: must not be empty",
+            ),
+            (
+                Box::new(|c| {
+                    c.chains.get_mut(&Chain::new("Polygon")).unwrap().block_time_secs = f64::NAN
+                }),
+                "chains.Polygon.block_time_secs: must be positive",
+            ),
+            (
+                Box::new(|c| c.explorer_url = "etherscan".into()),
+                "explorer_url: does not parse",
+            ),
+            (
+                Box::new(|c| c.relay_url = "relay".into()),
+                "relay_url: does not parse",
+            ),
+            (
+                Box::new(|c| c.relay_target_blocks = 0),
+                "relay_target_blocks: must be at least 1",
+            ),
+            (Box::new(|c| c.max_hops = 1), "max_hops: must be at least 2"),
+            (Box::new(|c| c.gas_limit = 0), "gas_limit: must be positive"),
+            (
+                Box::new(|c| c.slippage_threshhold = 1000.0),
+                "slippage_threshhold: must be a percentage from 0 up to 100",
+            ),
+            (
+                Box::new(|c| c.minimum_profit = -1.0),
+                "minimum_profit: must not be negative",
+            ),
+            (
+                Box::new(|c| c.amount_to_trade = 0.0),
+                "amount_to_trade: must be positive",
+            ),
+            (
+                Box::new(|c| c.priority_fee_gwei = f64::INFINITY),
+                "priority_fee_gwei: must be a non-negative number of gwei",
+            ),
+            (
+                Box::new(|c| c.max_fee_gwei = -0.5),
+                "max_fee_gwei: must be a non-negative number of gwei",
+            ),
+            (
+                Box::new(|c| {
+                    c.fee_strategy = FeeStrategy::Fixed;
+                    c.priority_fee_gwei = 3.0;
+                    c.max_fee_gwei = 2.0;
+                }),
+                "priority_fee_gwei: is above the max fee cap",
+            ),
+        ];
+        for (change, expected) in cases {
+            assert_eq!(errors(change), [expected]);
+        }
+    }
+
+    #[test]
+    fn decimals() {
+        let master: Address = MASTER.parse().unwrap();
+        let token: Address = TOKEN.parse().unwrap();
+        let errors = |amount_to_trade: f64, master_decimals: u8, token_decimals: u8| {
+            let mut config = config();
+            config.amount_to_trade = amount_to_trade;
+            let decimals = HashMap::from([(master, master_decimals), (token, token_decimals)]);
+            config
+                .validate_decimals(&decimals)
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(errors(0.0001, 18, 6), Vec::<String>::new());
+        assert_eq!(errors(0.000001, 6, 18), Vec::<String>::new());
+        // Passes the static check, but is no unit of a 6 decimal token.
+        assert_eq!(
+            errors(0.0000001, 6, 18),
+            ["amount_to_trade: is less than one unit of the master token, which has 6 decimals"]
+        );
+        assert_eq!(
+            errors(0.0001, 18, 78),
+            ["tokens[0]: has 78 decimals, more than a uint256 amount can hold"]
+        );
+        assert_eq!(
+            errors(0.0001, 255, 18),
+            ["token_address_master: has 255 decimals, more than a uint256 amount can hold"]
+        );
+        // Tokens whose decimals could not be read are left alone.
+        assert_eq!(
+            config().validate_decimals(&HashMap::new()),
+            Vec::<FieldError>::new()
+        );
+    }
+
+    #[test]
+    fn addresses() {
+        // EIP-55 test vectors.
+        for address in [
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+            "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359",
+            "0xdbF03B407c01E7cD3CBea99509d93f8DDDC8C6FB",
+            "0xD1220A0cf47c7B9Be7A2E6BA89F429762e7b9aDb",
+        ] {
+            assert_eq!(check_address(address), Ok(address.parse().unwrap()));
+            assert_eq!(checksummed(&address.parse().unwrap()), address[2..]);
+        }
+        assert_eq!(
+            check_address("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeD"),
+            Err("checksum does not match")
+        );
+        // Single-case addresses carry no checksum.
+        assert!(check_address("0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed").is_ok());
+        assert!(check_address("0x5AAEB6053F3E94C9B9A09F33669435E7EF1BEAED").is_ok());
+        assert_eq!(
+            check_address("0x0000000000000000000000000000000000000000"),
+            Err("must not be the zero address")
+        );
+        assert_eq!(check_address(""), Err("not an address"));
+        assert_eq!(
+            check_address("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeA"),
+            Err("not an address")
+        );
+    }
+}
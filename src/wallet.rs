@@ -0,0 +1,188 @@
+use crate::{
+    discovery::load_abi,
+    error::{self, Error},
+    fetch_decimals_of_token,
+    fixed_point::from_token_units,
+    rpc::FailoverHttp,
+};
+use eframe::egui;
+use futures::future::try_join_all;
+use std::{collections::HashMap, fmt};
+use web3::{
+    contract::{Contract, Options},
+    types::{Address, U256, U64},
+    Web3,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenBalance {
+    pub token: Address,
+    pub label: String,
+    /// In whole tokens.
+    pub balance: f64,
+}
+
+/// What the wallet holds of the native token and every configured token.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Balances {
+    pub block: U64,
+    /// Symbol of the native token, from the chain's config.
+    pub native_symbol: String,
+    /// In whole native tokens.
+    pub native: f64,
+    pub tokens: Vec<TokenBalance>,
+}
+
+impl Balances {
+    pub fn of(&self, token: Address) -> f64 {
+        self.tokens
+            .iter()
+            .find(|balance| balance.token == token)
+            .map_or(0.0, |balance| balance.balance)
+    }
+}
+
+/// Reads the native balance of `owner` and its `balanceOf` on each token.
+pub async fn fetch_balances(
+    web3: &Web3<FailoverHttp>,
+    owner: Address,
+    native_symbol: &str,
+    tokens: &[Address],
+    labels: &HashMap<Address, String>,
+) -> error::Result<Balances> {
+    let erc20_abi = load_abi("./erc20_abi.json")?;
+    let block = web3.eth().block_number().await?;
+    let native = web3.eth().balance(owner, None).await?;
+
+    let tokens = try_join_all(tokens.iter().map(|token| {
+        let contract = Contract::new(web3.eth(), *token, erc20_abi.clone());
+        async move {
+            let balance: U256 = contract
+                .query("balanceOf", (owner,), None, Options::default(), None)
+                .await?;
+            let decimals = fetch_decimals_of_token(web3, *token).await?;
+            Ok::<_, Error>(TokenBalance {
+                token: *token,
+                label: labels
+                    .get(token)
+                    .cloned()
+                    .unwrap_or_else(|| format!("{:?}", token)),
+                balance: from_token_units(balance, decimals),
+            })
+        }
+    }))
+    .await?;
+
+    Ok(Balances {
+        block,
+        native_symbol: native_symbol.to_string(),
+        native: from_token_units(native, 18),
+        tokens,
+    })
+}
+
+/// Refuses to trade when the wallet cannot cover `gas_needed` native tokens
+/// of fees or `amount_to_trade` of the master token.
+pub fn check_funds(
+    balances: &Balances,
+    master: Address,
+    amount_to_trade: f64,
+    gas_needed: f64,
+) -> error::Result<()> {
+    if balances.native < gas_needed {
+        return Err(Error::InsufficientFunds(format!(
+            "{:.6} {} held, a transaction may need {:.6} for gas",
+            balances.native, balances.native_symbol, gas_needed
+        )));
+    }
+    let master_balance = balances.of(master);
+    if master_balance < amount_to_trade {
+        return Err(Error::InsufficientFunds(format!(
+            "{:.6} master tokens held, amount_to_trade is {:.6}",
+            master_balance, amount_to_trade
+        )));
+    }
+    Ok(())
+}
+
+/// Balances when the run started next to the latest ones, so what trading
+/// has done to the wallet shows up as drift.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Inventory {
+    pub start: Balances,
+    pub current: Balances,
+}
+
+impl Inventory {
+    pub fn new(balances: Balances) -> Inventory {
+        Inventory {
+            start: balances.clone(),
+            current: balances,
+        }
+    }
+
+    pub fn update(&mut self, balances: Balances) {
+        self.current = balances;
+    }
+
+    pub fn native_drift(&self) -> f64 {
+        self.current.native - self.start.native
+    }
+
+    /// Change of every token balance since the start.
+    pub fn drift(&self) -> Vec<(&TokenBalance, f64)> {
+        self.current
+            .tokens
+            .iter()
+            .map(|balance| (balance, balance.balance - self.start.of(balance.token)))
+            .collect()
+    }
+
+    pub fn show(&self, ui: &mut egui::Ui) {
+        ui.heading("Wallet");
+        egui::Grid::new("wallet")
+            .num_columns(3)
+            .striped(true)
+            .show(ui, |ui| {
+                ui.strong("Token");
+                ui.strong("Balance");
+                ui.strong("Drift");
+                ui.end_row();
+                ui.label(&self.current.native_symbol);
+                ui.label(format!("{:.6}", self.current.native));
+                ui.label(format!("{:+.6}", self.native_drift()));
+                ui.end_row();
+                for (balance, drift) in self.drift() {
+                    ui.label(&balance.label);
+                    ui.label(format!("{:.6}", balance.balance));
+                    ui.label(format!("{:+.6}", drift));
+                    ui.end_row();
+                }
+            });
+        ui.label(format!(
+            "Since block {}, as of block {}",
+            self.start.block, self.current.block
+        ));
+    }
+}
+
+impl fmt::Display for Inventory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Wallet at block {}: {} {:.6} ({:+.6})",
+            self.current.block,
+            self.current.native_symbol,
+            self.current.native,
+            self.native_drift()
+        )?;
+        for (balance, drift) in self.drift() {
+            write!(
+                f,
+                ", {} {:.6} ({:+.6})",
+                balance.label, balance.balance, drift
+            )?;
+        }
+        Ok(())
+    }
+}